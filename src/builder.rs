@@ -0,0 +1,268 @@
+//! Builders for opening dictionaries with custom options
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::cache::BlockCache;
+use crate::decompress::{next_decompressor_id, BlockDecompressor};
+use crate::error::Result;
+use crate::incremental::IncrementalMdx;
+use crate::mdd::Mdd;
+use crate::mdict_base::{MdictBase, MdictOptions, DEFINITION_CACHE_ENTRIES};
+use crate::mdx::Mdx;
+#[cfg(feature = "metrics")]
+use crate::metrics::LookupMetrics;
+use crate::types::FileExt;
+
+/// Options both builders accept, defined once for [`MdxBuilder`] and
+/// [`MddBuilder`]
+macro_rules! shared_options {
+    () => {
+        /// Use a custom decoder for key and record blocks instead of the
+        /// built-in None/LZO/Zlib dispatch
+        pub fn decompressor(mut self, decompressor: impl BlockDecompressor + 'static) -> Self {
+            self.options.decompressor = Arc::new(decompressor);
+            self.options.decompressor_id = next_decompressor_id();
+            self
+        }
+
+        /// Fail with [`MdictError::EncodingError`](crate::MdictError::EncodingError)
+        /// on key text that doesn't decode, instead of keeping an empty key
+        ///
+        /// The error names the key block and the byte offsets of the key and
+        /// of its first invalid byte within the decompressed block, to locate
+        /// corruption when converting a dictionary. Off by default.
+        pub fn strict_keys(mut self, enabled: bool) -> Self {
+            self.options.strict_keys = enabled;
+            self
+        }
+
+        /// Refuse to decompress blocks declaring more than `bytes`
+        ///
+        /// Block sizes come from the file, so a crafted dictionary could make
+        /// a lookup allocate gigabytes. Blocks over the limit fail with
+        /// [`MdictError::BlockTooLarge`](crate::MdictError::BlockTooLarge).
+        /// Defaults to 256 MiB.
+        pub fn max_block_size(mut self, bytes: usize) -> Self {
+            self.options.max_block_size = bytes;
+            self
+        }
+
+        /// Share decompressed record blocks through `cache`
+        ///
+        /// Blocks are keyed by their compressed content, declared size and
+        /// decompressor, so dictionaries opened with the same cache reuse
+        /// each other's identical blocks. See [`BlockCache`] for when blocks
+        /// are shared.
+        pub fn block_cache(mut self, cache: BlockCache) -> Self {
+            self.options.block_cache = Some(cache);
+            self
+        }
+    };
+}
+
+/// Builder for opening an MDX dictionary with custom options
+///
+/// # Example
+/// ```no_run
+/// use rust_mdict::MdxBuilder;
+///
+/// let mdx = MdxBuilder::new().open("dictionary.mdx").unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct MdxBuilder {
+    options: MdictOptions,
+}
+
+impl MdxBuilder {
+    /// Create a builder with default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    shared_options!();
+
+    /// Register as `user_id` with the publisher's hex `regcode`
+    ///
+    /// Dictionaries sold per user (`Encrypted="1"` or `"Yes"` in the header)
+    /// encrypt their key header, and opening them fails with
+    /// [`MdictError::EncryptedFileRequiresPasscode`](crate::MdictError::EncryptedFileRequiresPasscode)
+    /// without credentials. The decryption key is derived from both values,
+    /// so `user_id` must be the one the regcode was issued for: the email
+    /// address if the header has `RegisterBy="EMail"`, otherwise the device
+    /// ID (see [`RegisterBy`](crate::RegisterBy)). Files with
+    /// `Encrypted="2"` only obfuscate the key info block and open without
+    /// credentials.
+    pub fn credentials(mut self, user_id: &str, regcode: &str) -> Self {
+        self.options.credentials = Some((user_id.to_string(), regcode.to_string()));
+        self
+    }
+
+    /// Add `fixup` bytes to every record offset when slicing definitions
+    ///
+    /// An escape hatch for dictionaries whose packer wrote offsets that are
+    /// consistently off, losing the first bytes of each definition or
+    /// leaving stray ones. Shifted offsets are clamped to the record block.
+    pub fn offset_fixup(mut self, fixup: i64) -> Self {
+        self.options.offset_fixup = fixup;
+        self
+    }
+
+    /// Read only the index, skipping the key blocks
+    ///
+    /// Opening then costs little more than reading the header, and block
+    /// level information such as [`Mdx::key_info_list`] and
+    /// [`Mdx::key_block_ranges`] is available. The keyword list stays
+    /// empty, so lookups find nothing; use [`Mdx::open_incremental`] to
+    /// load key blocks on demand instead.
+    pub fn key_info_only(mut self, enabled: bool) -> Self {
+        self.options.key_info_only = enabled;
+        self
+    }
+
+    /// Check after loading that the keyword list is in the order lookups
+    /// binary search it in
+    ///
+    /// A mismatch between the load-time sort and the lookup comparison
+    /// otherwise shows up only as lookups that silently miss. Opening fails
+    /// with `InvalidFormat` naming the first pair out of order. Costs one
+    /// pass comparing adjacent keys.
+    pub fn verify_sort_order(mut self, enabled: bool) -> Self {
+        self.options.verify_sort_order = enabled;
+        self
+    }
+
+    /// Build a reversed-key index so [`Mdx::suffix_keys`] is a binary
+    /// search instead of a scan
+    ///
+    /// The index holds a reversed copy of every stripped key plus its
+    /// position, roughly the size of the key text again plus 32 bytes per
+    /// keyword, and takes a sort of all keys to build when opening.
+    pub fn with_suffix_index(mut self, enabled: bool) -> Self {
+        self.options.suffix_index = enabled;
+        self
+    }
+
+    /// Call `hook` with timings after every `lookup`/`fetch`
+    ///
+    /// Also applies to a [`SharedMdx`](crate::SharedMdx) made from the
+    /// opened dictionary.
+    #[cfg(feature = "metrics")]
+    pub fn on_lookup(mut self, hook: impl Fn(LookupMetrics) + Send + Sync + 'static) -> Self {
+        self.options.on_lookup = Some(Arc::new(hook));
+        self
+    }
+
+    /// Choose between UTF-8, GB18030 and Big5 from sampled record blocks
+    /// even when the header declares an encoding
+    ///
+    /// The check always runs when the `Encoding` header is missing or
+    /// empty. It only tells those three apart: dictionaries in any other
+    /// byte encoding keep the declared one, or UTF-8. The result is
+    /// reported by `meta().encoding`.
+    #[cfg(feature = "cjk-detect")]
+    pub fn detect_cjk_encoding(mut self, enabled: bool) -> Self {
+        self.options.detect_cjk_encoding = enabled;
+        self
+    }
+
+    /// Look up the mapped headword when a word itself isn't found
+    ///
+    /// For bolting morphology onto a dictionary without it, e.g. mapping
+    /// inflected forms to their lemmas. Words are matched exactly as given.
+    /// [`Mdx::lookup_with_fallback_strip`](crate::Mdx::lookup_with_fallback_strip)
+    /// reports such matches as [`MatchKind::Alias`](crate::MatchKind::Alias).
+    pub fn aliases(mut self, aliases: HashMap<String, String>) -> Self {
+        self.options.aliases = aliases;
+        self
+    }
+
+    /// Keep the 1024 most recently used definitions returned by
+    /// [`Mdx::cached_lookup`](crate::Mdx::cached_lookup), so looking up a
+    /// word again, or another key sharing its record, returns the same
+    /// allocation instead of decoding it again
+    pub fn cache_definitions(mut self, enabled: bool) -> Self {
+        self.options.definition_cache_entries = if enabled { DEFINITION_CACHE_ENTRIES } else { 0 };
+        self
+    }
+
+    /// Keep the results of the last `capacity` distinct
+    /// [`Mdx::lookup`](crate::Mdx::lookup) queries, least recently used
+    /// evicted first
+    ///
+    /// Queries are keyed after stripping, so `Hello` and `hello` share a
+    /// slot in a case-insensitive dictionary. Words that aren't found, or
+    /// are only found through an alias, aren't cached.
+    pub fn query_cache(mut self, capacity: usize) -> Self {
+        self.options.query_cache_entries = capacity;
+        self
+    }
+
+    /// Open the dictionary at `filepath`
+    pub fn open<P: AsRef<Path>>(self, filepath: P) -> Result<Mdx> {
+        let base = MdictBase::with_options(filepath, FileExt::Mdx, self.options)?;
+        Ok(Mdx::from_base(base))
+    }
+
+    /// Open a dictionary held in memory, sharing `data` instead of copying it
+    pub fn open_shared(self, data: Arc<[u8]>) -> Result<Mdx> {
+        let base = MdictBase::from_shared_with_options(data, FileExt::Mdx, self.options)?;
+        Ok(Mdx::from_base(base))
+    }
+
+    /// Open the dictionary at `filepath` for loading one key block at a time
+    pub fn open_incremental<P: AsRef<Path>>(self, filepath: P) -> Result<IncrementalMdx> {
+        let base = MdictBase::open_index(filepath, FileExt::Mdx, self.options)?;
+        Ok(IncrementalMdx::from_base(base))
+    }
+}
+
+/// Builder for opening an MDD resource file with custom options
+///
+/// # Example
+/// ```no_run
+/// use rust_mdict::MddBuilder;
+///
+/// let mdd = MddBuilder::new()
+///     .underscore_as_bang(false)
+///     .open("dictionary.mdd")
+///     .unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct MddBuilder {
+    options: MdictOptions,
+}
+
+impl MddBuilder {
+    /// Create a builder with default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    shared_options!();
+
+    /// Whether `_` is treated as `!` when comparing resource keys
+    ///
+    /// MDict does this to match its own sort order, and it is enabled by
+    /// default. It also makes `a_b` and `a!b` compare equal, so files packed
+    /// by other tools may want it off. The choice applies to both sorting
+    /// and lookup.
+    pub fn underscore_as_bang(mut self, enabled: bool) -> Self {
+        self.options.mdd_underscore_as_bang = enabled;
+        self
+    }
+
+    /// Open the resource file at `filepath`
+    pub fn open<P: AsRef<Path>>(self, filepath: P) -> Result<Mdd> {
+        let base = MdictBase::with_options(filepath, FileExt::Mdd, self.options)?;
+        Ok(Mdd::from_base(base))
+    }
+
+    /// Open a resource file held in memory, sharing `data` instead of
+    /// copying it
+    pub fn open_shared(self, data: Arc<[u8]>) -> Result<Mdd> {
+        let base = MdictBase::from_shared_with_options(data, FileExt::Mdd, self.options)?;
+        Ok(Mdd::from_base(base))
+    }
+}
@@ -0,0 +1,143 @@
+//! Opening a dictionary folder: one MDX and its MDD volumes
+//!
+//! Dictionaries are usually distributed as `name.mdx` next to `name.mdd`,
+//! with large resource sets split into further volumes named `name.1.mdd`,
+//! `name.2.mdd`, ... (or `name.mdd.1`, ... by some packers). [`open_bundle`]
+//! finds and opens all of them; [`companion_mdd_paths`] only finds the
+//! MDD files.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::{MdictError, Result};
+use crate::mdd::Mdd;
+use crate::mdx::{inline_resources, Mdx};
+use crate::types::FileExt;
+
+/// An MDX and the MDD volumes found next to it, returned by [`open_bundle`]
+pub struct Bundle {
+    /// The dictionary
+    pub mdx: Mdx,
+    /// Resource volumes, `name.mdd` first and then by volume number; empty
+    /// if the dictionary has no resources
+    pub mdd: Vec<Mdd>,
+}
+
+impl Bundle {
+    /// Look up a word and inline the resources its definition references
+    ///
+    /// Like [`Mdx::lookup_inlined`], trying each volume in turn.
+    pub fn lookup(&mut self, word: &str) -> Option<String> {
+        let definition = self.mdx.lookup(word)?.definition;
+        Some(inline_resources(&definition, &mut self.mdd))
+    }
+
+    /// Raw bytes of a resource from the first volume that has it
+    pub fn locate_raw(&mut self, resource_key: &str) -> Option<Vec<u8>> {
+        self.mdd
+            .iter_mut()
+            .find_map(|mdd| mdd.locate_raw(resource_key))
+    }
+}
+
+/// Open the dictionary in folder `dir`
+///
+/// The folder must hold exactly one `.mdx` file. Every MDD sharing its
+/// name (`name.mdd`, `name.N.mdd` or `name.mdd.N`) is opened as a volume.
+/// Fails with `InvalidFormat` if there is no MDX or more than one.
+pub fn open_bundle(dir: &Path) -> Result<Bundle> {
+    let mut mdx_paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if FileExt::from_path(&path) == Some(FileExt::Mdx) && path.is_file() {
+            mdx_paths.push(path);
+        }
+    }
+
+    let mdx_path = match mdx_paths.as_slice() {
+        [path] => path,
+        [] => {
+            return Err(MdictError::InvalidFormat(format!(
+                "No .mdx file in {}",
+                dir.display()
+            )))
+        }
+        _ => {
+            return Err(MdictError::InvalidFormat(format!(
+                "{} .mdx files in {}; open one with Mdx::new instead",
+                mdx_paths.len(),
+                dir.display()
+            )))
+        }
+    };
+
+    let mdd = companion_mdd_paths(mdx_path)
+        .into_iter()
+        .map(Mdd::new)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Bundle {
+        mdx: Mdx::new(mdx_path)?,
+        mdd,
+    })
+}
+
+/// The existing MDD files belonging to the MDX at `mdx_path`, in volume
+/// order: `name.mdd`, then `name.1.mdd`, `name.2.mdd`, ... (or
+/// `name.mdd.1`, ...)
+///
+/// Names are matched ignoring case. The list is empty if the dictionary has
+/// no resources or its folder can't be read.
+pub fn companion_mdd_paths(mdx_path: &Path) -> Vec<PathBuf> {
+    let Some(stem) = mdx_path.file_stem() else {
+        return Vec::new();
+    };
+    let stem = stem.to_string_lossy().to_lowercase();
+    let dir = match mdx_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut volumes: Vec<(u32, PathBuf)> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_string_lossy().to_lowercase();
+            let volume = mdd_volume(&name, &stem)?;
+            path.is_file().then_some((volume, path))
+        })
+        .collect();
+    volumes.sort();
+    volumes.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Volume number of MDD file `name` belonging to a dictionary named
+/// `stem`: 0 for `stem.mdd`, N for `stem.N.mdd` or `stem.mdd.N`
+///
+/// Both names are lowercase.
+fn mdd_volume(name: &str, stem: &str) -> Option<u32> {
+    let rest = name.strip_prefix(stem)?.strip_prefix('.')?;
+    if rest == "mdd" {
+        return Some(0);
+    }
+    let number = rest
+        .strip_suffix(".mdd")
+        .or_else(|| rest.strip_prefix("mdd."))?;
+    number.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mdd_volume() {
+        assert_eq!(mdd_volume("oald.mdd", "oald"), Some(0));
+        assert_eq!(mdd_volume("oald.2.mdd", "oald"), Some(2));
+        assert_eq!(mdd_volume("oald.mdd.1", "oald"), Some(1));
+        assert_eq!(mdd_volume("oald9.mdd", "oald"), None);
+        assert_eq!(mdd_volume("oald.css", "oald"), None);
+        assert_eq!(mdd_volume("oald.x.mdd", "oald"), None);
+    }
+}
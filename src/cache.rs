@@ -0,0 +1,326 @@
+//! Content-addressed cache of decompressed record blocks
+//!
+//! A [`BlockCache`] is keyed by a RIPEMD-128 digest of the compressed
+//! block, so dictionaries that share identical blocks (e.g. editions
+//! derived from one source) share entries too. The key also holds the
+//! declared decompressed size and the decompressor, so a block is only
+//! shared between dictionaries that would decode it the same way. One
+//! handle can be passed to any number of builders; clones refer to the
+//! same cache.
+//!
+//! An [`LruCache`] belongs to a single [`Mdx`](crate::Mdx) and keeps
+//! decoded definitions or lookup results.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use crate::ripemd128::ripemd128;
+
+/// Digest of a compressed block including its preamble, its declared
+/// decompressed size and the id of the decompressor that decodes it
+type BlockKey = ([u8; 16], usize, u64);
+
+/// Shared cache of decompressed record blocks, bounded by total size
+///
+/// Install with [`MdxBuilder::block_cache`](crate::MdxBuilder::block_cache).
+/// When inserting would exceed the capacity, the oldest blocks are evicted
+/// first. Blocks larger than the whole capacity are not cached.
+///
+/// Dictionaries share blocks only if they use the built-in decompressor or
+/// were opened by clones of one builder after the same
+/// [`decompressor`](crate::MdxBuilder::decompressor) call: each call gets
+/// an id of its own.
+///
+/// The hit rate depends on how many blocks the dictionaries have in
+/// common. It hasn't been measured on real dictionaries: the only figure
+/// is from a synthetic test, where three generated editions of a
+/// 2,000-entry dictionary, two of them revising 1% of the definitions,
+/// reach 97.5% (2,340 hits and 60 misses, see
+/// `test_block_cache_derived_editions`). Check [`Self::stats`] on your own
+/// workload before settling on a capacity.
+#[derive(Clone)]
+pub struct BlockCache {
+    inner: Arc<Mutex<CacheInner>>,
+}
+
+/// Hit and size counters of a [`BlockCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that had to decompress
+    pub misses: u64,
+    /// Blocks currently cached
+    pub entries: usize,
+    /// Total decompressed size of the cached blocks
+    pub bytes: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups answered from the cache, or 0 before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+struct CacheInner {
+    capacity: usize,
+    blocks: HashMap<BlockKey, Arc<Vec<u8>>>,
+    /// Keys in insertion order, oldest first
+    order: VecDeque<BlockKey>,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    /// Create a cache holding up to `capacity` bytes of decompressed blocks
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            inner: Arc::new(Mutex::new(CacheInner {
+                capacity,
+                blocks: HashMap::new(),
+                order: VecDeque::new(),
+                stats: CacheStats::default(),
+            })),
+        }
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    /// Drop every cached block, keeping the hit and miss counts
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.blocks.clear();
+        inner.order.clear();
+        inner.stats.entries = 0;
+        inner.stats.bytes = 0;
+    }
+
+    /// Return the decompressed form of `packed`, calling `decompress` and
+    /// caching its result on a miss
+    ///
+    /// Only blocks cached for the same `unpack_size` and `decoder` (the
+    /// decompressor's id) are hits. The lock isn't held while
+    /// decompressing, so two threads missing on the same block may both
+    /// decompress it. Hits share the cached block rather than copying it.
+    /// The flag tells whether the result came from the cache.
+    pub(crate) fn get_or_insert<E>(
+        &self,
+        packed: &[u8],
+        unpack_size: usize,
+        decoder: u64,
+        decompress: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<(Arc<Vec<u8>>, bool), E> {
+        let key = (ripemd128(packed), unpack_size, decoder);
+        {
+            let mut inner = self.lock();
+            if let Some(block) = inner.blocks.get(&key).cloned() {
+                inner.stats.hits += 1;
+                return Ok((block, true));
+            }
+            inner.stats.misses += 1;
+        }
+
+        let block = Arc::new(decompress()?);
+        self.lock().insert(key, &block);
+        Ok((block, false))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheInner> {
+        // A panic while holding the lock can't leave the maps inconsistent
+        // in a way that matters for a cache
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl CacheInner {
+    fn insert(&mut self, key: BlockKey, block: &Arc<Vec<u8>>) {
+        if block.len() > self.capacity || self.blocks.contains_key(&key) {
+            return;
+        }
+        while self.stats.bytes + block.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.blocks.remove(&oldest) {
+                self.stats.bytes -= evicted.len();
+            }
+        }
+
+        self.blocks.insert(key, Arc::clone(block));
+        self.order.push_back(key);
+        self.stats.bytes += block.len();
+        self.stats.entries = self.blocks.len();
+    }
+}
+
+/// Entry-bounded cache owned by one dictionary, evicting the least
+/// recently used entry first
+///
+/// Backs [`MdxBuilder::cache_definitions`](crate::MdxBuilder::cache_definitions),
+/// keyed by record offset so every key pointing at the same record shares
+/// one `Arc<str>`, and [`MdxBuilder::query_cache`](crate::MdxBuilder::query_cache),
+/// keyed by stripped query.
+pub(crate) struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by the tick they were last used at, least recent first
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+/// Decoded definitions by record start offset
+pub(crate) type DefinitionCache = LruCache<u64, Arc<str>>;
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// The value for `key`, marking it most recently used
+    pub(crate) fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let (value, used) = self.entries.get_mut(key)?;
+        self.recency.remove(used);
+        *used = self.tick;
+        self.recency.insert(self.tick, key.clone());
+        Some(value.clone())
+    }
+
+    /// Cache `value` for `key`, evicting the least recently used entry if
+    /// the cache is full
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (value, self.tick)) {
+            self.recency.remove(&used);
+        } else if self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.recency.insert(self.tick, key);
+    }
+
+    /// Return the value for `key`, calling `load` and caching its result
+    /// on a miss
+    pub(crate) fn get_or_insert(&mut self, key: K, load: impl FnOnce() -> Option<V>) -> Option<V> {
+        if let Some(value) = self.get(&key) {
+            return Some(value);
+        }
+        let value = load()?;
+        self.insert(key, value.clone());
+        Some(value)
+    }
+
+    /// Drop every entry
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch(cache: &BlockCache, packed: &[u8]) -> (Arc<Vec<u8>>, bool) {
+        cache
+            .get_or_insert(packed, packed.len() * 2, 0, || {
+                Ok::<_, ()>(packed.repeat(2))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_hits_and_eviction() {
+        let cache = BlockCache::new(8);
+        let (first, hit) = fetch(&cache, b"ab");
+        assert_eq!((first.as_slice(), hit), (&b"abab"[..], false));
+        // Hits share the cached block instead of copying it
+        let (again, hit) = fetch(&cache, b"ab");
+        assert!(hit && Arc::ptr_eq(&first, &again));
+        fetch(&cache, b"cd");
+        assert_eq!(cache.stats().bytes, 8);
+
+        // A third block evicts the oldest
+        fetch(&cache, b"ef");
+        assert!(!fetch(&cache, b"ab").1);
+        assert!(fetch(&cache, b"ef").1);
+
+        // Blocks over the capacity are never cached
+        fetch(&cache, b"toolong");
+        assert!(!fetch(&cache, b"toolong").1);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 6));
+        assert!(stats.entries <= 2 && stats.bytes <= 8);
+    }
+
+    #[test]
+    fn test_key_includes_size_and_decoder() {
+        let cache = BlockCache::new(64);
+        let decode = |out: &'static [u8]| move || Ok::<_, ()>(out.to_vec());
+        cache.get_or_insert(b"ab", 4, 1, decode(b"abab")).unwrap();
+        assert!(cache.get_or_insert(b"ab", 4, 1, decode(b"abab")).unwrap().1);
+
+        // Another declared size or decompressor decodes afresh
+        let (block, hit) = cache.get_or_insert(b"ab", 2, 1, decode(b"ab")).unwrap();
+        assert_eq!((block.as_slice(), hit), (&b"ab"[..], false));
+        let (block, hit) = cache.get_or_insert(b"ab", 4, 2, decode(b"ABAB")).unwrap();
+        assert_eq!((block.as_slice(), hit), (&b"ABAB"[..], false));
+    }
+
+    #[test]
+    fn test_definition_cache() {
+        let mut cache = DefinitionCache::new(2);
+        let first = cache.get_or_insert(0, || Some("a".into())).unwrap();
+        let again = cache.get_or_insert(0, || panic!("decoded twice")).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        assert!(cache.get_or_insert(5, || None).is_none());
+
+        // A third offset evicts the least recently used
+        cache.get_or_insert(5, || Some("b".into()));
+        cache.get_or_insert(9, || Some("c".into()));
+        let reloaded = cache.get_or_insert(0, || Some("a".into())).unwrap();
+        assert!(!Arc::ptr_eq(&first, &reloaded));
+    }
+
+    #[test]
+    fn test_lru_order() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        // "b" is now the least recently used
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!((cache.get(&"a"), cache.get(&"c")), (Some(1), Some(3)));
+
+        // Replacing a value doesn't evict anything
+        cache.insert("a", 10);
+        assert_eq!((cache.get(&"a"), cache.get(&"c")), (Some(10), Some(3)));
+
+        cache.clear();
+        assert_eq!(cache.get(&"a"), None);
+        let mut empty = LruCache::new(0);
+        empty.insert("a", 1);
+        assert_eq!(empty.get(&"a"), None);
+    }
+}
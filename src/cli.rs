@@ -4,11 +4,16 @@
 //!   mdict-cli <file> lookup <word>
 //!   mdict-cli <file> prefix <prefix>
 //!   mdict-cli <file> info
+//!   mdict-cli <file.mdx> export <base_path> [--progress]
+//!
+//! A `<file>` of `-` reads an MDX from standard input.
 
 use std::env;
+use std::io::Read;
+use std::path::Path;
 use std::process;
 
-use rust_mdict::{Mdd, Mdx};
+use rust_mdict::{FileExt, Mdd, Mdx};
 
 fn print_usage() {
     eprintln!("Usage:");
@@ -16,9 +21,14 @@ fn print_usage() {
     eprintln!("  mdict-cli <file.mdx> prefix <prefix>  - Find words with prefix in MDX");
     eprintln!("  mdict-cli <file.mdx> suggest <word>   - Suggest similar words in MDX");
     eprintln!("  mdict-cli <file.mdx> info             - Show MDX dictionary info");
+    eprintln!("  mdict-cli <file.mdx> export <base> [--progress]");
+    eprintln!(
+        "                                        - Export MDX as StarDict <base>.ifo/.idx/.dict"
+    );
     eprintln!("  mdict-cli <file.mdd> locate <key>     - Locate resource in MDD file");
     eprintln!("  mdict-cli <file.mdd> prefix <prefix>  - Find resources with prefix in MDD");
     eprintln!("  mdict-cli <file.mdd> info             - Show MDD file info");
+    eprintln!("A <file.mdx> of - reads the dictionary from standard input");
 }
 
 fn main() {
@@ -33,7 +43,7 @@ fn main() {
     let command = &args[2];
 
     // Determine file type
-    let is_mdd = filepath.to_lowercase().ends_with(".mdd");
+    let is_mdd = FileExt::from_path(Path::new(filepath)) == Some(FileExt::Mdd);
 
     if is_mdd {
         handle_mdd(filepath, command, &args[3..]);
@@ -43,7 +53,12 @@ fn main() {
 }
 
 fn handle_mdx(filepath: &str, command: &str, args: &[String]) {
-    let mut mdx = match Mdx::new(filepath) {
+    let mdx = if filepath == "-" {
+        read_stdin().and_then(|data| Mdx::from_shared(data.into()))
+    } else {
+        Mdx::new(filepath)
+    };
+    let mut mdx = match mdx {
         Ok(m) => m,
         Err(e) => {
             eprintln!("Error opening MDX file: {}", e);
@@ -96,9 +111,32 @@ fn handle_mdx(filepath: &str, command: &str, args: &[String]) {
                 println!("  {}", suggestion);
             }
         }
+        "export" => {
+            if args.is_empty() {
+                eprintln!("Error: missing base path argument");
+                process::exit(1);
+            }
+            let base_path = Path::new(&args[0]);
+            let show_progress = args[1..].iter().any(|arg| arg == "--progress");
+            let result = mdx.export_stardict_with_progress(base_path, |done, total| {
+                if show_progress {
+                    print_progress(done, total);
+                }
+            });
+            if let Err(e) = result {
+                eprintln!("Error exporting: {}", e);
+                process::exit(1);
+            }
+            println!("Exported {} entries to {}", mdx.keyword_count(), args[0]);
+        }
         "info" => {
             println!("MDX Dictionary Info:");
-            println!("  File: {}", mdx.filepath());
+            let file = if filepath == "-" {
+                "<stdin>"
+            } else {
+                mdx.filepath()
+            };
+            println!("  File: {}", file);
             println!("  Version: {}", mdx.meta().version);
             println!("  Encoding: {:?}", mdx.meta().encoding);
             println!("  Encryption: {:?}", mdx.meta().encrypt);
@@ -117,6 +155,13 @@ fn handle_mdx(filepath: &str, command: &str, args: &[String]) {
     }
 }
 
+/// Read all of standard input, for an MDX piped in as `-`
+fn read_stdin() -> rust_mdict::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::io::stdin().read_to_end(&mut data)?;
+    Ok(data)
+}
+
 fn handle_mdd(filepath: &str, command: &str, args: &[String]) {
     let mut mdd = match Mdd::new(filepath) {
         Ok(m) => m,
@@ -184,3 +229,23 @@ fn handle_mdd(filepath: &str, command: &str, args: &[String]) {
         }
     }
 }
+
+/// Draw a progress bar on stderr, ending the line when complete
+fn print_progress(done: usize, total: usize) {
+    const WIDTH: usize = 40;
+    // Clamped, so a miscounted total can't overflow the bar
+    let filled = (done * WIDTH)
+        .checked_div(total)
+        .unwrap_or(WIDTH)
+        .min(WIDTH);
+    eprint!(
+        "\r[{}{}] {}/{}",
+        "#".repeat(filled),
+        " ".repeat(WIDTH.saturating_sub(filled)),
+        done,
+        total
+    );
+    if done == total {
+        eprintln!();
+    }
+}
@@ -0,0 +1,78 @@
+//! Block decompression
+//!
+//! Key blocks and record blocks start with an 8-byte preamble: a 4-byte
+//! little-endian compression tag followed by a 4-byte checksum. The payload
+//! after the preamble is handed to a [`BlockDecompressor`] along with the tag.
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{MdictError, Result};
+use crate::lzo;
+use crate::types::CompressionType;
+
+/// Decoder for the payload of key and record blocks
+///
+/// Implement this to support non-standard compression schemes, and install
+/// it with [`MdxBuilder::decompressor`](crate::MdxBuilder::decompressor).
+/// Implementations are shared between threads, hence `Send + Sync`.
+pub trait BlockDecompressor: Send + Sync {
+    /// Decompress `data` (the block without its preamble)
+    ///
+    /// `tag` is the block's compression tag and `unpack_size` the declared
+    /// decompressed size.
+    fn decompress(&self, tag: u32, data: &[u8], unpack_size: usize) -> Result<Vec<u8>>;
+}
+
+/// Id of [`DefaultDecompressor`] in [`BlockCache`](crate::BlockCache) keys
+pub(crate) const DEFAULT_DECOMPRESSOR_ID: u64 = 0;
+
+/// A fresh id for a custom decompressor, never reused within the process
+///
+/// A [`BlockCache`](crate::BlockCache) outlives the dictionaries using it,
+/// so an address could be reused by another decompressor while blocks
+/// decoded by the old one are still cached.
+pub(crate) fn next_decompressor_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(DEFAULT_DECOMPRESSOR_ID + 1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Magic bytes starting a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Built-in decompressor handling the None, LZO and Zlib tags
+///
+/// Some mis-packed files store gzip streams in Zlib-tagged blocks; these
+/// are recognized by their magic bytes and decoded as gzip.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultDecompressor;
+
+impl BlockDecompressor for DefaultDecompressor {
+    fn decompress(&self, tag: u32, data: &[u8], unpack_size: usize) -> Result<Vec<u8>> {
+        let comp_type = CompressionType::from_bytes(&tag.to_le_bytes())
+            .ok_or(MdictError::InvalidCompressionType(tag))?;
+
+        match comp_type {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Lzo => lzo::decompress(data, unpack_size),
+            CompressionType::Zlib => {
+                // Stop one byte past the declared size, so a stream that
+                // inflates far beyond it can't exhaust memory but the size
+                // mismatch is still visible to the caller
+                let limit = unpack_size as u64 + 1;
+                let mut decompressed = Vec::new();
+                if data.starts_with(&GZIP_MAGIC) {
+                    GzDecoder::new(data)
+                        .take(limit)
+                        .read_to_end(&mut decompressed)?;
+                } else {
+                    ZlibDecoder::new(data)
+                        .take(limit)
+                        .read_to_end(&mut decompressed)?;
+                }
+                Ok(decompressed)
+            }
+        }
+    }
+}
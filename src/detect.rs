@@ -0,0 +1,113 @@
+//! Narrow check between UTF-8 and the Chinese encodings for dictionaries
+//! with a missing or wrong `Encoding`
+//!
+//! This is not general charset detection: Japanese, Korean and
+//! single-byte encodings such as Shift_JIS, EUC-KR or Windows-1252 are
+//! never reported. A few record blocks are decompressed and the bytes
+//! tried as UTF-8, GB18030 and Big5. Valid UTF-8 wins outright; otherwise each CJK
+//! candidate is scored by how many very common characters its decoding
+//! contains, minus a penalty for undecodable sequences. Misreading GBK as
+//! Big5 (or the reverse) produces valid but rare characters, so the
+//! correct encoding scores far higher.
+//!
+//! UTF-16 is not detected: it changes the layout of the key index, so a
+//! UTF-16 dictionary with the wrong header wouldn't get this far.
+
+use encoding_rs::{BIG5, GB18030};
+
+use crate::types::Encoding;
+
+/// Most frequent Chinese characters, simplified and traditional forms
+const COMMON_CHARS: &str = "的一是不了人在有我他这個个们們中来來上大为為和国國地到以说說\
+时時要就出会會也你对對生能而子那得于於着著下自之年过過发發后後作里裡用道行所然家\
+种種事成方多经經么麼去法学學如都同现現没沒动動面起看定天分还還进進好小部其些主样樣\
+理心本前开開但因只从從想实實日者意无無力它与與长長把机機十民第公此已工使情明性知全";
+
+/// Penalty for each U+FFFD in a decoding, relative to one common character
+const REPLACEMENT_PENALTY: usize = 10;
+
+/// Pick UTF-8, GB18030 or Big5 for `samples`, or `None` if no candidate
+/// is convincing
+pub(crate) fn detect_encoding(samples: &[Vec<u8>]) -> Option<Encoding> {
+    let samples: Vec<&[u8]> = samples
+        .iter()
+        .map(|s| trim_nuls(s))
+        .filter(|s| !s.is_empty())
+        .collect();
+    if samples.is_empty() {
+        return None;
+    }
+
+    if samples.iter().all(|s| std::str::from_utf8(s).is_ok()) {
+        return Some(Encoding::Utf8);
+    }
+
+    [(Encoding::Gb18030, GB18030), (Encoding::Big5, BIG5)]
+        .into_iter()
+        .map(|(encoding, decoder)| {
+            let score: isize = samples
+                .iter()
+                .map(|s| score(&decoder.decode_without_bom_handling(s).0))
+                .sum();
+            (encoding, score)
+        })
+        .filter(|&(_, score)| score > 0)
+        .max_by_key(|&(_, score)| score)
+        .map(|(encoding, _)| encoding)
+}
+
+/// Common characters in `text` minus the penalty for replacements
+fn score(text: &str) -> isize {
+    let mut common = 0;
+    let mut replaced = 0;
+    for c in text.chars() {
+        if c == '\u{FFFD}' {
+            replaced += 1;
+        } else if COMMON_CHARS.contains(c) {
+            common += 1;
+        }
+    }
+    common as isize - (replaced * REPLACEMENT_PENALTY) as isize
+}
+
+/// Drop the NUL terminators records end with
+fn trim_nuls(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &bytes[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "这是一个中文词典的释义，我们在上面可以看到很多常用的字。";
+    const TRADITIONAL: &str = "這是一個中文詞典的釋義，我們在上面可以看到很多常用的字。";
+
+    #[test]
+    fn test_detect_utf8() {
+        let samples = vec![TEXT.as_bytes().to_vec(), b"ascii\0".to_vec()];
+        assert_eq!(detect_encoding(&samples), Some(Encoding::Utf8));
+    }
+
+    #[test]
+    fn test_detect_gb18030() {
+        let (bytes, _, _) = GB18030.encode(TEXT);
+        assert_eq!(
+            detect_encoding(&[bytes.into_owned()]),
+            Some(Encoding::Gb18030)
+        );
+    }
+
+    #[test]
+    fn test_detect_big5() {
+        let (bytes, _, _) = BIG5.encode(TRADITIONAL);
+        assert_eq!(detect_encoding(&[bytes.into_owned()]), Some(Encoding::Big5));
+    }
+
+    #[test]
+    fn test_detect_nothing() {
+        assert_eq!(detect_encoding(&[]), None);
+        assert_eq!(detect_encoding(&[vec![0, 0]]), None);
+        assert_eq!(detect_encoding(&[vec![0xff, 0xfe, 0xfd]]), None);
+    }
+}
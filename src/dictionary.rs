@@ -0,0 +1,128 @@
+//! Accessors shared by MDX and MDD files
+//!
+//! [`MdictFile`] covers what both file types have in common, so tooling can
+//! take `&dyn MdictFile` instead of handling [`Mdx`], [`SharedMdx`] and
+//! [`Mdd`] separately.
+//! [`Dictionary`] opens either type, picking one from the file extension.
+//! Lookups stay on the concrete types, since MDX definitions and MDD
+//! resources are returned differently. [`read_header_only`] reads just
+//! the header of either type.
+
+use std::path::Path;
+
+use crate::error::{MdictError, Result};
+use crate::mdd::Mdd;
+use crate::mdict_base::MdictBase;
+use crate::mdx::Mdx;
+use crate::shared::SharedMdx;
+use crate::types::{DictHeader, DictMeta, FileExt, HeaderInfo};
+
+/// Accessors common to MDX dictionaries and MDD resource files
+///
+/// Only this crate's types can implement it, since they all read from the
+/// same parsed index.
+pub trait MdictFile {
+    /// The parsed index behind the accessors
+    #[doc(hidden)]
+    fn base(&self) -> &MdictBase;
+
+    /// Get header attributes
+    fn header(&self) -> &DictHeader {
+        &self.base().header
+    }
+
+    /// Get file metadata
+    fn meta(&self) -> &DictMeta {
+        &self.base().meta
+    }
+
+    /// Check if a key exists
+    fn contains(&self, key: &str) -> bool {
+        self.base().lookup_keyword_by_word(key, false).is_some()
+    }
+
+    /// Get all keys in index order
+    fn keys(&self) -> Vec<&str> {
+        self.base()
+            .keyword_list
+            .iter()
+            .map(|k| k.key_text.as_str())
+            .collect()
+    }
+
+    /// Get the file path
+    fn filepath(&self) -> &str {
+        &self.base().filepath
+    }
+}
+
+impl MdictFile for Mdx {
+    fn base(&self) -> &MdictBase {
+        &self.base
+    }
+}
+
+impl MdictFile for Mdd {
+    fn base(&self) -> &MdictBase {
+        &self.base
+    }
+}
+
+impl MdictFile for SharedMdx {
+    fn base(&self) -> &MdictBase {
+        &self.base
+    }
+}
+
+/// An MDX or MDD file, opened according to its extension
+pub enum Dictionary {
+    Mdx(Mdx),
+    Mdd(Mdd),
+}
+
+impl Dictionary {
+    /// Open `filepath` as MDX or MDD depending on its extension
+    ///
+    /// Fails with `InvalidFormat` if the extension is neither.
+    pub fn open<P: AsRef<Path>>(filepath: P) -> Result<Self> {
+        let path = filepath.as_ref();
+        match FileExt::from_path(path) {
+            Some(FileExt::Mdx) => Ok(Dictionary::Mdx(Mdx::new(path)?)),
+            Some(FileExt::Mdd) => Ok(Dictionary::Mdd(Mdd::new(path)?)),
+            None => Err(MdictError::InvalidFormat(format!(
+                "Not an .mdx or .mdd file: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// The opened file through the shared accessors
+    pub fn as_file(&self) -> &dyn MdictFile {
+        match self {
+            Dictionary::Mdx(mdx) => mdx,
+            Dictionary::Mdd(mdd) => mdd,
+        }
+    }
+}
+
+/// Read the header of the MDX or MDD file at `filepath` without loading
+/// its index
+///
+/// Only the header and the key header are read, so this is cheap enough to
+/// list a folder of dictionaries. Encrypted key headers aren't decrypted;
+/// their keyword count is reported as `None`. Files that are neither
+/// `.mdx` nor `.mdd` are read as MDD if the header's root element is
+/// `<Library_Data>`, and as MDX otherwise.
+pub fn read_header_only<P: AsRef<Path>>(filepath: P) -> Result<HeaderInfo> {
+    let path = filepath.as_ref();
+    MdictBase::read_header_info(path, FileExt::from_path(path))
+}
+
+impl MdictFile for Dictionary {
+    fn base(&self) -> &MdictBase {
+        match self {
+            Dictionary::Mdx(mdx) => &mdx.base,
+            Dictionary::Mdd(mdd) => &mdd.base,
+        }
+    }
+}
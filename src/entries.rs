@@ -0,0 +1,58 @@
+//! Consuming iteration over every MDX entry
+
+use std::sync::Arc;
+use std::vec;
+
+use crate::error::Result;
+use crate::mdict_base::MdictBase;
+
+/// Iterator over every `(key, definition)` of an MDX, returned by
+/// [`Mdx::into_entries`](crate::Mdx::into_entries)
+///
+/// Entries come in record order. Each record block is decompressed when
+/// its first entry is reached and dropped after its last, so memory use
+/// stays at one block. If a block can't be read its error is yielded once
+/// and iteration continues with the next block.
+pub struct MdxEntries {
+    /// Base parser, owned so the dictionary is released with the iterator
+    base: MdictBase,
+    /// Remaining `(block index, keyword indices)` groups
+    groups: vec::IntoIter<(usize, Vec<usize>)>,
+    /// Decompressed current block and its remaining keyword indices
+    current: Option<(usize, Arc<Vec<u8>>, vec::IntoIter<usize>)>,
+}
+
+impl MdxEntries {
+    pub(crate) fn new(base: MdictBase) -> Self {
+        let groups = base.group_indices_by_record_block(&base.keyword_list);
+        MdxEntries {
+            base,
+            groups: groups.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl Iterator for MdxEntries {
+    type Item = Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((block_idx, block, indices)) = &mut self.current {
+                if let Some(i) = indices.next() {
+                    let item = &self.base.keyword_list[i];
+                    let def_bytes = self.base.slice_record(block, *block_idx, item);
+                    let definition = self.base.decode_definition(def_bytes);
+                    return Some(Ok((item.key_text.clone(), definition)));
+                }
+                self.current = None;
+            }
+
+            let (block_idx, indices) = self.groups.next()?;
+            match self.base.read_record_block(block_idx) {
+                Ok(block) => self.current = Some((block_idx, block, indices.into_iter())),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
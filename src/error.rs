@@ -32,6 +32,25 @@ pub enum MdictError {
     #[error("Invalid compression type: {0:08x}")]
     InvalidCompressionType(u32),
 
+    #[error("Record block index out of range: {0}")]
+    RecordBlockOutOfRange(usize),
+
+    #[error("Dictionary has no record blocks")]
+    NoRecordBlocks,
+
+    #[error("Block declares {size} decompressed bytes, over the {limit}-byte limit")]
+    BlockTooLarge { size: usize, limit: usize },
+
+    #[error("Records span {total} bytes, past the 4 GiB a v1.x dictionary's offsets can address")]
+    OffsetOverflow { total: u64 },
+
+    #[error("Resource {key} is listed but its data can't be read: {source}")]
+    ResourceUnreadable {
+        key: String,
+        #[source]
+        source: Box<MdictError>,
+    },
+
     #[error("Encrypted file requires passcode")]
     EncryptedFileRequiresPasscode,
 }
@@ -0,0 +1,67 @@
+//! Incremental MDX loading
+//!
+//! Opening an [`Mdx`] reads every key block up front. [`IncrementalMdx`]
+//! instead reads the index first and then one key block per call, so a UI
+//! can report progress, show early results, or cancel by dropping it.
+
+use crate::error::Result;
+use crate::mdict_base::MdictBase;
+use crate::mdx::Mdx;
+use crate::types::KeyWordItem;
+
+/// MDX dictionary whose key blocks are read one at a time
+pub struct IncrementalMdx {
+    /// Base parser with a partially loaded keyword list
+    base: MdictBase,
+    /// Index of the next key block to read
+    next_block: usize,
+}
+
+impl IncrementalMdx {
+    /// Wrap a base whose index has been read but no key blocks
+    pub(crate) fn from_base(base: MdictBase) -> Self {
+        IncrementalMdx {
+            base,
+            next_block: 0,
+        }
+    }
+
+    /// Total number of key blocks
+    pub fn block_count(&self) -> usize {
+        self.base.key_info_list.len()
+    }
+
+    /// Number of key blocks read so far
+    pub fn blocks_read(&self) -> usize {
+        self.next_block
+    }
+
+    /// Keywords read so far, in file order
+    pub fn keywords_read(&self) -> &[KeyWordItem] {
+        &self.base.keyword_list
+    }
+
+    /// Read the next key block and return its keywords in file order, or
+    /// `None` once every block has been read
+    ///
+    /// The returned block's last keyword has `record_end_offset == 0`: its
+    /// end is the first offset of the following block, which is only known
+    /// once that block is read. The final [`Mdx`] has complete offsets.
+    pub fn next_block(&mut self) -> Result<Option<Vec<KeyWordItem>>> {
+        if self.next_block >= self.block_count() {
+            return Ok(None);
+        }
+
+        let range = self.base.load_key_block(self.next_block)?;
+        self.next_block += 1;
+
+        Ok(Some(self.base.keyword_list[range].to_vec()))
+    }
+
+    /// Read any remaining key blocks and return the fully usable dictionary
+    pub fn finish(mut self) -> Result<Mdx> {
+        while self.next_block()?.is_some() {}
+        self.base.index_keywords()?;
+        Ok(Mdx::from_base(self.base))
+    }
+}
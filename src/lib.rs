@@ -19,16 +19,45 @@
 //! }
 //! ```
 
+mod builder;
+mod bundle;
+mod cache;
+mod decompress;
+#[cfg(feature = "cjk-detect")]
+mod detect;
+mod dictionary;
+mod entries;
 mod error;
+mod incremental;
 mod lzo;
 mod mdd;
 mod mdict_base;
 mod mdx;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod ripemd128;
+mod salsa20;
+mod shared;
+mod stardict;
 mod types;
 mod utils;
+mod verify;
+#[cfg(feature = "zip")]
+mod zip;
 
+pub use builder::{MddBuilder, MdxBuilder};
+pub use bundle::{companion_mdd_paths, open_bundle, Bundle};
+pub use cache::{BlockCache, CacheStats};
+pub use decompress::{BlockDecompressor, DefaultDecompressor};
+pub use dictionary::{read_header_only, Dictionary, MdictFile};
+pub use entries::MdxEntries;
 pub use error::{MdictError, Result};
+pub use incremental::IncrementalMdx;
 pub use mdd::Mdd;
 pub use mdx::Mdx;
+#[cfg(feature = "metrics")]
+pub use metrics::LookupMetrics;
+pub use shared::SharedMdx;
 pub use types::*;
+pub use utils::rewrite_internal_links;
+pub use verify::{BlockError, BlockKind};
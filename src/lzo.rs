@@ -232,29 +232,59 @@ impl Lzo1xDecompressor {
     }
 }
 
+/// Run minilzo's bounds-checked decoder, or `None` if the stream is
+/// malformed or overruns `output_size`
+///
+/// minilzo-rs doesn't hand back the length the decoder wrote: it zero-pads
+/// short output up to the requested size. A stream that overruns its buffer
+/// fails with `OutputOverrun`, though, so a stream that decodes into
+/// `output_size` bytes but doesn't overrun one byte less is short. Telling
+/// them apart costs a second pass over the block.
+fn minilzo_decompress(
+    lzo: &minilzo_rs::LZO,
+    input: &[u8],
+    output_size: usize,
+) -> Option<Result<Vec<u8>>> {
+    let output = lzo.decompress_safe(input, output_size).ok()?;
+    if output_size > 0
+        && lzo.decompress_safe(input, output_size - 1) != Err(minilzo_rs::Error::OutputOverrun)
+    {
+        return Some(Err(MdictError::DecompressionError(format!(
+            "LZO output is shorter than the expected {} bytes",
+            output_size
+        ))));
+    }
+    Some(Ok(output))
+}
+
 /// Decompress LZO1X data
 ///
-/// This function uses the minilzo-rs crate for reliable decompression.
-/// Falls back to our implementation if the crate fails.
+/// This function uses minilzo for reliable decompression.
+/// Falls back to our implementation if it fails. Either way, output
+/// shorter or longer than `output_size` is an error rather than a silently
+/// truncated block.
 pub fn decompress(input: &[u8], output_size: usize) -> Result<Vec<u8>> {
-    // Try using minilzo-rs first
-    match minilzo_rs::LZO::init() {
-        Ok(lzo) => {
-            match lzo.decompress_safe(input, output_size) {
-                Ok(data) => Ok(data),
-                Err(_) => {
-                    // Fall back to our implementation
-                    let mut decompressor = Lzo1xDecompressor::new();
-                    decompressor.decompress(input, output_size)
-                }
-            }
-        }
-        Err(_) => {
+    // `init` checks the linked library matches the expected ABI
+    let minilzo = minilzo_rs::LZO::init()
+        .ok()
+        .and_then(|lzo| minilzo_decompress(&lzo, input, output_size));
+    let data = match minilzo {
+        Some(data) => data?,
+        None => {
             // Fall back to our implementation
             let mut decompressor = Lzo1xDecompressor::new();
-            decompressor.decompress(input, output_size)
+            decompressor.decompress(input, output_size)?
         }
+    };
+
+    if data.len() != output_size {
+        return Err(MdictError::DecompressionError(format!(
+            "LZO output is {} bytes, expected {} bytes",
+            data.len(),
+            output_size
+        )));
     }
+    Ok(data)
 }
 
 #[cfg(test)]
@@ -267,4 +297,37 @@ mod tests {
         assert!(result.is_ok());
         assert!(result.unwrap().is_empty());
     }
+
+    #[test]
+    fn test_decompress_checks_size() {
+        // A three-byte literal run followed by the end-of-stream marker
+        let input = [20, b'a', b'b', b'c', 0x11, 0, 0];
+        assert_eq!(decompress(&input, 3).unwrap(), b"abc");
+
+        let err = decompress(&input, 5).unwrap_err();
+        assert!(err.to_string().contains("expected 5 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn test_decompress_nul_terminated() {
+        // Record blocks end in NUL, which used to look like padding
+        let data = b"<p>hello</p>\r\n\0<p>world</p>\r\n\0".repeat(20);
+        let mut lzo = minilzo_rs::LZO::init().unwrap();
+        let input = lzo.compress(&data).unwrap();
+        assert_eq!(decompress(&input, data.len()).unwrap(), data);
+        assert_eq!(
+            minilzo_decompress(&lzo, &input, data.len())
+                .unwrap()
+                .unwrap(),
+            data
+        );
+        assert!(minilzo_decompress(&lzo, &input, data.len() + 4)
+            .unwrap()
+            .is_err());
+        assert!(minilzo_decompress(&lzo, &input, data.len() - 1).is_none());
+
+        let err = decompress(&input, data.len() + 1).unwrap_err();
+        let expected = format!("expected {} bytes", data.len() + 1);
+        assert!(err.to_string().contains(&expected), "{}", err);
+    }
 }
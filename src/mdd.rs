@@ -4,33 +4,75 @@
 //! MDD files store binary resources like images, audio files, CSS, etc.
 
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use std::fmt;
+#[cfg(feature = "zip")]
+use std::io::{Seek, Write};
 use std::path::Path;
+use std::sync::Arc;
 
-use crate::error::Result;
+use crate::builder::MddBuilder;
+use crate::dictionary::MdictFile;
+use crate::error::{MdictError, Result};
 use crate::mdict_base::MdictBase;
 use crate::types::*;
 
+/// Resource bytes encoded per write by [`Mdd::locate_base64_to`]; a
+/// multiple of 3, so no chunk but the last is padded
+const BASE64_CHUNK: usize = 3 * 1024;
+
 /// MDD resource file parser
 pub struct Mdd {
     /// Base parser
-    base: MdictBase,
+    pub(crate) base: MdictBase,
 }
 
 impl Mdd {
     /// Create a new MDD parser from file path
+    ///
+    /// Fails with `InvalidFormat` if the header declares a byte encoding
+    /// such as UTF-8, as only an MDX header would, or if its root isn't
+    /// `<Library_Data>` and the first key isn't a `\` resource path.
     pub fn new<P: AsRef<Path>>(filepath: P) -> Result<Self> {
         let base = MdictBase::new(filepath, FileExt::Mdd)?;
         Ok(Mdd { base })
     }
 
+    /// Open an MDD held in memory, sharing `data` instead of copying it
+    pub fn from_shared(data: Arc<[u8]>) -> Result<Self> {
+        let base = MdictBase::from_shared(data, FileExt::Mdd)?;
+        Ok(Mdd { base })
+    }
+
+    /// Open an optional companion MDD, returning `Ok(None)` if there is no
+    /// file at `filepath`
+    ///
+    /// A file that exists but can't be parsed is still an error.
+    pub fn open_optional<P: AsRef<Path>>(filepath: P) -> Result<Option<Self>> {
+        match Self::new(filepath) {
+            Ok(mdd) => Ok(Some(mdd)),
+            Err(MdictError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a builder for opening an MDD with custom options
+    pub fn builder() -> MddBuilder {
+        MddBuilder::new()
+    }
+
+    /// Wrap an already parsed base
+    pub(crate) fn from_base(base: MdictBase) -> Self {
+        Mdd { base }
+    }
+
     /// Get dictionary header
     pub fn header(&self) -> &DictHeader {
-        &self.base.header
+        MdictFile::header(self)
     }
 
     /// Get dictionary metadata
     pub fn meta(&self) -> &DictMeta {
-        &self.base.meta
+        MdictFile::meta(self)
     }
 
     /// Get total number of resources
@@ -38,6 +80,25 @@ impl Mdd {
         self.base.keyword_list.len()
     }
 
+    /// Number of resource keys recovered from corrupt key blocks
+    ///
+    /// Keys whose UTF-16 text had an odd length or no terminator are cut to
+    /// whole code units instead of dropping the rest of their block, so
+    /// they may be truncated. Zero for an intact file.
+    pub fn key_decode_warnings(&self) -> usize {
+        self.base.key_decode_warnings
+    }
+
+    /// Key blocks whose declared entry count disagrees with the keys found
+    /// at each terminator
+    ///
+    /// Keys in these blocks may hold a NUL: they are read up to the
+    /// terminator that is followed by a valid entry. Empty for an intact
+    /// file.
+    pub fn key_count_mismatches(&self) -> &[KeyCountMismatch] {
+        &self.base.key_count_mismatches
+    }
+
     /// Get all resource keys
     pub fn resource_keys(&self) -> Vec<&str> {
         self.base
@@ -48,21 +109,75 @@ impl Mdd {
     }
 
     /// Locate a resource and return its data as base64
+    ///
+    /// A missing key and data that can't be read both give `None`; use
+    /// [`Self::try_locate`] to tell them apart.
     pub fn locate(&mut self, resource_key: &str) -> Option<LookupResult> {
+        self.try_locate(resource_key).ok().flatten()
+    }
+
+    /// Locate a resource and return its data as base64, telling a missing
+    /// resource apart from one whose data can't be read
+    ///
+    /// Returns `Ok(None)` if no key matches, and fails with
+    /// `ResourceUnreadable` if the key is listed but its record block is
+    /// missing, truncated or corrupt.
+    pub fn try_locate(&mut self, resource_key: &str) -> Result<Option<LookupResult>> {
         // Find resource in the list
-        let keyword_item = self.base.lookup_keyword_by_word(resource_key, false)?;
+        let Some(keyword_item) = self.base.lookup_keyword_by_word(resource_key, false) else {
+            return Ok(None);
+        };
         let keyword_item = keyword_item.clone();
 
         // Get the resource data
-        let data_bytes = self.base.lookup_record_by_keyword(&keyword_item).ok()?;
+        let data_bytes = self
+            .base
+            .lookup_record_by_keyword(&keyword_item)
+            .map_err(|e| MdictError::ResourceUnreadable {
+                key: keyword_item.key_text.clone(),
+                source: Box::new(e),
+            })?;
 
         // Encode as base64
         let definition = BASE64.encode(&data_bytes);
 
-        Some(LookupResult {
-            key_text: keyword_item.key_text.clone(),
+        Ok(Some(LookupResult {
+            key_text: keyword_item.key_text,
             definition,
-        })
+        }))
+    }
+
+    /// Locate a resource and write its data as base64 to `out`
+    ///
+    /// Unlike [`Self::locate`], the encoding is written in small chunks
+    /// straight from the decompressed record block, so neither a copy of
+    /// the resource nor the whole base64 string is held in memory. Returns
+    /// `Ok(None)` if no key matches, and fails if the record can't be read
+    /// or `out` returns an error.
+    pub fn locate_base64_to(
+        &mut self,
+        resource_key: &str,
+        out: &mut impl fmt::Write,
+    ) -> Result<Option<()>> {
+        let Some(keyword_item) = self.base.lookup_keyword_by_word(resource_key, false) else {
+            return Ok(None);
+        };
+        let keyword_item = keyword_item.clone();
+
+        let block_idx = self.base.record_block_for(&keyword_item)?;
+        let block = self.base.read_record_block(block_idx)?;
+        let data = self.base.slice_record(&block, block_idx, &keyword_item);
+
+        let mut encoded = [0u8; BASE64_CHUNK / 3 * 4];
+        for chunk in data.chunks(BASE64_CHUNK) {
+            let len = BASE64
+                .encode_slice(chunk, &mut encoded)
+                .expect("buffer holds an encoded chunk");
+            let text = std::str::from_utf8(&encoded[..len]).expect("base64 is ASCII");
+            out.write_str(text)
+                .map_err(|_| std::io::Error::other("writing base64 output failed"))?;
+        }
+        Ok(Some(()))
     }
 
     /// Locate a resource and return raw bytes
@@ -75,6 +190,54 @@ impl Mdd {
         self.base.lookup_record_by_keyword(&keyword_item).ok()
     }
 
+    /// Locate a resource and return `len` of its bytes from `start`, for
+    /// answering HTTP range requests
+    ///
+    /// The window is clamped to the resource, so a `start` at or past its
+    /// end gives an empty vector. The whole resource is still decompressed.
+    pub fn locate_range(&mut self, resource_key: &str, start: u64, len: u64) -> Option<Vec<u8>> {
+        let mut data = self.locate_raw(resource_key)?;
+        let size = data.len() as u64;
+        let start = start.min(size);
+        let end = start.saturating_add(len).min(size);
+        data.truncate(end as usize);
+        data.drain(..start as usize);
+        Some(data)
+    }
+
+    /// Locate a resource and return it as a `data:` URI, with the MIME type
+    /// from [`Self::get_resource_info`]
+    pub fn locate_data_uri(&mut self, resource_key: &str) -> Option<String> {
+        let info = self.get_resource_info(resource_key)?;
+        let data = self.locate_raw(resource_key)?;
+        Some(format!(
+            "data:{};base64,{}",
+            info.mime_type,
+            BASE64.encode(data)
+        ))
+    }
+
+    /// Locate a resource and return raw bytes, telling a missing resource
+    /// apart from one whose data can't be read
+    ///
+    /// Returns `Ok(None)` if no key matches, like [`Self::try_locate`], and
+    /// fails with `ResourceUnreadable` if the key is listed but its record
+    /// block is missing, truncated or corrupt.
+    pub fn try_locate_raw(&mut self, resource_key: &str) -> Result<Option<Vec<u8>>> {
+        let Some(keyword_item) = self.base.lookup_keyword_by_word(resource_key, false) else {
+            return Ok(None);
+        };
+        let keyword_item = keyword_item.clone();
+
+        self.base
+            .lookup_record_by_keyword(&keyword_item)
+            .map(Some)
+            .map_err(|e| MdictError::ResourceUnreadable {
+                key: keyword_item.key_text,
+                source: Box::new(e),
+            })
+    }
+
     /// Find resources with the given prefix
     pub fn prefix(&mut self, prefix: &str) -> Vec<LookupResult> {
         // Clone keywords to avoid borrowing issues
@@ -111,50 +274,141 @@ impl Mdd {
 
     /// Check if a resource exists
     pub fn contains(&self, resource_key: &str) -> bool {
-        self.base
-            .lookup_keyword_by_word(resource_key, false)
-            .is_some()
+        MdictFile::contains(self, resource_key)
+    }
+
+    /// Resolve an HTML `src`/`href` to the stored key of a resource
+    ///
+    /// Same as [`Self::resolve_in`] with no current directory.
+    pub fn resolve(&self, href: &str) -> Option<&str> {
+        self.resolve_in("", href)
+    }
+
+    /// Resolve an HTML `src`/`href` found in an entry living in directory
+    /// `dir` (e.g. `\html`) to the stored key of a resource
+    ///
+    /// Any `scheme://` prefix (such as MDict's `sound://`), query and
+    /// fragment are dropped and `/` becomes `\`. The candidates are then the
+    /// path as given, with a leading `\`, and relative to `dir`; each is
+    /// tried exactly, then ignoring case. Returns `None` if nothing matches.
+    ///
+    /// Every step is a binary search, except that in `KeyCaseSensitive`
+    /// files a reference that only matches ignoring case costs one scan of
+    /// the keys.
+    pub fn resolve_in(&self, dir: &str, href: &str) -> Option<&str> {
+        let path = href.split_once("://").map_or(href, |(_, rest)| rest);
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let mut path = path.trim().replace('/', "\\");
+        while let Some(rest) = path.strip_prefix(".\\") {
+            path = rest.to_string();
+        }
+        if path.is_empty() {
+            return None;
+        }
+
+        let mut candidates = vec![path.clone()];
+        if !path.starts_with('\\') {
+            candidates.push(format!("\\{}", path));
+            let dir = dir.replace('/', "\\");
+            let dir = dir.trim_matches('\\');
+            if !dir.is_empty() {
+                candidates.push(format!("\\{}\\{}", dir, path));
+            }
+        }
+
+        for candidate in &candidates {
+            let matches = self.base.keywords_equal_to(candidate);
+            if let Some(item) = matches.iter().find(|item| &item.key_text == candidate) {
+                return Some(&item.key_text);
+            }
+        }
+
+        let folded: Vec<String> = candidates.iter().map(|c| c.to_lowercase()).collect();
+        for (candidate, folded) in candidates.iter().zip(&folded) {
+            let matches = self.base.keywords_equal_to(candidate);
+            if let Some(item) = matches
+                .iter()
+                .find(|item| &item.key_text.to_lowercase() == folded)
+            {
+                return Some(&item.key_text);
+            }
+        }
+
+        // Stripping already folds case unless the file says otherwise; only
+        // then can a match hide outside the runs searched above. One scan
+        // serves every candidate, the earliest candidate winning
+        if !self.base.meta.key_case_sensitive {
+            return None;
+        }
+        let mut best: Option<(usize, &str)> = None;
+        for item in &self.base.keyword_list {
+            let key = item.key_text.to_lowercase();
+            let Some(rank) = folded.iter().position(|f| *f == key) else {
+                continue;
+            };
+            if best.is_none_or(|(best, _)| rank < best) {
+                best = Some((rank, &item.key_text));
+                if rank == 0 {
+                    break;
+                }
+            }
+        }
+        best.map(|(_, key)| key)
     }
 
     /// Get the file path
     pub fn filepath(&self) -> &str {
-        &self.base.filepath
+        MdictFile::filepath(self)
+    }
+
+    /// Coarse kind of a resource, for deciding how to render it
+    ///
+    /// Derived from the MIME type [`Self::get_resource_info`] reports.
+    /// Returns `None` if the key doesn't exist.
+    pub fn media_category(&self, resource_key: &str) -> Option<MediaCategory> {
+        let info = self.get_resource_info(resource_key)?;
+        Some(MediaCategory::from_mime_type(&info.mime_type))
+    }
+
+    /// Iterate over the resources of one [`MediaCategory`] with their bytes
+    ///
+    /// Keys are classified by extension before anything is read, and each
+    /// record block holding a match is decompressed once; resources come
+    /// in record order. If a block can't be read, each of its matching
+    /// resources yields the error instead.
+    pub fn iter_category(
+        &mut self,
+        category: MediaCategory,
+    ) -> impl Iterator<Item = Result<(String, Vec<u8>)>> + '_ {
+        let base = &self.base;
+        let matching = base.keyword_list.iter().filter(move |item| {
+            MediaCategory::from_mime_type(mime_type_for(&key_extension(&item.key_text))) == category
+        });
+        let groups = base.group_by_record_block(matching);
+
+        groups.into_iter().flat_map(move |(block_idx, items)| {
+            let block = base.read_record_block(block_idx);
+            items
+                .into_iter()
+                .map(|item| {
+                    let data = match &block {
+                        Ok(block) => base.slice_record(block, block_idx, &item).to_vec(),
+                        // Errors aren't cloneable; read each record again so
+                        // each reports its own
+                        Err(_) => base.lookup_record_by_keyword(&item)?,
+                    };
+                    Ok((item.key_text, data))
+                })
+                .collect::<Vec<_>>()
+        })
     }
 
     /// Get resource info without loading data
     pub fn get_resource_info(&self, resource_key: &str) -> Option<ResourceInfo> {
         let keyword_item = self.base.lookup_keyword_by_word(resource_key, false)?;
 
-        // Extract file extension from key
-        let extension = resource_key
-            .rsplit('.')
-            .next()
-            .map(|s| s.to_lowercase())
-            .unwrap_or_default();
-
-        // Determine MIME type
-        let mime_type = match extension.as_str() {
-            "jpg" | "jpeg" => "image/jpeg",
-            "png" => "image/png",
-            "gif" => "image/gif",
-            "bmp" => "image/bmp",
-            "svg" => "image/svg+xml",
-            "webp" => "image/webp",
-            "ico" => "image/x-icon",
-            "mp3" => "audio/mpeg",
-            "wav" => "audio/wav",
-            "ogg" => "audio/ogg",
-            "spx" => "audio/speex",
-            "css" => "text/css",
-            "js" => "application/javascript",
-            "html" | "htm" => "text/html",
-            "ttf" => "font/ttf",
-            "woff" => "font/woff",
-            "woff2" => "font/woff2",
-            "eot" => "application/vnd.ms-fontobject",
-            _ => "application/octet-stream",
-        }
-        .to_string();
+        let extension = key_extension(resource_key);
+        let mime_type = mime_type_for(&extension).to_string();
 
         Some(ResourceInfo {
             key: keyword_item.key_text.clone(),
@@ -162,6 +416,57 @@ impl Mdd {
             mime_type,
         })
     }
+
+    /// Write every resource into a zip archive and return the entry count
+    ///
+    /// Each record block is decompressed once. Entry names are the resource
+    /// keys with `\` normalized to `/`, and empty, `.` and `..` components
+    /// dropped, so no entry can land outside the extraction directory.
+    /// Archives with more than 65,535 entries or past 4 GiB use zip64.
+    /// Fails before anything is written if a single resource is 4 GiB or
+    /// more, a name exceeds 65,535 bytes, or two keys normalize to the same
+    /// name (e.g. `\x` and `/x`).
+    #[cfg(feature = "zip")]
+    pub fn export_zip(&mut self, out: &mut (impl Write + Seek)) -> Result<usize> {
+        self.export_zip_with_progress(out, |_, _| {})
+    }
+
+    /// [`Self::export_zip`], calling `progress(done, total)` after each
+    /// record block
+    #[cfg(feature = "zip")]
+    pub fn export_zip_with_progress(
+        &mut self,
+        out: &mut (impl Write + Seek),
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<usize> {
+        let mut names = std::collections::HashMap::with_capacity(self.base.keyword_list.len());
+        for item in &self.base.keyword_list {
+            crate::zip::to_u32(self.base.record_len(item), "entry size")?;
+            let name = zip_entry_name(&item.key_text);
+            crate::zip::to_name_len(&name)?;
+            if let Some(other) = names.insert(name, &item.key_text) {
+                return Err(MdictError::InvalidFormat(format!(
+                    "Resource keys {:?} and {:?} map to the same zip entry",
+                    other, item.key_text
+                )));
+            }
+        }
+
+        let groups = self.base.group_by_record_block(&self.base.keyword_list);
+        let total = groups.len();
+        let mut zip = crate::zip::ZipWriter::new(out);
+
+        for (done, (block_idx, items)) in groups.into_iter().enumerate() {
+            let block = self.base.read_record_block(block_idx)?;
+            for item in &items {
+                let data = self.base.slice_record(&block, block_idx, item);
+                zip.add_file(&zip_entry_name(&item.key_text), data)?;
+            }
+            progress(done + 1, total);
+        }
+
+        zip.finish()
+    }
 }
 
 /// Resource information
@@ -175,9 +480,64 @@ pub struct ResourceInfo {
     pub mime_type: String,
 }
 
+/// Zip entry name for a resource key, with `/` separators and no empty,
+/// `.` or `..` components
+#[cfg(feature = "zip")]
+fn zip_entry_name(resource_key: &str) -> String {
+    resource_key
+        .split(['\\', '/'])
+        .filter(|part| !matches!(*part, "" | "." | ".."))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Lowercased extension of a resource key, or the whole key if it has none
+fn key_extension(resource_key: &str) -> String {
+    resource_key
+        .rsplit('.')
+        .next()
+        .map(|s| s.to_lowercase())
+        .unwrap_or_default()
+}
+
+/// MIME type of a lowercased file extension
+fn mime_type_for(extension: &str) -> &'static str {
+    match extension {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "spx" => "audio/speex",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "html" | "htm" => "text/html",
+        "ttf" => "font/ttf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "eot" => "application/vnd.ms-fontobject",
+        _ => "application/octet-stream",
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::*;
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_zip_entry_name() {
+        use super::zip_entry_name;
+
+        assert_eq!(zip_entry_name("\\img\\a.png"), "img/a.png");
+        assert_eq!(zip_entry_name("\\..\\x"), "x");
+        assert_eq!(zip_entry_name("/a/./b//../c.css"), "a/b/c.css");
+    }
 
     #[test]
     fn test_mdd_creation() {
@@ -2,26 +2,141 @@
 //!
 //! This module provides the core parsing functionality for MDX/MDD files.
 
+use encoding_rs::{UTF_16BE, UTF_16LE};
 use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::Read;
+use std::ops::Range;
 use std::path::Path;
+use std::sync::Arc;
 
+use crate::cache::BlockCache;
+use crate::decompress::{BlockDecompressor, DefaultDecompressor, DEFAULT_DECOMPRESSOR_ID};
 use crate::error::{MdictError, Result};
-use crate::lzo;
+use crate::salsa20::salsa20_8;
 use crate::types::*;
-use crate::utils::{self, bytes_to_number, decode_string, decode_utf16le, parse_header, strip_key};
+use crate::utils::{self, bytes_to_number, decode_string, decode_utf16le, parse_header};
+
+/// Default limit on a block's declared decompressed size
+pub(crate) const DEFAULT_MAX_BLOCK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Definitions kept by `MdxBuilder::cache_definitions`
+pub(crate) const DEFINITION_CACHE_ENTRIES: usize = 1024;
+
+/// Options controlling how a dictionary is opened
+#[derive(Clone)]
+pub(crate) struct MdictOptions {
+    /// Decoder for key and record blocks
+    pub decompressor: Arc<dyn BlockDecompressor>,
+    /// Identifies `decompressor` in block cache keys
+    pub decompressor_id: u64,
+    /// Treat `_` as `!` when stripping MDD keys, matching MDict's sort
+    pub mdd_underscore_as_bang: bool,
+    /// User email and registration code for encrypted key headers
+    pub credentials: Option<(String, String)>,
+    /// Correction added to record offsets when slicing definitions
+    pub offset_fixup: i64,
+    /// Largest decompressed size a key or record block may declare
+    pub max_block_size: usize,
+    /// Build a reversed-key index for suffix search
+    pub suffix_index: bool,
+    /// Skip reading key blocks, leaving the keyword list empty
+    pub key_info_only: bool,
+    /// Take the file type from the header's root element instead of
+    /// rejecting a header that doesn't match the requested type
+    pub infer_file_ext: bool,
+    /// Shared cache of decompressed record blocks
+    pub block_cache: Option<BlockCache>,
+    /// Fail to open if the sorted keyword list isn't in lookup order
+    pub verify_sort_order: bool,
+    /// Fail on undecodable key text instead of keeping an empty key
+    pub strict_keys: bool,
+    /// Number of decoded definitions `Mdx::cached_lookup` keeps, 0 for none
+    pub definition_cache_entries: usize,
+    /// Number of `Mdx::lookup` results kept by stripped query, 0 for none
+    pub query_cache_entries: usize,
+    /// Headwords to look up instead of words that aren't found
+    pub aliases: HashMap<String, String>,
+    /// Check for UTF-8, GB18030 or Big5 even when the header declares an
+    /// encoding
+    #[cfg(feature = "cjk-detect")]
+    pub detect_cjk_encoding: bool,
+    /// Callback invoked after each lookup
+    #[cfg(feature = "metrics")]
+    pub on_lookup: Option<crate::metrics::LookupHook>,
+}
+
+impl Default for MdictOptions {
+    fn default() -> Self {
+        MdictOptions {
+            decompressor: Arc::new(DefaultDecompressor),
+            decompressor_id: DEFAULT_DECOMPRESSOR_ID,
+            mdd_underscore_as_bang: true,
+            credentials: None,
+            offset_fixup: 0,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            suffix_index: false,
+            key_info_only: false,
+            infer_file_ext: false,
+            block_cache: None,
+            verify_sort_order: false,
+            strict_keys: false,
+            definition_cache_entries: 0,
+            query_cache_entries: 0,
+            aliases: HashMap::new(),
+            #[cfg(feature = "cjk-detect")]
+            detect_cjk_encoding: false,
+            #[cfg(feature = "metrics")]
+            on_lookup: None,
+        }
+    }
+}
+
+/// Where a dictionary's bytes are read from
+enum Source {
+    /// An open file, read with positional reads
+    File(File),
+    /// A buffer in memory, possibly shared with other parsers
+    Shared(Arc<[u8]>),
+}
+
+impl Source {
+    fn len(&self) -> Result<u64> {
+        match self {
+            Source::File(file) => Ok(file.metadata()?.len()),
+            Source::Shared(data) => Ok(data.len() as u64),
+        }
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        match self {
+            Source::File(file) => read_exact_at(file, buf, offset),
+            Source::Shared(data) => {
+                let start = usize::try_from(offset).unwrap_or(usize::MAX);
+                let bytes = start
+                    .checked_add(buf.len())
+                    .and_then(|end| data.get(start..end))
+                    .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+}
 
 /// MDict base parser
 pub struct MdictBase {
-    /// File handle
-    file: File,
-    /// File path
+    /// File handle or shared buffer
+    source: Source,
+    /// File path; empty when opened from a shared buffer
     pub filepath: String,
     /// Dictionary metadata
     pub meta: DictMeta,
     /// Dictionary header attributes
     pub header: DictHeader,
+    /// Raw header bytes and checksum
+    pub header_meta: HeaderMeta,
     /// Key header information
     pub key_header: KeyHeader,
     /// Key block info list
@@ -32,6 +147,19 @@ pub struct MdictBase {
     pub record_header: RecordHeader,
     /// Record block info list
     pub record_info_list: Vec<RecordInfo>,
+    /// Number of UTF-16 keys recovered from misaligned or unterminated key
+    /// text
+    pub key_decode_warnings: usize,
+    /// Key blocks whose declared entry count disagrees with a plain NUL
+    /// scan
+    pub key_count_mismatches: Vec<KeyCountMismatch>,
+    /// Reversed stripped keys and their `keyword_list` indices, sorted, if
+    /// requested with `MdxBuilder::with_suffix_index`
+    suffix_index: Option<Vec<(String, usize)>>,
+    /// Open options
+    options: MdictOptions,
+    /// File length in bytes, checked against section offsets
+    file_len: u64,
 
     // Internal offsets
     header_end_offset: u64,
@@ -49,23 +177,171 @@ pub struct MdictBase {
 impl MdictBase {
     /// Create a new MdictBase from file path
     pub fn new<P: AsRef<Path>>(filepath: P, ext: FileExt) -> Result<Self> {
+        Self::with_options(filepath, ext, MdictOptions::default())
+    }
+
+    /// Create a new MdictBase from file path with custom options
+    pub(crate) fn with_options<P: AsRef<Path>>(
+        filepath: P,
+        ext: FileExt,
+        options: MdictOptions,
+    ) -> Result<Self> {
+        Self::open_index(filepath, ext, options)?.read_keywords()
+    }
+
+    /// Create a new MdictBase reading from a buffer shared with its other
+    /// owners
+    ///
+    /// Nothing is copied: blocks are read straight from `data`, so several
+    /// parsers can be opened over one buffer.
+    pub fn from_shared(data: Arc<[u8]>, ext: FileExt) -> Result<Self> {
+        Self::from_shared_with_options(data, ext, MdictOptions::default())
+    }
+
+    /// Create a new MdictBase from a shared buffer with custom options
+    pub(crate) fn from_shared_with_options(
+        data: Arc<[u8]>,
+        ext: FileExt,
+        options: MdictOptions,
+    ) -> Result<Self> {
+        Self::index_source(Source::Shared(data), String::new(), ext, options)?.read_keywords()
+    }
+
+    /// Read every key block into `keyword_list`, unless only the key
+    /// infos were requested
+    fn read_keywords(mut self) -> Result<Self> {
+        if self.options.key_info_only {
+            return Ok(self);
+        }
+
+        // Step 6: Read all key blocks
+        self.read_key_blocks()?;
+        self.index_keywords()?;
+
+        Ok(self)
+    }
+
+    /// Sort the loaded keywords and build the indices over them
+    pub(crate) fn index_keywords(&mut self) -> Result<()> {
+        self.sort_keyword_list();
+        self.build_suffix_index();
+
+        if self.options.verify_sort_order {
+            if let Some(i) = self.first_unsorted_index() {
+                return Err(MdictError::InvalidFormat(format!(
+                    "Keywords {:?} and {:?} are out of lookup order",
+                    self.keyword_list[i].key_text,
+                    self.keyword_list[i + 1].key_text
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Index `i` of the first keyword that compares greater than keyword
+    /// `i + 1` under [`Self::stripped_chars`], or `None` if the list is in
+    /// the order the allocation-free comparison expects
+    ///
+    /// Loading sorts by [`Self::strip`], which lowercases whole strings,
+    /// while [`Self::stripped_chars`] lowercases one character at a time.
+    /// The two disagree on context-sensitive mappings such as a final
+    /// sigma, and this finds where.
+    pub(crate) fn first_unsorted_index(&self) -> Option<usize> {
+        self.keyword_list.windows(2).position(|pair| {
+            self.stripped_chars(&pair[0].key_text)
+                .cmp(self.stripped_chars(&pair[1].key_text))
+                .is_gt()
+        })
+    }
+
+    /// Open a file and read everything except the key blocks, leaving
+    /// `keyword_list` empty
+    pub(crate) fn open_index<P: AsRef<Path>>(
+        filepath: P,
+        ext: FileExt,
+        options: MdictOptions,
+    ) -> Result<Self> {
         let path = filepath.as_ref();
         let file = File::open(path)?;
         let filepath_str = path.to_string_lossy().to_string();
+        Self::index_source(Source::File(file), filepath_str, ext, options)
+    }
 
-        let mut base = MdictBase {
-            file,
-            filepath: filepath_str,
+    /// Read everything except the key blocks from `source`
+    fn index_source(
+        source: Source,
+        filepath: String,
+        ext: FileExt,
+        options: MdictOptions,
+    ) -> Result<Self> {
+        let mut base = Self::unread(source, filepath, ext, options)?;
+        base.read_index()?;
+        Ok(base)
+    }
+
+    /// Read only the header and, unless it is encrypted, the key header of
+    /// the file at `path`
+    ///
+    /// Without an `ext`, the type is taken from the header.
+    pub(crate) fn read_header_info(path: &Path, ext: Option<FileExt>) -> Result<HeaderInfo> {
+        let file = File::open(path)?;
+        let filepath = path.to_string_lossy().to_string();
+        let options = MdictOptions {
+            infer_file_ext: ext.is_none(),
+            ..Default::default()
+        };
+        let ext = ext.unwrap_or(FileExt::Mdx);
+        let mut base = Self::unread(Source::File(file), filepath, ext, options)?;
+        base.read_header()?;
+
+        let keyword_count = if base.meta.encrypt == EncryptType::RecordBlock {
+            None
+        } else {
+            base.read_key_header()?;
+            Some(base.key_header.keyword_num)
+        };
+
+        Ok(HeaderInfo {
+            title: base
+                .header_str("Title")
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+            version: base.meta.version,
+            encoding: base.meta.encoding,
+            encrypt: base.meta.encrypt,
+            keyword_count,
+        })
+    }
+
+    /// A parser over `source` that hasn't read anything yet
+    fn unread(
+        source: Source,
+        filepath: String,
+        ext: FileExt,
+        options: MdictOptions,
+    ) -> Result<Self> {
+        let file_len = source.len()?;
+
+        Ok(MdictBase {
+            source,
+            filepath,
             meta: DictMeta {
                 ext,
                 ..Default::default()
             },
             header: DictHeader::new(),
+            header_meta: HeaderMeta::default(),
             key_header: KeyHeader::default(),
             key_info_list: Vec::new(),
             keyword_list: Vec::new(),
             record_header: RecordHeader::default(),
             record_info_list: Vec::new(),
+            key_decode_warnings: 0,
+            key_count_mismatches: Vec::new(),
+            suffix_index: None,
+            options,
+            file_len,
             header_end_offset: 0,
             key_header_start_offset: 0,
             key_header_end_offset: 0,
@@ -76,14 +352,14 @@ impl MdictBase {
             record_info_start_offset: 0,
             record_info_end_offset: 0,
             record_block_start_offset: 0,
-        };
-
-        base.read_dict()?;
-        Ok(base)
+        })
     }
 
-    /// Read and parse the dictionary file
-    fn read_dict(&mut self) -> Result<()> {
+    /// Read the dictionary index: everything but the key blocks
+    ///
+    /// The record section's position only depends on the key header, so it
+    /// can be read before the key blocks themselves.
+    fn read_index(&mut self) -> Result<()> {
         // Step 1: Read header
         self.read_header()?;
 
@@ -91,54 +367,217 @@ impl MdictBase {
         self.read_key_header()?;
 
         // Step 3: Read key block info
-        self.read_key_infos()?;
+        let key_infos = self.read_key_infos();
+        self.check_key_paths(key_infos)?;
 
-        // Step 4: Read all key blocks
-        self.read_key_blocks()?;
-
-        // Step 5: Read record header
+        // Step 4: Read record header
         self.read_record_header()?;
 
-        // Step 6: Read record block info
+        // Step 5: Read record block info
         self.read_record_infos()?;
 
-        // Sort keyword list - capture needed values before closure
-        let is_mdd = self.meta.ext == FileExt::Mdd;
-        self.keyword_list.sort_by(|a, b| {
-            let key_a = strip_key(&a.key_text, is_mdd);
-            let key_b = strip_key(&b.key_text, is_mdd);
-            key_a.cmp(&key_b)
-        });
+        #[cfg(feature = "cjk-detect")]
+        self.apply_detected_encoding()?;
 
         Ok(())
     }
 
+    /// Replace the header's encoding with one detected from sampled record
+    /// blocks, if it is missing or detection was requested
+    ///
+    /// A declared UTF-16 is always kept: the detector only tells byte
+    /// encodings apart. The key block info holds encoded first and last
+    /// keys, so it is read again when the encoding changes.
+    #[cfg(feature = "cjk-detect")]
+    fn apply_detected_encoding(&mut self) -> Result<()> {
+        let declared = self
+            .header
+            .get("Encoding")
+            .is_some_and(|s| !s.trim().is_empty());
+        let utf16 = matches!(self.meta.encoding, Encoding::Utf16Le | Encoding::Utf16Be);
+        if self.meta.ext != FileExt::Mdx
+            || (declared && (utf16 || !self.options.detect_cjk_encoding))
+        {
+            return Ok(());
+        }
+
+        // First, middle and last blocks
+        let count = self.record_info_list.len();
+        let mut indices = vec![0, count / 2, count.saturating_sub(1)];
+        indices.dedup();
+
+        let samples = indices
+            .into_iter()
+            .filter(|&i| i < count)
+            .map(|i| self.read_record_block(i).map(Arc::unwrap_or_clone))
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(encoding) = crate::detect::detect_encoding(&samples) {
+            if encoding != self.meta.encoding {
+                self.meta.encoding = encoding;
+                self.read_key_infos()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch to `encoding` and read the key index again with it
+    ///
+    /// The first/last keys of the key block info and every key block are
+    /// decoded afresh. If that fails, the previous encoding and keywords
+    /// are kept.
+    pub(crate) fn set_encoding(&mut self, encoding: Encoding) -> Result<()> {
+        let previous_encoding = std::mem::replace(&mut self.meta.encoding, encoding);
+        let key_info_list = std::mem::take(&mut self.key_info_list);
+        let keyword_list = std::mem::take(&mut self.keyword_list);
+        let suffix_index = self.suffix_index.take();
+        let warnings = std::mem::take(&mut self.key_decode_warnings);
+        let mismatches = std::mem::take(&mut self.key_count_mismatches);
+
+        let result = self.read_key_infos().and_then(|()| {
+            if self.options.key_info_only {
+                return Ok(());
+            }
+            self.read_key_blocks()?;
+            self.index_keywords()
+        });
+        if result.is_err() {
+            self.meta.encoding = previous_encoding;
+            self.key_info_list = key_info_list;
+            self.keyword_list = keyword_list;
+            self.suffix_index = suffix_index;
+            self.key_decode_warnings = warnings;
+            self.key_count_mismatches = mismatches;
+        }
+        result
+    }
+
+    /// Sort keyword list with the same normalization lookups use, so the
+    /// binary search agrees with the order
+    pub(crate) fn sort_keyword_list(&mut self) {
+        let mut keyword_list = std::mem::take(&mut self.keyword_list);
+        keyword_list.sort_by_cached_key(|item| self.strip(&item.key_text));
+        self.keyword_list = keyword_list;
+    }
+
+    /// Build the reversed-key index if the options ask for one
+    ///
+    /// Must run after the keyword list is sorted, since it stores indices.
+    pub(crate) fn build_suffix_index(&mut self) {
+        if !self.options.suffix_index {
+            return;
+        }
+        let mut index: Vec<(String, usize)> = self
+            .keyword_list
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (self.strip(&item.key_text).chars().rev().collect(), i))
+            .collect();
+        index.sort_unstable();
+        self.suffix_index = Some(index);
+    }
+
+    /// Keywords ending with `suffix` after stripping, at most `limit`
+    ///
+    /// Uses the reversed-key index when built, so matches come in order of
+    /// their reversed keys. Otherwise every keyword is checked and matches
+    /// come in index order.
+    pub(crate) fn suffix_keywords(&self, suffix: &str, limit: usize) -> Vec<&KeyWordItem> {
+        let stripped = self.strip(suffix);
+        match &self.suffix_index {
+            Some(index) => {
+                let reversed: String = stripped.chars().rev().collect();
+                let start = index.partition_point(|(key, _)| key.as_str() < reversed.as_str());
+                index[start..]
+                    .iter()
+                    .take_while(|(key, _)| key.starts_with(&reversed))
+                    .take(limit)
+                    .map(|&(_, i)| &self.keyword_list[i])
+                    .collect()
+            }
+            None => self
+                .keyword_list
+                .iter()
+                .filter(|item| self.strip(&item.key_text).ends_with(&stripped))
+                .take(limit)
+                .collect(),
+        }
+    }
+
     /// Read buffer from file at offset
-    fn read_buffer(&mut self, offset: u64, length: usize) -> Result<Vec<u8>> {
-        self.file.seek(SeekFrom::Start(offset))?;
+    ///
+    /// Uses positional reads, so it only needs `&self` and concurrent calls
+    /// don't interfere with each other.
+    fn read_buffer(&self, offset: u64, length: usize) -> Result<Vec<u8>> {
         let mut buffer = vec![0u8; length];
-        self.file.read_exact(&mut buffer)?;
+        self.source.read_exact_at(&mut buffer, offset)?;
         Ok(buffer)
     }
 
+    /// Number of decoded definitions to cache, 0 if caching is off
+    pub(crate) fn definition_cache_entries(&self) -> usize {
+        self.options.definition_cache_entries
+    }
+
+    /// Number of lookup results to cache, 0 if caching is off
+    pub(crate) fn query_cache_entries(&self) -> usize {
+        self.options.query_cache_entries
+    }
+
+    /// Callback to report lookup metrics to, if one was installed
+    #[cfg(feature = "metrics")]
+    pub(crate) fn lookup_hook(&self) -> Option<&crate::metrics::LookupHook> {
+        self.options.on_lookup.as_ref()
+    }
+
+    /// Fail with `InvalidFormat` if `section` would end past the end of
+    /// the file
+    ///
+    /// Checked before each section is read, so a corrupt size gives a clear
+    /// error instead of an `UnexpectedEof` or a huge allocation.
+    fn check_section_end(&self, section: &str, start: u64, size: u64) -> Result<()> {
+        match start.checked_add(size) {
+            Some(end) if end <= self.file_len => Ok(()),
+            _ => Err(MdictError::InvalidFormat(format!(
+                "{} at offset {} with size {} exceeds file length {}; the file is truncated or its header is wrong",
+                section, start, size, self.file_len
+            ))),
+        }
+    }
+
     /// Read header section
     fn read_header(&mut self) -> Result<()> {
         // [0:4] - 4 bytes header length (big-endian)
         let header_size_buf = self.read_buffer(0, 4)?;
         let header_byte_size = bytes_to_number(&header_size_buf) as usize;
+        self.check_section_end("Header", 4, header_byte_size as u64 + 4)?;
 
         // [4:header_byte_size + 4] - header content
         let header_buffer = self.read_buffer(4, header_byte_size)?;
 
-        // [header_bytes_size + 4:header_bytes_size + 8] - Adler32 checksum (skip)
+        // [header_bytes_size + 4:header_bytes_size + 8] - Adler32 checksum
+        let checksum_buf = self.read_buffer(4 + header_byte_size as u64, 4)?;
         self.header_end_offset = (header_byte_size + 4 + 4) as u64;
         self.key_header_start_offset = self.header_end_offset;
 
-        // Decode UTF-16LE header text
+        // Decode UTF-16 header text; rare files are big-endian with a BOM
+        let big_endian = header_buffer.starts_with(&[0xfe, 0xff]);
         let header_text = decode_utf16le(&header_buffer)?;
 
         // Parse XML header attributes
         self.header = parse_header(&header_text)?;
+        self.check_file_ext(&header_text)?;
+        self.header_meta = HeaderMeta {
+            byte_size: header_byte_size as u32,
+            raw: header_buffer,
+            checksum: u32::from_le_bytes([
+                checksum_buf[0],
+                checksum_buf[1],
+                checksum_buf[2],
+                checksum_buf[3],
+            ]),
+        };
 
         // Set default values
         if !self.header.contains_key("KeyCaseSensitive") {
@@ -150,33 +589,23 @@ impl MdictBase {
                 .insert("StripKey".to_string(), "Yes".to_string());
         }
 
+        self.meta.strip_mode = StripMode::from_header(&self.header["StripKey"]);
+        self.meta.key_case_sensitive = self.header_bool("KeyCaseSensitive", false);
+
         // Determine encryption type
-        let encrypted = self
-            .header
-            .get("Encrypted")
-            .map(|s| s.as_str())
-            .unwrap_or("");
+        let encrypted = self.header_str("Encrypted").unwrap_or("");
         self.meta.encrypt = match encrypted {
             "" | "No" => EncryptType::None,
             "Yes" => EncryptType::RecordBlock,
             s => EncryptType::from(s.parse::<u8>().unwrap_or(0)),
         };
 
-        // Determine version and number format
-        let version_str = self
-            .header
-            .get("GeneratedByEngineVersion")
-            .map(|s| s.as_str())
-            .unwrap_or("1.2");
-        self.meta.version = version_str.parse::<f64>().unwrap_or(1.2);
+        self.meta.register_by = RegisterBy::from_header(self.header_str("RegisterBy"));
 
-        if self.meta.version >= 2.0 {
-            self.meta.num_width = 8;
-            self.meta.num_fmt = NumFmt::Uint64;
-        } else {
-            self.meta.num_width = 4;
-            self.meta.num_fmt = NumFmt::Uint32;
-        }
+        // Determine version and number format
+        let version_str = self.header_str("GeneratedByEngineVersion").unwrap_or("1.2");
+        self.meta
+            .set_version(utils::parse_engine_version(version_str).unwrap_or(1.2));
 
         // Determine encoding
         let encoding_str = self
@@ -189,15 +618,96 @@ impl MdictBase {
             "" => Encoding::Utf8,
             "gbk" | "gb2312" => Encoding::Gb18030,
             "big5" => Encoding::Big5,
-            "utf16" | "utf-16" => Encoding::Utf16Le,
+            "utf16" | "utf-16" if big_endian => Encoding::Utf16Be,
+            "utf16" | "utf-16" | "utf-16le" => Encoding::Utf16Le,
+            "utf16be" | "utf-16be" => Encoding::Utf16Be,
             _ => Encoding::Utf8,
         };
 
-        // MDD files always use UTF-16LE
+        // An explicit byte order wins over the BOM, but the mismatch is kept
+        self.meta.bom_conflict = big_endian && encoding_str == "utf-16le";
+
+        // MDD files always use UTF-16, in the header's byte order unless
+        // declared otherwise
         if self.meta.ext == FileExt::Mdd {
-            self.meta.encoding = Encoding::Utf16Le;
+            self.meta.encoding = if big_endian && !self.meta.bom_conflict {
+                Encoding::Utf16Be
+            } else {
+                Encoding::Utf16Le
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Check that the header belongs to the requested file type, or with
+    /// `infer_file_ext` adopt the type it names
+    ///
+    /// Opening an MDD as MDX (or the reverse) would split the keys with the
+    /// wrong text width and return garbage instead of failing. A
+    /// `<Library_Data>` root is always an MDD. Some MDDs use a
+    /// `<Dictionary>` root too, so for an MDD that one only fails together
+    /// with a byte encoding, which an MDD never declares; otherwise
+    /// [`Self::check_key_paths`] decides once the first key is read. An
+    /// unknown root falls back to the encoding alone.
+    fn check_file_ext(&mut self, header_text: &str) -> Result<()> {
+        let named = utils::header_file_ext(header_text);
+        if self.options.infer_file_ext {
+            self.meta.ext = named.unwrap_or(FileExt::Mdx);
+            return Ok(());
+        }
+
+        let byte_encoding = self.header_str("Encoding").is_some_and(|encoding| {
+            let encoding = encoding.trim().to_lowercase();
+            !encoding.is_empty() && !encoding.replace('-', "").starts_with("utf16")
+        });
+        let looks_like = match (named, self.meta.ext) {
+            (Some(FileExt::Mdd), _) => FileExt::Mdd,
+            (Some(FileExt::Mdx), FileExt::Mdx) => FileExt::Mdx,
+            _ if byte_encoding => FileExt::Mdx,
+            _ => return Ok(()),
+        };
+        self.check_looks_like(looks_like)
+    }
+
+    /// For an MDD whose root doesn't say `<Library_Data>`, check that the
+    /// first key is a resource path starting with `\` or `/`
+    ///
+    /// Keys of an MDX read as MDD are headwords, or garbage when they are
+    /// not UTF-16. Key block info that doesn't decode fails with its own
+    /// error, since a damaged MDD can't be told from a misnamed MDX.
+    fn check_key_paths(&self, key_infos: Result<()>) -> Result<()> {
+        let root = decode_utf16le(&self.header_meta.raw)
+            .ok()
+            .and_then(|text| utils::header_file_ext(&text));
+        if self.options.infer_file_ext
+            || self.meta.ext != FileExt::Mdd
+            || root == Some(FileExt::Mdd)
+        {
+            return key_infos;
+        }
+
+        key_infos?;
+        let is_path = |key: &str| key.starts_with(['\\', '/']);
+        if self
+            .key_info_list
+            .first()
+            .is_none_or(|info| is_path(&info.first_key))
+        {
+            return Ok(());
         }
+        self.check_looks_like(FileExt::Mdx)
+    }
 
+    /// Fail with `InvalidFormat` unless `looks_like` is the requested type
+    fn check_looks_like(&self, looks_like: FileExt) -> Result<()> {
+        if looks_like != self.meta.ext {
+            return Err(MdictError::InvalidFormat(format!(
+                "Expected {}, but the header looks like {}",
+                self.meta.ext.as_str().to_uppercase(),
+                looks_like.as_str().to_uppercase()
+            )));
+        }
         Ok(())
     }
 
@@ -211,11 +721,35 @@ impl MdictBase {
         } else {
             4 * 4
         };
-        let key_header_buf = self.read_buffer(self.key_header_start_offset, header_meta_size)?;
-
-        // Check encryption
-        if self.meta.encrypt == EncryptType::RecordBlock && self.meta.passcode.is_none() {
-            return Err(MdictError::EncryptedFileRequiresPasscode);
+        self.check_section_end(
+            "Key header",
+            self.key_header_start_offset,
+            header_meta_size as u64,
+        )?;
+        let mut key_header_buf =
+            self.read_buffer(self.key_header_start_offset, header_meta_size)?;
+
+        // Registered dictionaries encrypt the key header with a per-user key
+        if self.meta.encrypt == EncryptType::RecordBlock {
+            let (user_id, regcode) = self
+                .options
+                .credentials
+                .as_ref()
+                .ok_or(MdictError::EncryptedFileRequiresPasscode)?;
+            let key = utils::regcode_key(user_id, regcode, self.meta.register_by)?;
+            key_header_buf = salsa20_8(&key_header_buf, &key);
+
+            // v2.0 checksums the decrypted header, which catches wrong
+            // credentials before the garbage sizes are used
+            if self.meta.version >= 2.0 {
+                let checksum_buf =
+                    self.read_buffer(self.key_header_start_offset + header_meta_size as u64, 4)?;
+                if bytes_to_number(&checksum_buf) != utils::adler32(&key_header_buf) as u64 {
+                    return Err(MdictError::DecryptionError(
+                        "Key header checksum mismatch, credentials are wrong".to_string(),
+                    ));
+                }
+            }
         }
 
         let mut offset = 0;
@@ -254,6 +788,17 @@ impl MdictBase {
 
         self.key_block_info_start_offset = self.key_header_end_offset;
 
+        self.check_section_end(
+            "Key block info",
+            self.key_block_info_start_offset,
+            self.key_header.key_info_packed_size,
+        )?;
+        self.check_section_end(
+            "Key blocks",
+            self.key_block_info_start_offset + self.key_header.key_info_packed_size,
+            self.key_header.keyword_block_packed_size,
+        )?;
+
         Ok(())
     }
 
@@ -274,8 +819,10 @@ impl MdictBase {
 
             // Handle compression
             if comp_type == Some(CompressionType::Zlib) {
+                let unpack_size = self.key_header.key_info_unpack_size as usize;
+                self.check_block_size(unpack_size)?;
                 let compressed_data = &key_info_buf[8..];
-                let mut decoder = ZlibDecoder::new(compressed_data);
+                let mut decoder = ZlibDecoder::new(compressed_data).take(unpack_size as u64 + 1);
                 let mut decompressed = Vec::new();
                 decoder.read_to_end(&mut decompressed)?;
                 key_info_buf = decompressed;
@@ -302,65 +849,39 @@ impl MdictBase {
         let mut kb_unpack_size_accu = 0u64;
 
         let num_width = self.meta.num_width;
-        let is_utf16 = self.meta.encoding == Encoding::Utf16Le;
+        let text_len_width = self.meta.text_len_width;
+        let unit_width = self.meta.text_unit_width();
+        let terminator = if self.meta.version >= 2.0 { 1 } else { 0 };
 
         while kb_count < key_block_num {
             // Read number of entries in this block
             let block_word_count =
-                bytes_to_number(&key_info_buf[index_offset..index_offset + num_width]);
-            index_offset += num_width;
+                bytes_to_number(take(key_info_buf, &mut index_offset, num_width)?);
 
             // Read first word size
             let first_word_size_raw =
-                bytes_to_number(&key_info_buf[index_offset..index_offset + num_width / 4]) as usize;
-            index_offset += num_width / 4;
-
-            // Adjust for encoding
-            let first_word_size = if self.meta.version >= 2.0 {
-                if is_utf16 {
-                    (first_word_size_raw + 1) * 2
-                } else {
-                    first_word_size_raw + 1
-                }
-            } else if is_utf16 {
-                first_word_size_raw * 2
-            } else {
-                first_word_size_raw
-            };
+                bytes_to_number(take(key_info_buf, &mut index_offset, text_len_width)?) as usize;
+
+            // Sizes are in code units; v2.0 adds a NUL terminator
+            let first_word_size = (first_word_size_raw + terminator) * unit_width;
 
             // Read first word
-            let first_word_buffer = &key_info_buf[index_offset..index_offset + first_word_size];
-            index_offset += first_word_size;
+            let first_word_buffer = take(key_info_buf, &mut index_offset, first_word_size)?;
 
             // Read last word size
             let last_word_size_raw =
-                bytes_to_number(&key_info_buf[index_offset..index_offset + num_width / 4]) as usize;
-            index_offset += num_width / 4;
-
-            let last_word_size = if self.meta.version >= 2.0 {
-                if is_utf16 {
-                    (last_word_size_raw + 1) * 2
-                } else {
-                    last_word_size_raw + 1
-                }
-            } else if is_utf16 {
-                last_word_size_raw * 2
-            } else {
-                last_word_size_raw
-            };
+                bytes_to_number(take(key_info_buf, &mut index_offset, text_len_width)?) as usize;
+
+            let last_word_size = (last_word_size_raw + terminator) * unit_width;
 
             // Read last word
-            let last_word_buffer = &key_info_buf[index_offset..index_offset + last_word_size];
-            index_offset += last_word_size;
+            let last_word_buffer = take(key_info_buf, &mut index_offset, last_word_size)?;
 
             // Read pack size
-            let pack_size = bytes_to_number(&key_info_buf[index_offset..index_offset + num_width]);
-            index_offset += num_width;
+            let pack_size = bytes_to_number(take(key_info_buf, &mut index_offset, num_width)?);
 
             // Read unpack size
-            let unpack_size =
-                bytes_to_number(&key_info_buf[index_offset..index_offset + num_width]);
-            index_offset += num_width;
+            let unpack_size = bytes_to_number(take(key_info_buf, &mut index_offset, num_width)?);
 
             // Decode first and last keys
             let first_key = decode_string(first_word_buffer, self.meta.encoding)
@@ -395,77 +916,127 @@ impl MdictBase {
 
     /// Read all key blocks
     fn read_key_blocks(&mut self) -> Result<()> {
-        let key_block_start = self.key_block_info_end_offset;
-
-        // Collect key info data first to avoid borrowing issues
-        let key_info_data: Vec<(usize, u64, usize, usize)> = self
-            .key_info_list
-            .iter()
-            .enumerate()
-            .map(|(idx, ki)| {
-                (
-                    idx,
-                    ki.key_block_pack_accumulator,
-                    ki.key_block_pack_size as usize,
-                    ki.key_block_unpack_size as usize,
-                )
-            })
-            .collect();
-
-        for (idx, pack_accum, packed_size, unpack_size) in key_info_data {
-            let offset = key_block_start + pack_accum;
-
-            let packed_buf = self.read_buffer(offset, packed_size)?;
-            let unpacked_buf = self.unpack_key_block(&packed_buf, unpack_size)?;
-
-            let mut keywords = self.split_key_block(&unpacked_buf, idx)?;
-            self.keyword_list.append(&mut keywords);
+        for idx in 0..self.key_info_list.len() {
+            self.load_key_block(idx)?;
         }
+        Ok(())
+    }
 
-        // Set record end offsets
-        for i in 1..self.keyword_list.len() {
+    /// Read key block `idx`, append its keywords to `keyword_list` and
+    /// return the range of the new entries
+    ///
+    /// Blocks must be loaded in order: each entry's `record_end_offset` is
+    /// the next entry's start, so the last entry of a block is only
+    /// completed when the following block is loaded.
+    pub(crate) fn load_key_block(&mut self, idx: usize) -> Result<Range<usize>> {
+        let unpacked_buf = self.read_key_block(idx)?;
+        let (mut keywords, warnings, mismatch) = self.split_key_block(&unpacked_buf, idx)?;
+        self.key_decode_warnings += warnings;
+        self.key_count_mismatches.extend(mismatch);
+
+        let start = self.keyword_list.len();
+        self.keyword_list.append(&mut keywords);
+
+        // Set record end offsets, including the previous block's last entry
+        for i in start.max(1)..self.keyword_list.len() {
             self.keyword_list[i - 1].record_end_offset = self.keyword_list[i].record_start_offset;
         }
 
-        Ok(())
+        Ok(start..self.keyword_list.len())
     }
 
-    /// Unpack a key block
-    fn unpack_key_block(&self, packed_buf: &[u8], unpack_size: usize) -> Result<Vec<u8>> {
-        let comp_type = CompressionType::from_bytes(packed_buf).ok_or_else(|| {
-            MdictError::InvalidCompressionType(u32::from_le_bytes([
-                packed_buf[0],
-                packed_buf[1],
-                packed_buf[2],
-                packed_buf[3],
-            ]))
-        })?;
+    /// Read and decompress key block `idx`
+    pub(crate) fn read_key_block(&self, idx: usize) -> Result<Vec<u8>> {
+        let (packed_buf, unpack_size) = self.read_packed_key_block(idx)?;
+        self.unpack_key_block(&packed_buf, unpack_size)
+    }
 
-        match comp_type {
-            CompressionType::None => Ok(packed_buf[8..].to_vec()),
-            CompressionType::Lzo => lzo::decompress(&packed_buf[8..], unpack_size),
-            CompressionType::Zlib => {
-                let mut decoder = ZlibDecoder::new(&packed_buf[8..]);
-                let mut decompressed = Vec::new();
-                decoder.read_to_end(&mut decompressed)?;
-                Ok(decompressed)
-            }
-        }
+    /// Read the still compressed key block `idx`, along with its declared
+    /// decompressed size
+    pub(crate) fn read_packed_key_block(&self, idx: usize) -> Result<(Vec<u8>, usize)> {
+        let key_info = &self.key_info_list[idx];
+        let offset = self.key_block_info_end_offset + key_info.key_block_pack_accumulator;
+        let packed_size = key_info.key_block_pack_size as usize;
+        let unpack_size = key_info.key_block_unpack_size as usize;
+
+        Ok((self.read_buffer(offset, packed_size)?, unpack_size))
     }
 
-    /// Split key block into individual keywords
-    fn split_key_block(&self, key_block: &[u8], key_block_idx: usize) -> Result<Vec<KeyWordItem>> {
-        let width = if self.meta.encoding == Encoding::Utf16Le || self.meta.ext == FileExt::Mdd {
-            2
-        } else {
-            1
-        };
+    /// Unpack a key block
+    pub(crate) fn unpack_key_block(
+        &self,
+        packed_buf: &[u8],
+        unpack_size: usize,
+    ) -> Result<Vec<u8>> {
+        let tag = block_tag(packed_buf)?;
+        self.check_block_size(unpack_size)?;
+        self.options
+            .decompressor
+            .decompress(tag, &packed_buf[8..], unpack_size)
+    }
 
+    /// Split key block into individual keywords
+    ///
+    /// Each entry is a big-endian record offset of `num_width` bytes (4 for
+    /// v1.x, 8 for v2.0+) followed by NUL-terminated key text. In UTF-16LE
+    /// files, which includes every MDD, the text and its terminator are
+    /// 2-byte code units, and the terminator is searched for on code unit
+    /// boundaries so a `0x00` high or low byte inside a character is not
+    /// mistaken for it.
+    ///
+    /// If corruption leaves a UTF-16 key with an odd byte length, the
+    /// aligned scan runs off the end of the block. The terminator is then
+    /// searched for at any offset, or the key taken to run to the end of
+    /// the block, and the text is cut to an even length and decoded as far
+    /// as it is valid. Such keys are counted in the returned warning count.
+    ///
+    /// Splitting stops after the entry count declared in the key block
+    /// info, so trailing bytes aren't read as keys, and a last key without
+    /// its NUL runs to the end of the block.
+    ///
+    /// Key text that doesn't decode is kept as an empty key, unless
+    /// `strict_keys` is set: then both cases fail with `EncodingError`
+    /// giving the block index and byte offsets.
+    fn split_key_block(
+        &self,
+        key_block: &[u8],
+        key_block_idx: usize,
+    ) -> Result<(Vec<KeyWordItem>, usize, Option<KeyCountMismatch>)> {
+        let width = self.meta.text_unit_width();
+
+        let mut warnings = 0;
         let mut key_list = Vec::new();
         let mut key_start_index = 0;
         let num_width = self.meta.num_width;
+        let declared = self
+            .key_info_list
+            .get(key_block_idx)
+            .map(|info| info.key_block_entries_num as usize)
+            .filter(|&n| n > 0);
+        // Keys normally end at their first terminator. Only when that
+        // disagrees with the declared count can keys hold a NUL
+        let mismatch = declared
+            .map(|declared| KeyCountMismatch {
+                key_block_idx,
+                declared,
+                scanned: count_terminated_keys(key_block, num_width, width),
+            })
+            .filter(|mismatch| mismatch.declared != mismatch.scanned);
+        let record_data_size = self
+            .record_info_list
+            .last()
+            .map_or(0, |info| info.unpack_accumulate_offset + info.unpack_size);
+        // A terminator is then real if what follows can be the next entry:
+        // the end of the block, or a record offset inside the record data.
+        // Otherwise the NUL is part of the key
+        let starts_entry = |pos: usize| {
+            mismatch.is_none()
+                || pos + num_width > key_block.len()
+                || record_data_size == 0
+                || bytes_to_number(&key_block[pos..pos + num_width]) < record_data_size
+        };
 
-        while key_start_index < key_block.len() {
+        while key_start_index < key_block.len() && declared.is_none_or(|n| key_list.len() < n) {
             // Read record offset
             if key_start_index + num_width > key_block.len() {
                 break;
@@ -473,31 +1044,77 @@ impl MdictBase {
             let meaning_offset =
                 bytes_to_number(&key_block[key_start_index..key_start_index + num_width]);
 
-            // Find key end (null terminator)
+            // Find key end (null terminator). The entry count bounds the
+            // last key, so only earlier keys can hold embedded NULs
+            let is_last = declared == Some(key_list.len() + 1);
             let mut key_end_index = None;
+            let mut first_terminator = None;
             let mut i = key_start_index + num_width;
-            while i < key_block.len() {
-                if (width == 1 && key_block[i] == 0)
-                    || (width == 2
-                        && i + 1 < key_block.len()
-                        && key_block[i] == 0
-                        && key_block[i + 1] == 0)
-                {
-                    key_end_index = Some(i);
+            while let Some(pos) = find_terminator(key_block, i, width) {
+                first_terminator.get_or_insert(pos);
+                if is_last || starts_entry(pos + width) {
+                    key_end_index = Some(pos);
                     break;
                 }
-                i += width;
+                i = pos + width;
+            }
+            // No terminator is followed by an entry: keep the first
+            if key_end_index.is_none() {
+                key_end_index = first_terminator;
             }
 
-            let key_end = match key_end_index {
-                Some(idx) => idx,
+            // A final single-byte key may lack its terminator; it then runs
+            // to the end of the block. So does the key of a block declaring
+            // one entry, as some MDDs store one resource per key block
+            let text_start = key_start_index + num_width;
+            let runs_to_end = text_start < key_block.len()
+                && (width == 1
+                    || (declared == Some(1) && (key_block.len() - text_start).is_multiple_of(2)));
+            let key_end_index = key_end_index.or(runs_to_end.then_some(key_block.len()));
+            let (key_end, key_text) = match key_end_index {
+                Some(idx) => {
+                    let key_text_buffer = &key_block[text_start..idx];
+                    let key_text = match decode_string(key_text_buffer, self.meta.encoding) {
+                        Ok(text) => text,
+                        Err(_) if self.options.strict_keys => {
+                            let offset = text_start
+                                + utils::first_invalid_byte(key_text_buffer, self.meta.encoding)
+                                    .unwrap_or(0);
+                            return Err(MdictError::EncodingError(format!(
+                                "Key block {}: key text at byte {} is not valid {:?} (first bad byte at {})",
+                                key_block_idx, text_start, self.meta.encoding, offset
+                            )));
+                        }
+                        Err(_) => String::new(),
+                    };
+                    (idx, key_text)
+                }
+                None if width == 2 && text_start < key_block.len() && self.options.strict_keys => {
+                    return Err(MdictError::EncodingError(format!(
+                        "Key block {}: key text at byte {} has no aligned UTF-16 terminator",
+                        key_block_idx, text_start
+                    )));
+                }
+                None if width == 2 && text_start < key_block.len() => {
+                    // Misaligned: any `00 00`, else the rest of the block
+                    let idx = key_block[text_start..]
+                        .windows(2)
+                        .position(|w| w == [0, 0])
+                        .map_or(key_block.len(), |pos| text_start + pos);
+                    let even_end = text_start + (idx - text_start) / 2 * 2;
+                    warnings += 1;
+                    let decoder = if self.meta.encoding == Encoding::Utf16Be {
+                        UTF_16BE
+                    } else {
+                        UTF_16LE
+                    };
+                    let (key_text, _) =
+                        decoder.decode_without_bom_handling(&key_block[text_start..even_end]);
+                    (idx, key_text.trim_end_matches('\u{FFFD}').to_string())
+                }
                 None => break,
             };
 
-            // Extract key text
-            let key_text_buffer = &key_block[key_start_index + num_width..key_end];
-            let key_text = decode_string(key_text_buffer, self.meta.encoding).unwrap_or_default();
-
             key_list.push(KeyWordItem {
                 record_start_offset: meaning_offset,
                 record_end_offset: 0, // Will be set later
@@ -508,7 +1125,7 @@ impl MdictBase {
             key_start_index = key_end + width;
         }
 
-        Ok(key_list)
+        Ok((key_list, warnings, mismatch))
     }
 
     /// Read record header section
@@ -522,6 +1139,11 @@ impl MdictBase {
             4 * 4
         };
         self.record_header_end_offset = self.record_header_start_offset + record_header_len as u64;
+        self.check_section_end(
+            "Record header",
+            self.record_header_start_offset,
+            record_header_len as u64,
+        )?;
 
         let record_header_buf =
             self.read_buffer(self.record_header_start_offset, record_header_len)?;
@@ -555,14 +1177,35 @@ impl MdictBase {
     fn read_record_infos(&mut self) -> Result<()> {
         self.record_info_start_offset = self.record_header_end_offset;
 
+        self.check_section_end(
+            "Record block info",
+            self.record_info_start_offset,
+            self.record_header.record_info_comp_size,
+        )?;
+        self.check_section_end(
+            "Record blocks",
+            self.record_info_start_offset + self.record_header.record_info_comp_size,
+            self.record_header.record_block_comp_size,
+        )?;
+
         let record_info_size = self.record_header.record_info_comp_size as usize;
         let record_info_buf = self.read_buffer(self.record_info_start_offset, record_info_size)?;
 
+        let num_width = self.meta.num_width;
+        let needed = (self.record_header.record_blocks_num as usize).saturating_mul(2 * num_width);
+        if record_info_buf.len() < needed {
+            return Err(MdictError::InvalidFormat(format!(
+                "Record info is {} bytes, but {} record blocks need {}",
+                record_info_buf.len(),
+                self.record_header.record_blocks_num,
+                needed
+            )));
+        }
+
         let mut record_info_list = Vec::new();
         let mut offset = 0;
         let mut compressed_adder = 0u64;
         let mut decompression_adder = 0u64;
-        let num_width = self.meta.num_width;
 
         for _ in 0..self.record_header.record_blocks_num {
             // Read pack size
@@ -584,6 +1227,27 @@ impl MdictBase {
             decompression_adder += unpack_size;
         }
 
+        // Leftover bytes mean the sizes were misread, and every record block
+        // offset computed from the section's end would be shifted
+        if offset != record_info_size {
+            return Err(MdictError::InvalidFormat(format!(
+                "Record info is {} bytes, but {} record blocks use {}; {} bytes left over",
+                record_info_size,
+                self.record_header.record_blocks_num,
+                offset,
+                record_info_size - offset
+            )));
+        }
+
+        // v1.x key blocks store each record's start as 4 bytes. Past 4 GiB
+        // those offsets would have wrapped, and slicing with them would
+        // silently return the wrong records
+        if num_width == 4 && decompression_adder > u64::from(u32::MAX) + 1 {
+            return Err(MdictError::OffsetOverflow {
+                total: decompression_adder,
+            });
+        }
+
         self.record_info_list = record_info_list;
         self.record_info_end_offset = self.record_info_start_offset + record_info_size as u64;
         self.record_block_start_offset = self.record_info_end_offset;
@@ -591,36 +1255,50 @@ impl MdictBase {
         Ok(())
     }
 
+    /// Header attribute `key`, if present
+    pub fn header_str(&self, key: &str) -> Option<&str> {
+        self.header.get(key).map(|s| s.as_str())
+    }
+
+    /// Header flag `key` parsed as `Yes`/`No` (see
+    /// [`utils::parse_header_bool`]), or `default` if it is missing or
+    /// unrecognized
+    pub fn header_bool(&self, key: &str, default: bool) -> bool {
+        self.header_str(key)
+            .and_then(utils::parse_header_bool)
+            .unwrap_or(default)
+    }
+
     /// Strip key for comparison
     pub fn strip(&self, key: &str) -> String {
         let is_mdd = self.meta.ext == FileExt::Mdd;
-        let mut result = key.to_string();
-
-        // Check StripKey setting
-        let strip_key = self
-            .header
-            .get("StripKey")
-            .map(|s| s.as_str())
-            .unwrap_or("Yes");
 
-        if strip_key == "Yes" {
-            result = utils::strip_key(&result, is_mdd);
-        }
-
-        // Check KeyCaseSensitive setting
-        let case_sensitive = self
-            .header
-            .get("KeyCaseSensitive")
-            .map(|s| s.as_str())
-            .unwrap_or("No");
+        let mut result = match self.meta.strip_mode {
+            StripMode::None => key.to_string(),
+            StripMode::StripPunct => {
+                utils::strip_key(key, is_mdd, self.options.mdd_underscore_as_bang)
+            }
+            StripMode::CompactWhitespace => utils::compact_whitespace(key),
+        };
 
-        if case_sensitive == "No" {
+        if !self.meta.key_case_sensitive {
             result = result.to_lowercase();
         }
 
         result.trim().to_string()
     }
 
+    /// The characters of [`Self::strip`]`(key)`, without allocating
+    pub(crate) fn stripped_chars<'a>(&self, key: &'a str) -> utils::StrippedChars<'a> {
+        utils::StrippedChars::new(
+            key,
+            self.meta.strip_mode,
+            self.meta.ext == FileExt::Mdd,
+            self.options.mdd_underscore_as_bang,
+            !self.meta.key_case_sensitive,
+        )
+    }
+
     /// Compare two keys
     pub fn compare_keys(&self, a: &str, b: &str) -> std::cmp::Ordering {
         let stripped_a = self.strip(a);
@@ -630,6 +1308,34 @@ impl MdictBase {
 
     /// Binary search for keyword by word
     pub fn lookup_keyword_by_word(&self, word: &str, is_associate: bool) -> Option<&KeyWordItem> {
+        let index = self.search_keyword(word, is_associate, |_, _| {})?;
+        Some(&self.keyword_list[index])
+    }
+
+    /// Find the keyword for `word`, or failing that for the headword
+    /// `MdxBuilder::aliases` maps it to
+    ///
+    /// The flag tells whether the alias was used.
+    pub(crate) fn lookup_keyword_or_alias(&self, word: &str) -> Option<(&KeyWordItem, bool)> {
+        if let Some(item) = self.lookup_keyword_by_word(word, false) {
+            return Some((item, false));
+        }
+        let headword = self.options.aliases.get(word)?;
+        self.lookup_keyword_by_word(headword, false)
+            .map(|item| (item, true))
+    }
+
+    /// Binary search for `word`, calling `on_step` with each probed index
+    /// and how `word` compared to it
+    ///
+    /// Returns the matching index, or with `is_associate` the index where
+    /// the search stopped even without a match.
+    pub(crate) fn search_keyword(
+        &self,
+        word: &str,
+        is_associate: bool,
+        mut on_step: impl FnMut(usize, std::cmp::Ordering),
+    ) -> Option<usize> {
         let list = &self.keyword_list;
         if list.is_empty() {
             return None;
@@ -643,6 +1349,7 @@ impl MdictBase {
             mid = left + (right - left) / 2;
 
             let cmp_result = self.compare_keys(word, &list[mid].key_text);
+            on_step(mid, cmp_result);
 
             match cmp_result {
                 std::cmp::Ordering::Greater => {
@@ -667,104 +1374,322 @@ impl MdictBase {
             return None;
         }
 
-        Some(&list[mid])
+        Some(mid)
     }
 
     /// Find record block index by record start offset
+    ///
+    /// Returns the last block starting at or before `record_start`, so an
+    /// offset equal to a block's first byte maps to that block rather than
+    /// the previous one. Empty blocks share their start with the next block
+    /// and are passed over.
     pub fn find_record_block_index(&self, record_start: u64) -> usize {
-        let mut left = 0;
-        let mut right = self.record_info_list.len();
+        self.record_info_list
+            .partition_point(|info| info.unpack_accumulate_offset <= record_start)
+            .saturating_sub(1)
+    }
 
-        while left < right {
-            let mid = left + (right - left) / 2;
-            if record_start >= self.record_info_list[mid].unpack_accumulate_offset {
-                left = mid + 1;
-            } else {
-                right = mid;
-            }
-        }
+    /// Lookup record by keyword item
+    pub fn lookup_record_by_keyword(&self, item: &KeyWordItem) -> Result<Vec<u8>> {
+        let record_block_index = self.record_block_for(item)?;
+        let unpacked_buffer = self.read_record_block(record_block_index)?;
 
-        if left > 0 {
-            left - 1
-        } else {
-            0
+        Ok(self
+            .slice_record(&unpacked_buffer, record_block_index, item)
+            .to_vec())
+    }
+
+    /// Decoded definition of `item`, decoding straight from the record block
+    /// without copying the record out first
+    pub(crate) fn definition_of(&self, item: &KeyWordItem) -> Result<String> {
+        let record_block_index = self.record_block_for(item)?;
+        let unpacked_buffer = self.read_record_block(record_block_index)?;
+        Ok(self.decode_definition(self.slice_record(&unpacked_buffer, record_block_index, item)))
+    }
+
+    /// Index of the record block holding `item`, checking that its offset
+    /// lies inside the record data
+    pub(crate) fn record_block_for(&self, item: &KeyWordItem) -> Result<usize> {
+        let last = self
+            .record_info_list
+            .last()
+            .ok_or(MdictError::NoRecordBlocks)?;
+        if item.record_start_offset >= last.unpack_accumulate_offset + last.unpack_size {
+            return Err(MdictError::KeyNotFound(item.key_text.clone()));
         }
+
+        Ok(self.find_record_block_index(item.record_start_offset))
     }
 
-    /// Lookup record by keyword item
-    pub fn lookup_record_by_keyword(&mut self, item: &KeyWordItem) -> Result<Vec<u8>> {
-        let record_block_index = self.find_record_block_index(item.record_start_offset);
+    /// Decompressed size of a keyword's record, from its offsets alone
+    ///
+    /// The final record has no end offset and runs to the end of the record
+    /// data.
+    pub fn record_len(&self, item: &KeyWordItem) -> u64 {
+        item.record_len().unwrap_or_else(|| {
+            self.record_info_list
+                .last()
+                .map(|info| info.unpack_accumulate_offset + info.unpack_size)
+                .unwrap_or(0)
+                .saturating_sub(item.record_start_offset)
+        })
+    }
+
+    /// Read and decompress the record block at `index`
+    pub fn read_record_block(&self, index: usize) -> Result<Arc<Vec<u8>>> {
+        let (record_buffer, unpack_size) = self.read_packed_record_block(index)?;
+        self.decompress_record_block(&record_buffer, unpack_size)
+    }
+
+    /// Compression of every key block followed by every record block, as
+    /// named by the tag in each block's preamble
+    ///
+    /// Only the 4-byte tags are read. `None` stands for a tag that is
+    /// unknown or can't be read.
+    pub(crate) fn block_compressions(&self) -> impl Iterator<Item = Option<CompressionType>> + '_ {
+        let keys = self
+            .key_info_list
+            .iter()
+            .map(|info| self.key_block_info_end_offset + info.key_block_pack_accumulator);
+        let records = self
+            .record_info_list
+            .iter()
+            .map(|info| self.record_block_start_offset + info.pack_accumulate_offset);
+        keys.chain(records).map(|offset| {
+            let tag = self.read_buffer(offset, 4).ok()?;
+            CompressionType::from_bytes(&tag)
+        })
+    }
+
+    /// Read the still compressed record block at `index`, along with its
+    /// declared decompressed size
+    pub(crate) fn read_packed_record_block(&self, index: usize) -> Result<(Vec<u8>, usize)> {
+        if self.record_info_list.is_empty() {
+            return Err(MdictError::NoRecordBlocks);
+        }
+        if index >= self.record_info_list.len() {
+            return Err(MdictError::RecordBlockOutOfRange(index));
+        }
 
         // Copy needed values to avoid borrowing issues
-        let pack_accumulate_offset =
-            self.record_info_list[record_block_index].pack_accumulate_offset;
-        let pack_size = self.record_info_list[record_block_index].pack_size as usize;
-        let unpack_size = self.record_info_list[record_block_index].unpack_size as usize;
-        let unpack_accumulate_offset =
-            self.record_info_list[record_block_index].unpack_accumulate_offset;
+        let pack_accumulate_offset = self.record_info_list[index].pack_accumulate_offset;
+        let pack_size = self.record_info_list[index].pack_size as usize;
+        let unpack_size = self.record_info_list[index].unpack_size as usize;
 
         // Read compressed record block
         let offset = self.record_block_start_offset + pack_accumulate_offset;
         let record_buffer = self.read_buffer(offset, pack_size)?;
 
-        // Decompress record block
-        let unpacked_buffer = self.decompress_record_block(&record_buffer, unpack_size)?;
+        Ok((record_buffer, unpack_size))
+    }
+
+    /// Slice a keyword's record out of its decompressed record block
+    pub fn slice_record<'a>(
+        &self,
+        unpacked_buffer: &'a [u8],
+        record_block_index: usize,
+        item: &KeyWordItem,
+    ) -> &'a [u8] {
+        &unpacked_buffer[self.record_range(unpacked_buffer.len(), record_block_index, item)]
+    }
 
-        // Calculate relative offsets
-        let start = (item.record_start_offset - unpack_accumulate_offset) as usize;
+    /// Byte range of a keyword's record within its decompressed record
+    /// block of `block_len` bytes
+    ///
+    /// An item whose offsets fall before the block's start, from a corrupt
+    /// index or a mismatched block, gets an empty range.
+    pub(crate) fn record_range(
+        &self,
+        block_len: usize,
+        record_block_index: usize,
+        item: &KeyWordItem,
+    ) -> Range<usize> {
+        let unpack_accumulate_offset =
+            self.record_info_list[record_block_index].unpack_accumulate_offset;
+
+        // Calculate relative offsets, shifted by any configured fixup
+        let fixup = self.options.offset_fixup;
+        let Some(start) = item
+            .record_start_offset
+            .checked_sub(unpack_accumulate_offset)
+        else {
+            return 0..0;
+        };
+        let start = shift(start, fixup);
         let end = if item.record_end_offset > 0 {
-            (item.record_end_offset - unpack_accumulate_offset) as usize
+            match item.record_end_offset.checked_sub(unpack_accumulate_offset) {
+                Some(end) => shift(end, fixup),
+                None => return 0..0,
+            }
         } else {
-            unpacked_buffer.len()
+            block_len
         };
 
-        Ok(unpacked_buffer[start..end.min(unpacked_buffer.len())].to_vec())
+        let end = end.min(block_len);
+        start.min(end)..end
     }
 
-    /// Decompress record block
-    fn decompress_record_block(&self, record_buffer: &[u8], unpack_size: usize) -> Result<Vec<u8>> {
-        let comp_type = CompressionType::from_bytes(record_buffer).ok_or_else(|| {
-            MdictError::InvalidCompressionType(u32::from_le_bytes([
-                record_buffer[0],
-                record_buffer[1],
-                record_buffer[2],
-                record_buffer[3],
-            ]))
-        })?;
+    /// Group keyword items by record block so each block only needs to be
+    /// decompressed once. Groups are ordered by block index and items within
+    /// a group by record offset.
+    pub fn group_by_record_block<'a, I>(&self, items: I) -> Vec<(usize, Vec<KeyWordItem>)>
+    where
+        I: IntoIterator<Item = &'a KeyWordItem>,
+    {
+        let mut sorted: Vec<KeyWordItem> = items.into_iter().cloned().collect();
+        sorted.sort_by_key(|item| item.record_start_offset);
+
+        let mut groups: Vec<(usize, Vec<KeyWordItem>)> = Vec::new();
+        for item in sorted {
+            let block_idx = self.find_record_block_index(item.record_start_offset);
+            match groups.last_mut() {
+                Some((idx, group)) if *idx == block_idx => group.push(item),
+                _ => groups.push((block_idx, vec![item])),
+            }
+        }
+        groups
+    }
 
-        match comp_type {
-            CompressionType::None => Ok(record_buffer[8..].to_vec()),
-            CompressionType::Lzo => {
-                let data = if self.meta.encrypt == EncryptType::RecordBlock {
-                    utils::mdx_decrypt(record_buffer)
-                } else {
-                    record_buffer.to_vec()
-                };
-                lzo::decompress(&data[8..], unpack_size)
+    /// Like [`Self::group_by_record_block`], but returns indices into `items`
+    /// instead of cloned items
+    pub fn group_indices_by_record_block(&self, items: &[KeyWordItem]) -> Vec<(usize, Vec<usize>)> {
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        order.sort_by_key(|&i| items[i].record_start_offset);
+
+        let mut groups: Vec<(usize, Vec<usize>)> = Vec::new();
+        for i in order {
+            let block_idx = self.find_record_block_index(items[i].record_start_offset);
+            match groups.last_mut() {
+                Some((idx, group)) if *idx == block_idx => group.push(i),
+                _ => groups.push((block_idx, vec![i])),
             }
-            CompressionType::Zlib => {
-                let data = if self.meta.encrypt == EncryptType::RecordBlock {
-                    utils::mdx_decrypt(record_buffer)
-                } else {
-                    record_buffer.to_vec()
-                };
-                let mut decoder = ZlibDecoder::new(&data[8..]);
-                let mut decompressed = Vec::new();
-                decoder.read_to_end(&mut decompressed)?;
-                Ok(decompressed)
+        }
+        groups
+    }
+
+    /// Decompress record block
+    pub(crate) fn decompress_record_block(
+        &self,
+        record_buffer: &[u8],
+        unpack_size: usize,
+    ) -> Result<Arc<Vec<u8>>> {
+        self.decompress_record_block_cached(record_buffer, unpack_size)
+            .map(|(block, _)| block)
+    }
+
+    /// [`Self::decompress_record_block`] through the block cache, if one
+    /// is installed, also telling whether the result was cached
+    pub(crate) fn decompress_record_block_cached(
+        &self,
+        record_buffer: &[u8],
+        unpack_size: usize,
+    ) -> Result<(Arc<Vec<u8>>, bool)> {
+        match &self.options.block_cache {
+            Some(cache) => {
+                // A hit must not get around the size limit
+                self.check_block_size(unpack_size)?;
+                let decoder = self.options.decompressor_id;
+                cache.get_or_insert(record_buffer, unpack_size, decoder, || {
+                    self.decompress_record_block_uncached(record_buffer, unpack_size)
+                })
             }
+            None => self
+                .decompress_record_block_uncached(record_buffer, unpack_size)
+                .map(|block| (Arc::new(block), false)),
+        }
+    }
+
+    fn decompress_record_block_uncached(
+        &self,
+        record_buffer: &[u8],
+        unpack_size: usize,
+    ) -> Result<Vec<u8>> {
+        let tag = block_tag(record_buffer)?;
+        self.check_block_size(unpack_size)?;
+        self.options
+            .decompressor
+            .decompress(tag, &record_buffer[8..], unpack_size)
+    }
+
+    /// Fail with `BlockTooLarge` before decompressing a block that declares
+    /// more than the configured maximum
+    fn check_block_size(&self, unpack_size: usize) -> Result<()> {
+        let limit = self.options.max_block_size;
+        if unpack_size > limit {
+            return Err(MdictError::BlockTooLarge {
+                size: unpack_size,
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decode definition bytes, falling back to lossy UTF-8
+    pub fn decode_definition(&self, def_bytes: &[u8]) -> String {
+        self.decode_definition_checked(def_bytes).0
+    }
+
+    /// Decode definition bytes like [`Self::decode_definition`], also
+    /// returning the encoding used and whether the lossy fallback was taken
+    pub(crate) fn decode_definition_checked(&self, def_bytes: &[u8]) -> (String, Encoding, bool) {
+        match decode_string(def_bytes, self.meta.encoding) {
+            Ok(text) => (text, self.meta.encoding, false),
+            Err(_) => (
+                String::from_utf8_lossy(def_bytes).to_string(),
+                Encoding::Utf8,
+                true,
+            ),
         }
     }
 
+    /// Every keyword that compares equal to `word` after stripping
+    ///
+    /// Unlike [`Self::lookup_keyword_by_word`], which returns one of them,
+    /// this returns the whole run, e.g. both `\a.png` and `\a.jpg` for MDD
+    /// keys, whose extension is stripped.
+    pub fn keywords_equal_to(&self, word: &str) -> &[KeyWordItem] {
+        let stripped = self.strip(word);
+        let start = self
+            .keyword_list
+            .partition_point(|item| self.strip(&item.key_text) < stripped);
+        let len = self.keyword_list[start..]
+            .iter()
+            .take_while(|item| self.strip(&item.key_text) == stripped)
+            .count();
+        &self.keyword_list[start..start + len]
+    }
+
+    /// Whether any keyword starts with `prefix`
+    ///
+    /// The keyword list is sorted by stripped key, so this is a binary
+    /// search for the first key not below the prefix. Keys are compared
+    /// through [`Self::stripped_chars`], so nothing is allocated. That
+    /// lowercases per character, so in a case-insensitive dictionary whose
+    /// keys hold an uppercase final `Σ` this can disagree with
+    /// [`Self::get_prefix_keywords`]; [`Self::first_unsorted_index`] finds
+    /// such keys.
+    pub fn has_prefix(&self, prefix: &str) -> bool {
+        let stripped_prefix = self.stripped_chars(prefix);
+        let index = self.keyword_list.partition_point(|item| {
+            self.stripped_chars(&item.key_text)
+                .lt(stripped_prefix.clone())
+        });
+
+        self.keyword_list.get(index).is_some_and(|item| {
+            let mut key = self.stripped_chars(&item.key_text);
+            stripped_prefix.clone().all(|c| key.next() == Some(c))
+        })
+    }
+
     /// Get keywords that start with the given prefix
+    ///
+    /// Both the prefix and the keys are normalized with [`Self::strip`], so
+    /// `KeyCaseSensitive`/`StripKey` apply exactly as they do for lookup.
     pub fn get_prefix_keywords(&self, prefix: &str) -> Vec<&KeyWordItem> {
+        let stripped_prefix = self.strip(prefix);
         self.keyword_list
             .iter()
-            .filter(|item| {
-                item.key_text
-                    .to_lowercase()
-                    .starts_with(&prefix.to_lowercase())
-            })
+            .filter(|item| self.strip(&item.key_text).starts_with(&stripped_prefix))
             .collect()
     }
 
@@ -781,3 +1706,107 @@ impl MdictBase {
         }
     }
 }
+
+/// Read exactly `buf.len()` bytes at `offset` without moving a shared cursor
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Read exactly `buf.len()` bytes at `offset` without moving a shared cursor
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Fallback for platforms without positional reads; not safe to call
+/// concurrently on the same file
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+/// Read the compression tag from a block's 8-byte preamble
+fn block_tag(block: &[u8]) -> Result<u32> {
+    if block.len() < 8 {
+        return Err(MdictError::DecompressionError(format!(
+            "block of {} bytes is shorter than its 8-byte preamble",
+            block.len()
+        )));
+    }
+    Ok(u32::from_le_bytes([block[0], block[1], block[2], block[3]]))
+}
+
+/// Add a signed `fixup` to a block-relative offset, clamping at zero
+fn shift(offset: u64, fixup: i64) -> usize {
+    offset.saturating_add_signed(fixup) as usize
+}
+
+/// Position of the first NUL text unit of `width` bytes in `buf` at or
+/// after `from`, stepping in whole units from `from`
+///
+/// The hot loop of key block splitting, so units are compared a chunk at
+/// a time rather than indexed byte by byte. A trailing odd byte of a
+/// UTF-16 block is never a terminator.
+fn find_terminator(buf: &[u8], from: usize, width: usize) -> Option<usize> {
+    let rest = buf.get(from..)?;
+    let pos = if width == 2 {
+        rest.chunks_exact(2).position(|unit| unit == [0, 0])? * 2
+    } else {
+        rest.iter().position(|&b| b == 0)?
+    };
+    Some(from + pos)
+}
+
+/// Number of keys in `key_block` when each ends at its first terminator
+///
+/// Bytes left over after the last terminator count as one more key, so a
+/// block split in the wrong places rarely comes out at the declared count.
+fn count_terminated_keys(key_block: &[u8], num_width: usize, width: usize) -> usize {
+    let mut count = 0;
+    let mut pos = 0;
+    while pos < key_block.len() {
+        count += 1;
+        match find_terminator(key_block, pos + num_width, width) {
+            Some(end) => pos = end + width,
+            None => break,
+        }
+    }
+    count
+}
+
+/// The `len` bytes of `buf` at `*offset`, advancing the offset past them
+///
+/// Lengths read from a damaged file, or one opened as the wrong type, can
+/// point past the end of the buffer; that is an error rather than a panic.
+fn take<'a>(buf: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let bytes = offset
+        .checked_add(len)
+        .and_then(|end| buf.get(*offset..end))
+        .ok_or_else(|| {
+            MdictError::InvalidFormat(format!(
+                "Key block info: {} bytes at offset {} run past its end at {}",
+                len,
+                offset,
+                buf.len()
+            ))
+        })?;
+    *offset += len;
+    Ok(bytes)
+}
@@ -2,34 +2,289 @@
 //!
 //! This module provides the MDX dictionary parsing and querying functionality.
 
+use flate2::Crc;
+use regex::RegexBuilder;
+use std::ops::Range;
 use std::path::Path;
+use std::sync::Arc;
 
-use crate::error::Result;
+use crate::builder::MdxBuilder;
+use crate::cache::{DefinitionCache, LruCache};
+use crate::dictionary::MdictFile;
+use crate::entries::MdxEntries;
+use crate::error::{MdictError, Result};
+use crate::incremental::IncrementalMdx;
+use crate::mdd::Mdd;
 use crate::mdict_base::MdictBase;
+use crate::shared::SharedMdx;
+use crate::stardict::StarDictWriter;
 use crate::types::*;
-use crate::utils::{decode_string, levenshtein_distance, strip_key};
+use crate::utils::{
+    fold_diacritics, glob_to_regex, html_to_text, levenshtein_distance, rewrite_resource_refs,
+    strip_wrapper_tags, truncate_html, truncate_text,
+};
+use crate::verify::BlockError;
+
+/// Keys tried by [`Mdx::about_entry`] before the dictionary title
+const ABOUT_KEYS: &[&str] = &["about", "_about", "00-about"];
 
 /// MDX dictionary parser
 pub struct Mdx {
     /// Base parser
-    base: MdictBase,
+    pub(crate) base: MdictBase,
+    /// Definitions kept by [`Self::cached_lookup`], if enabled
+    definitions: Option<DefinitionCache>,
+    /// Results of [`Self::lookup`] by stripped query, if enabled
+    queries: Option<LruCache<String, LookupResult>>,
 }
 
 impl Mdx {
     /// Create a new MDX parser from file path
+    ///
+    /// Fails with `InvalidFormat` if the header has a `<Library_Data>` root
+    /// element, as only an MDD header would.
     pub fn new<P: AsRef<Path>>(filepath: P) -> Result<Self> {
         let base = MdictBase::new(filepath, FileExt::Mdx)?;
-        Ok(Mdx { base })
+        Ok(Self::from_base(base))
+    }
+
+    /// Open an MDX and return it with its [`DictSummary`]
+    ///
+    /// For apps that show a dictionary's metadata before deciding to use
+    /// it; equivalent to [`Self::new`] followed by [`Self::summary`].
+    pub fn open<P: AsRef<Path>>(filepath: P) -> Result<(Self, DictSummary)> {
+        let mdx = Self::new(filepath)?;
+        let summary = mdx.summary();
+        Ok((mdx, summary))
+    }
+
+    /// Open an MDX held in memory, sharing `data` instead of copying it
+    ///
+    /// Any number of dictionaries can be opened over clones of one buffer.
+    /// [`Self::filepath`] is empty, so there are no sibling CSS/JS files.
+    pub fn from_shared(data: Arc<[u8]>) -> Result<Self> {
+        let base = MdictBase::from_shared(data, FileExt::Mdx)?;
+        Ok(Self::from_base(base))
+    }
+
+    /// Open an MDX, choosing between UTF-8, GB18030 and Big5 from sampled
+    /// record blocks instead of trusting the header
+    ///
+    /// See [`MdxBuilder::detect_cjk_encoding`].
+    #[cfg(feature = "cjk-detect")]
+    pub fn open_with_cjk_detection<P: AsRef<Path>>(filepath: P) -> Result<Self> {
+        MdxBuilder::new().detect_cjk_encoding(true).open(filepath)
+    }
+
+    /// Open an MDX whose key blocks are read one at a time
+    ///
+    /// See [`IncrementalMdx`].
+    pub fn open_incremental<P: AsRef<Path>>(filepath: P) -> Result<IncrementalMdx> {
+        MdxBuilder::new().open_incremental(filepath)
+    }
+
+    /// Create a builder for opening an MDX with custom options
+    pub fn builder() -> MdxBuilder {
+        MdxBuilder::new()
+    }
+
+    /// Open an MDX for concurrent read-only use
+    ///
+    /// See [`SharedMdx`] for the threading model.
+    pub fn open_readonly_shared<P: AsRef<Path>>(filepath: P) -> Result<SharedMdx> {
+        Ok(Self::new(filepath)?.into_shared())
+    }
+
+    /// Convert into a [`SharedMdx`] that can be queried from many threads
+    pub fn into_shared(self) -> SharedMdx {
+        SharedMdx::from_base(self.base)
+    }
+
+    /// Consume the dictionary and iterate over every `(key, definition)`
+    ///
+    /// See [`MdxEntries`].
+    pub fn into_entries(self) -> MdxEntries {
+        MdxEntries::new(self.base)
+    }
+
+    /// Wrap an already parsed base
+    pub(crate) fn from_base(base: MdictBase) -> Self {
+        let definitions = match base.definition_cache_entries() {
+            0 => None,
+            entries => Some(DefinitionCache::new(entries)),
+        };
+        let queries = match base.query_cache_entries() {
+            0 => None,
+            entries => Some(LruCache::new(entries)),
+        };
+        Mdx {
+            base,
+            definitions,
+            queries,
+        }
+    }
+
+    /// Decode the dictionary with `encoding` instead of the one it was
+    /// opened with
+    ///
+    /// For fixing mojibake from a wrong `Encoding` header without reopening
+    /// the file. Every key block is read and decoded again and the keyword
+    /// list re-sorted, so this costs about as much as opening; cached
+    /// definitions and lookup results are dropped. Switching between UTF-16
+    /// and a byte encoding changes how keys are terminated and usually
+    /// fails. On failure the previous encoding and keywords are kept.
+    pub fn set_encoding(&mut self, encoding: Encoding) -> Result<()> {
+        self.base.set_encoding(encoding)?;
+        if let Some(definitions) = &mut self.definitions {
+            definitions.clear();
+        }
+        self.clear_query_cache();
+        Ok(())
     }
 
     /// Get dictionary header
     pub fn header(&self) -> &DictHeader {
-        &self.base.header
+        MdictFile::header(self)
     }
 
     /// Get dictionary metadata
     pub fn meta(&self) -> &DictMeta {
-        &self.base.meta
+        MdictFile::meta(self)
+    }
+
+    /// Width of the file's size and offset fields: 32-bit for v1.x files,
+    /// 64-bit for v2.0+
+    pub fn num_format(&self) -> NumFmt {
+        self.base.meta.num_fmt
+    }
+
+    /// Get the raw header bytes, their length and stored checksum
+    pub fn header_meta(&self) -> &HeaderMeta {
+        &self.base.header_meta
+    }
+
+    /// Title, description, format and size of the dictionary in one struct
+    pub fn summary(&self) -> DictSummary {
+        let meta = &self.base.meta;
+        DictSummary {
+            title: self.title().map(str::to_string),
+            description: self.about_html(),
+            version: meta.version,
+            encoding: meta.encoding,
+            encrypt: meta.encrypt,
+            keyword_count: self.base.keyword_list.len(),
+            key_block_count: self.base.key_info_list.len(),
+            record_block_count: self.base.record_info_list.len(),
+            record_data_size: self
+                .base
+                .record_info_list
+                .last()
+                .map_or(0, |info| info.unpack_accumulate_offset + info.unpack_size),
+        }
+    }
+
+    /// Get a header attribute, e.g. `header_str("Encoding")`
+    pub fn header_str(&self, key: &str) -> Option<&str> {
+        self.base.header_str(key)
+    }
+
+    /// Get a header flag such as `KeyCaseSensitive` as a bool
+    ///
+    /// `Yes`/`No`, `true`/`false` and `1`/`0` are recognized in any case;
+    /// a missing or other value gives `default`.
+    pub fn header_bool(&self, key: &str, default: bool) -> bool {
+        self.base.header_bool(key, default)
+    }
+
+    /// Get the dictionary title from the header
+    pub fn title(&self) -> Option<&str> {
+        self.header_str("Title")
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Get the dictionary's about page HTML from the header `Description`
+    ///
+    /// Entities are decoded once while parsing the header, the same as for
+    /// every other attribute.
+    pub fn about_html(&self) -> Option<String> {
+        let description = self.base.header.get("Description")?.trim();
+        (!description.is_empty()).then(|| description.to_string())
+    }
+
+    /// Get the about page stored as an entry rather than in the header
+    ///
+    /// Some packers keep it under a key such as `about` or `00-about`, or
+    /// under the dictionary title; those keys are tried in that order,
+    /// ignoring case.
+    pub fn about_entry(&mut self) -> Option<String> {
+        let mut keys: Vec<String> = ABOUT_KEYS.iter().map(|k| k.to_string()).collect();
+        keys.extend(self.title().map(str::to_string));
+
+        let item = keys.iter().find_map(|key| {
+            self.base
+                .keywords_equal_to(key)
+                .iter()
+                .find(|item| item.key_text.eq_ignore_ascii_case(key))
+                .cloned()
+        })?;
+        let content = self.fetch(&item)?.definition;
+        let content = content.trim_end_matches('\0');
+        (!content.trim().is_empty()).then(|| content.to_string())
+    }
+
+    /// Get the stylesheet meant to be injected into every definition
+    ///
+    /// MDict itself reserves no key for this. Viewers such as GoldenDict
+    /// load `<name>.css` for `<name>.mdx`, and some packers store it in the
+    /// dictionary under that key instead of shipping it alongside. The
+    /// entry is tried first, then the file next to the dictionary if there
+    /// is no such entry or it can't be read.
+    pub fn global_css(&mut self) -> Option<String> {
+        self.companion_asset("css")
+    }
+
+    /// Get the script meant to be injected into every definition, found
+    /// as `<name>.js` the same way as [`Self::global_css`]
+    pub fn global_js(&mut self) -> Option<String> {
+        self.companion_asset("js")
+    }
+
+    /// Content of the entry or sibling file named after the dictionary
+    /// with extension `ext`
+    fn companion_asset(&mut self, ext: &str) -> Option<String> {
+        let path = Path::new(&self.base.filepath).to_path_buf();
+        let name = format!("{}.{}", path.file_stem()?.to_str()?, ext);
+
+        let item = self
+            .base
+            .keywords_equal_to(&name)
+            .iter()
+            .find(|item| item.key_text.eq_ignore_ascii_case(&name))
+            .cloned();
+        // An entry that fails to decode falls back to the file as well
+        let content = match item.and_then(|item| self.fetch(&item)) {
+            Some(entry) => entry.definition,
+            None => std::fs::read_to_string(path.with_extension(ext)).ok()?,
+        };
+
+        let content = content.trim_end_matches('\0');
+        (!content.trim().is_empty()).then(|| content.to_string())
+    }
+
+    /// Number of keywords recovered from corrupt UTF-16 key blocks
+    ///
+    /// See [`Mdd::key_decode_warnings`](crate::Mdd::key_decode_warnings).
+    pub fn key_decode_warnings(&self) -> usize {
+        self.base.key_decode_warnings
+    }
+
+    /// Key blocks whose declared entry count disagrees with the keys found
+    /// at each terminator
+    ///
+    /// See [`Mdd::key_count_mismatches`](crate::Mdd::key_count_mismatches).
+    pub fn key_count_mismatches(&self) -> &[KeyCountMismatch] {
+        &self.base.key_count_mismatches
     }
 
     /// Get total number of keywords
@@ -46,50 +301,397 @@ impl Mdx {
             .collect()
     }
 
+    /// Get each distinct keyword once, in index order
+    ///
+    /// Keywords are distinct if they differ after stripping (see
+    /// `StripKey`/`KeyCaseSensitive`); of several equal ones the first
+    /// stored form is kept.
+    pub fn unique_keys(&self) -> Vec<&str> {
+        let mut keys = Vec::new();
+        let mut previous: Option<String> = None;
+        for item in &self.base.keyword_list {
+            let stripped = self.base.strip(&item.key_text);
+            if previous.as_ref() != Some(&stripped) {
+                keys.push(item.key_text.as_str());
+                previous = Some(stripped);
+            }
+        }
+        keys
+    }
+
+    /// Iterate over the keywords from `word` on, in index order
+    ///
+    /// Starts at the first keyword that doesn't sort before `word`, which
+    /// need not exist: a batch job can checkpoint the last key it finished
+    /// and resume with `keys_from(checkpoint).skip(1)`, as long as keys are
+    /// distinct after stripping.
+    pub fn keys_from(&self, word: &str) -> impl Iterator<Item = &str> {
+        let base = &self.base;
+        let stripped = base.strip(word);
+        let start = base
+            .keyword_list
+            .partition_point(|item| base.strip(&item.key_text) < stripped);
+        base.keyword_list[start..]
+            .iter()
+            .map(|item| item.key_text.as_str())
+    }
+
+    /// Find `word`, along with the entry a word list should scroll to
+    ///
+    /// The nearest entry is the first keyword that doesn't sort before
+    /// `word`, or the last keyword if `word` sorts after all of them. The
+    /// exact match, if any, is that same entry. `None` for an empty
+    /// dictionary.
+    pub fn lookup_or_nearest(&self, word: &str) -> Option<(Option<&KeyWordItem>, &KeyWordItem)> {
+        let base = &self.base;
+        let stripped = base.strip(word);
+        let list = &base.keyword_list;
+        let index = list
+            .partition_point(|item| base.strip(&item.key_text) < stripped)
+            .min(list.len().checked_sub(1)?);
+        let nearest = &list[index];
+        let exact = (base.strip(&nearest.key_text) == stripped).then_some(nearest);
+        Some((exact, nearest))
+    }
+
+    /// Get each distinct first character of the stripped keywords with
+    /// its keyword count and the index of its first keyword
+    ///
+    /// Made in one pass over the sorted keyword list, for an A–Z (or
+    /// radical) navigation strip. Keywords that strip to nothing are
+    /// skipped.
+    pub fn initial_letters(&self) -> Vec<(char, usize, usize)> {
+        let mut letters: Vec<(char, usize, usize)> = Vec::new();
+        for (index, item) in self.base.keyword_list.iter().enumerate() {
+            let Some(first) = self.base.strip(&item.key_text).chars().next() else {
+                continue;
+            };
+            match letters.last_mut() {
+                Some((letter, count, _)) if *letter == first => *count += 1,
+                _ => letters.push((first, 1, index)),
+            }
+        }
+        letters
+    }
+
+    /// Find keywords matching a shell-style glob such as `*able` or
+    /// `un*ing`, returning at most `limit`
+    ///
+    /// `*` matches any run of characters and `?` any single character. The
+    /// whole key must match. Matching ignores case unless the dictionary is
+    /// `KeyCaseSensitive`.
+    pub fn search_keys_glob(&self, pattern: &str, limit: usize) -> Vec<String> {
+        let case_sensitive = self.base.meta.key_case_sensitive;
+        let regex = match RegexBuilder::new(&glob_to_regex(pattern))
+            .case_insensitive(!case_sensitive)
+            .build()
+        {
+            Ok(regex) => regex,
+            Err(_) => return Vec::new(),
+        };
+
+        self.filter_keys(|key| regex.is_match(key))
+            .take(limit)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Iterate over keywords for which `pred` returns true, in index order
+    pub fn filter_keys<'a>(
+        &'a self,
+        pred: impl Fn(&str) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.base
+            .keyword_list
+            .iter()
+            .map(|k| k.key_text.as_str())
+            .filter(move |key| pred(key))
+    }
+
     /// Lookup a word and return its definition
+    ///
+    /// With [`MdxBuilder::query_cache`], results are kept by stripped query,
+    /// so looking up `Hello` after `hello` doesn't touch the file. Alias
+    /// hits match the word exactly as given, so they aren't cached.
     pub fn lookup(&mut self, word: &str) -> Option<LookupResult> {
-        // Find keyword in the list
-        let keyword_item = self.base.lookup_keyword_by_word(word, false)?;
-        let keyword_item = keyword_item.clone();
+        let Some(queries) = &mut self.queries else {
+            return self.lookup_uncached(word);
+        };
+        let key = self.base.strip(word);
+        if let Some(result) = queries.get(&key) {
+            return Some(result);
+        }
+        let result = self.lookup_uncached(word)?;
+        // A direct hit strips to the query; anything else came from an alias
+        if self.base.strip(&result.key_text) == key {
+            if let Some(queries) = &mut self.queries {
+                queries.insert(key, result.clone());
+            }
+        }
+        Some(result)
+    }
+
+    /// Drop the results kept by [`MdxBuilder::query_cache`]
+    pub fn clear_query_cache(&mut self) {
+        if let Some(queries) = &mut self.queries {
+            queries.clear();
+        }
+    }
 
-        // Get the definition
-        let def_bytes = self.base.lookup_record_by_keyword(&keyword_item).ok()?;
+    fn lookup_uncached(&mut self, word: &str) -> Option<LookupResult> {
+        #[cfg(feature = "metrics")]
+        if let Some(hook) = self.base.lookup_hook() {
+            return self.base.lookup_timed(word, hook);
+        }
 
-        // Decode the definition
-        let definition = decode_string(&def_bytes, self.base.meta.encoding)
-            .unwrap_or_else(|_| String::from_utf8_lossy(&def_bytes).to_string());
+        // Find keyword in the list, or the headword it's an alias of
+        let (keyword_item, _) = self.base.lookup_keyword_or_alias(word)?;
 
         Some(LookupResult {
+            definition: self.base.definition_of(keyword_item).ok()?,
             key_text: keyword_item.key_text.clone(),
+        })
+    }
+
+    /// Look up a word, sharing the decoded definition with earlier lookups
+    /// of the same record
+    ///
+    /// With [`MdxBuilder::cache_definitions`], keys pointing at one record
+    /// (duplicate headwords, say) and repeated lookups return clones of a
+    /// single `Arc<str>`. Without it, each call decodes afresh.
+    pub fn cached_lookup(&mut self, word: &str) -> Option<Arc<str>> {
+        let base = &self.base;
+        let item = base.lookup_keyword_or_alias(word)?.0;
+        let decode = || base.definition_of(item).ok().map(Arc::from);
+        match &mut self.definitions {
+            Some(cache) => cache.get_or_insert(item.record_start_offset, decode),
+            None => decode(),
+        }
+    }
+
+    /// Look up a word and inline the resources its definition references
+    ///
+    /// Every `src` attribute and CSS `url(...)` that [`Mdd::resolve`]
+    /// finds in `mdd` is replaced with a `data:` URI, giving HTML that
+    /// renders without the MDD. References that are already `data:` URIs,
+    /// point to the web or aren't in `mdd` are left as they are.
+    pub fn lookup_inlined(&mut self, word: &str, mdd: &mut Mdd) -> Option<String> {
+        let definition = self.lookup(word)?.definition;
+        Some(inline_resources(&definition, std::slice::from_mut(mdd)))
+    }
+
+    /// Look up a word and drop the wrapper tags that need the MDict runtime
+    ///
+    /// Each `<link>` to a style sheet stored in `mdd` (one whose `rel` is
+    /// `stylesheet` or whose path ends in `.css`) is replaced by a
+    /// `<style>` element holding it; other `<link>`s and `<script>`s that
+    /// load internal files, such as icons and fonts, are removed. Use
+    /// [`rewrite_internal_links`](crate::rewrite_internal_links)
+    /// afterwards to point the remaining references at a server.
+    pub fn lookup_standalone(&mut self, word: &str, mut mdd: Option<&mut Mdd>) -> Option<String> {
+        let definition = self.lookup(word)?.definition;
+        Some(strip_wrapper_tags(&definition, |path| {
+            let mdd = mdd.as_deref_mut()?;
+            let key = mdd.resolve(path)?.to_string();
+            let sheet = mdd.locate_raw(&key)?;
+            Some(String::from_utf8_lossy(&sheet).into_owned())
+        }))
+    }
+
+    /// Look up a word with progressively looser matching, and report which
+    /// kind of match was made
+    ///
+    /// An identical key is preferred over one that only matches after
+    /// stripping, then over the headword of an alias registered with
+    /// [`MdxBuilder::aliases`], then over a case-insensitive match. The last
+    /// step scans every keyword, so it only runs when the others find
+    /// nothing, and only in `KeyCaseSensitive` dictionaries: otherwise
+    /// stripping already folds case.
+    pub fn lookup_with_fallback_strip(&mut self, word: &str) -> Option<(LookupResult, MatchKind)> {
+        let run = self.base.keywords_equal_to(word);
+        let found = match run.iter().find(|item| item.key_text == word) {
+            Some(item) => Some((item.clone(), MatchKind::Exact)),
+            None => run.first().map(|item| (item.clone(), MatchKind::Stripped)),
+        };
+        let found = found.or_else(|| match self.base.lookup_keyword_or_alias(word)? {
+            (item, true) => Some((item.clone(), MatchKind::Alias)),
+            (_, false) => None,
+        });
+
+        let (item, kind) = match found {
+            Some(found) => found,
+            None if !self.base.meta.key_case_sensitive => return None,
+            None => {
+                let folded = self.base.strip(word).to_lowercase();
+                let item = self
+                    .base
+                    .keyword_list
+                    .iter()
+                    .find(|item| self.base.strip(&item.key_text).to_lowercase() == folded)?;
+                (item.clone(), MatchKind::CaseFolded)
+            }
+        };
+
+        self.fetch(&item).map(|result| (result, kind))
+    }
+
+    /// Look up a word ignoring diacritics, so `cafe` finds `café`
+    ///
+    /// An exact match is tried first. Otherwise every keyword is folded
+    /// with the query in a linear scan, since the sorted index can't be
+    /// binary searched under a different comparison; expect this to be
+    /// slower than `lookup` on large dictionaries. The first match in index
+    /// order is returned.
+    pub fn lookup_fold_diacritics(&mut self, word: &str) -> Option<LookupResult> {
+        if let Some(result) = self.lookup(word) {
+            return Some(result);
+        }
+
+        let folded = fold_diacritics(&self.base.strip(word));
+        let item = self
+            .base
+            .keyword_list
+            .iter()
+            .find(|item| fold_diacritics(&self.base.strip(&item.key_text)) == folded)?
+            .clone();
+        self.fetch(&item)
+    }
+
+    /// Look up a word ignoring case but not punctuation, so `u.s.a.` finds
+    /// `U.S.A.` but not `USA`
+    ///
+    /// Keys equal to `word` after the usual stripping are checked with a
+    /// binary search. In dictionaries that aren't `KeyCaseSensitive`,
+    /// stripping folds case, so that is the whole cost and a miss is
+    /// final. In `KeyCaseSensitive` ones a miss falls back to lowercasing
+    /// every keyword in a linear scan, costing time proportional to the
+    /// dictionary size. The first match in index order is returned.
+    pub fn lookup_fold_case(&mut self, word: &str) -> Option<LookupResult> {
+        let folded = word.to_lowercase();
+        let matches = |item: &&KeyWordItem| item.key_text.to_lowercase() == folded;
+        let item = match self.base.keywords_equal_to(word).iter().find(matches) {
+            Some(item) => item,
+            None if !self.base.meta.key_case_sensitive => return None,
+            None => self.base.keyword_list.iter().find(matches)?,
+        }
+        .clone();
+        self.fetch(&item)
+    }
+
+    /// Gloss a tokenized sentence, matching the longest dictionary phrase
+    /// at each position
+    ///
+    /// From each token, spans of tokens joined with spaces are looked up
+    /// from the longest (`New York City`) down to the single token (`New`);
+    /// the first hit is returned with its token range and scanning resumes
+    /// after it. Tokens with no entry are skipped. With the usual
+    /// `StripKey`, spaces are ignored, so CJK tokens match unspaced
+    /// headwords too. A sentence of `n` tokens takes up to `n * (n + 1) / 2`
+    /// lookups, so split long texts into sentences first.
+    pub fn gloss(&mut self, tokens: &[&str]) -> Vec<(Range<usize>, LookupResult)> {
+        let mut glosses = Vec::new();
+        let mut start = 0;
+        while start < tokens.len() {
+            let found = (start + 1..=tokens.len())
+                .rev()
+                .find_map(|end| Some((end, self.lookup(&tokens[start..end].join(" "))?)));
+            match found {
+                Some((end, result)) => {
+                    glosses.push((start..end, result));
+                    start = end;
+                }
+                None => start += 1,
+            }
+        }
+        glosses
+    }
+
+    /// Short snippet of a word's definition for search results
+    ///
+    /// The snippet holds at most `max_chars` characters of text, followed
+    /// by `…` if the definition was longer. [`PreviewFormat::Text`] strips
+    /// the markup first; [`PreviewFormat::Html`] keeps it and cuts only
+    /// between tags, closing the elements left open, so the snippet renders
+    /// on its own.
+    pub fn preview(
+        &mut self,
+        word: &str,
+        max_chars: usize,
+        format: PreviewFormat,
+    ) -> Option<String> {
+        let definition = self.lookup(word)?.definition;
+        let definition = definition.trim_end_matches('\0');
+        Some(match format {
+            PreviewFormat::Text => truncate_text(&html_to_text(definition), max_chars),
+            PreviewFormat::Html => truncate_html(definition, max_chars),
+        })
+    }
+
+    /// Look up a word, reporting how its definition was decoded
+    ///
+    /// Fails with `KeyNotFound` if no keyword matches, or with the read
+    /// error if the record can't be read. Entries with `lossy` set point to
+    /// bytes that aren't valid in the dictionary's encoding.
+    pub fn try_lookup(&mut self, word: &str) -> Result<DecodedLookup> {
+        let item = self
+            .base
+            .lookup_keyword_by_word(word, false)
+            .ok_or_else(|| MdictError::KeyNotFound(word.to_string()))?
+            .clone();
+        let def_bytes = self.base.lookup_record_by_keyword(&item)?;
+        let (definition, encoding, lossy) = self.base.decode_definition_checked(&def_bytes);
+
+        Ok(DecodedLookup {
+            key_text: item.key_text,
             definition,
+            encoding,
+            lossy,
         })
     }
 
     /// Find words with the given prefix
     pub fn prefix(&mut self, prefix: &str) -> Vec<LookupResult> {
-        // Clone keywords to avoid borrowing issues
-        let keywords: Vec<KeyWordItem> = self
-            .base
-            .get_prefix_keywords(prefix)
+        let base = &self.base;
+        base.get_prefix_keywords(prefix)
             .into_iter()
-            .cloned()
-            .collect();
-        let mut results = Vec::new();
-        let encoding = self.base.meta.encoding;
-
-        for keyword in keywords {
-            if let Ok(def_bytes) = self.base.lookup_record_by_keyword(&keyword) {
-                let definition = decode_string(&def_bytes, encoding)
-                    .unwrap_or_else(|_| String::from_utf8_lossy(&def_bytes).to_string());
-
-                results.push(LookupResult {
-                    key_text: keyword.key_text,
-                    definition,
-                });
-            }
-        }
+            .filter_map(|keyword| {
+                Some(LookupResult {
+                    definition: base.definition_of(keyword).ok()?,
+                    key_text: keyword.key_text.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Iterate lazily over the words with the given prefix and their
+    /// definitions
+    ///
+    /// Yields the same entries as [`Self::prefix`], but each definition is
+    /// only fetched when reached, so `.take(n)` bounds the work. The most
+    /// recently decompressed record block is kept for following entries in
+    /// the same block. Entries whose record can't be read are skipped.
+    pub fn prefix_iter(&mut self, prefix: &str) -> impl Iterator<Item = LookupResult> + '_ {
+        let base = &self.base;
+        let stripped = base.strip(prefix);
+        let start = base
+            .keyword_list
+            .partition_point(|item| base.strip(&item.key_text) < stripped);
+        let mut current: Option<(usize, Arc<Vec<u8>>)> = None;
 
-        results
+        base.keyword_list[start..]
+            .iter()
+            .take_while(move |item| base.strip(&item.key_text).starts_with(&stripped))
+            .filter_map(move |item| {
+                let block_idx = base.record_block_for(item).ok()?;
+                if current.as_ref().map(|(idx, _)| *idx) != Some(block_idx) {
+                    current = Some((block_idx, base.read_record_block(block_idx).ok()?));
+                }
+                let (_, block) = current.as_ref()?;
+                Some(LookupResult {
+                    key_text: item.key_text.clone(),
+                    definition: base.decode_definition(base.slice_record(block, block_idx, item)),
+                })
+            })
     }
 
     /// Find words with the given prefix (keys only, no definitions)
@@ -101,33 +703,68 @@ impl Mdx {
             .collect()
     }
 
+    /// Find up to `limit` words ending with `suffix`, e.g. for rhymes
+    ///
+    /// Suffixes are matched after stripping, like prefixes. Without the
+    /// index from [`MdxBuilder::with_suffix_index`] this scans every
+    /// keyword.
+    pub fn suffix_keys(&self, suffix: &str, limit: usize) -> Vec<String> {
+        self.base
+            .suffix_keywords(suffix, limit)
+            .into_iter()
+            .map(|k| k.key_text.clone())
+            .collect()
+    }
+
+    /// Check whether any word starts with `prefix`, without collecting them
+    ///
+    /// A binary search that compares keys without allocating. Case is
+    /// folded per character, so a dictionary with keys ending in an
+    /// uppercase `Σ` may get a wrong answer here; it is one that
+    /// [`Self::is_sorted_for_lookup`] reports as unsorted.
+    pub fn has_prefix(&self, prefix: &str) -> bool {
+        self.base.has_prefix(prefix)
+    }
+
     /// Suggest similar words based on edit distance
+    ///
+    /// Closest words come first. Ties go to the word sharing the longer
+    /// prefix with `word`, then to the one closer in length, so for `helo`
+    /// both `help` and `hello` rank above `halo`.
     pub fn suggest(&self, word: &str, max_distance: usize) -> Vec<String> {
         if max_distance > 5 {
             return Vec::new();
         }
 
-        let is_mdd = self.base.meta.ext == FileExt::Mdd;
-        let stripped_word = strip_key(word, is_mdd);
+        let stripped_word = self.base.strip(word);
 
         // Get associated keywords
         let keywords = self.base.get_associated_keywords(word);
 
-        let mut suggestions: Vec<(String, usize)> = keywords
+        let word_len = stripped_word.chars().count();
+        let mut suggestions: Vec<_> = keywords
             .into_iter()
             .filter_map(|item| {
-                let stripped_key = strip_key(&item.key_text, is_mdd);
+                let stripped_key = self.base.strip(&item.key_text);
                 let distance = levenshtein_distance(&stripped_key, &stripped_word);
-                if distance <= max_distance {
-                    Some((item.key_text.clone(), distance))
-                } else {
-                    None
+                if distance > max_distance {
+                    return None;
                 }
+                let prefix = stripped_key
+                    .chars()
+                    .zip(stripped_word.chars())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let length_diff = stripped_key.chars().count().abs_diff(word_len);
+                Some((
+                    item.key_text.clone(),
+                    (distance, std::cmp::Reverse(prefix), length_diff),
+                ))
             })
             .collect();
 
-        // Sort by edit distance
-        suggestions.sort_by_key(|(_, d)| *d);
+        // Sort by edit distance, then prefix and length similarity
+        suggestions.sort_by_key(|(_, rank)| *rank);
 
         suggestions.into_iter().map(|(s, _)| s).collect()
     }
@@ -139,8 +776,7 @@ impl Mdx {
         max_results: usize,
         max_distance: usize,
     ) -> Vec<FuzzyWord> {
-        let is_mdd = self.base.meta.ext == FileExt::Mdd;
-        let stripped_word = strip_key(word, is_mdd);
+        let stripped_word = self.base.strip(word);
 
         // Get associated keywords
         let keywords = self.base.get_associated_keywords(word);
@@ -148,7 +784,7 @@ impl Mdx {
         let mut fuzzy_words: Vec<FuzzyWord> = keywords
             .into_iter()
             .filter_map(|item| {
-                let stripped_key = strip_key(&item.key_text, is_mdd);
+                let stripped_key = self.base.strip(&item.key_text);
                 let distance = levenshtein_distance(&stripped_key, &stripped_word);
                 if distance <= max_distance {
                     Some(FuzzyWord {
@@ -171,23 +807,20 @@ impl Mdx {
     }
 
     /// Get definition for a fuzzy word
+    ///
+    /// Same as [`Self::fetch_definition_only`].
     pub fn get_definition(&mut self, item: &KeyWordItem) -> Option<String> {
-        let def_bytes = self.base.lookup_record_by_keyword(item).ok()?;
-
-        let definition = decode_string(&def_bytes, self.base.meta.encoding)
-            .unwrap_or_else(|_| String::from_utf8_lossy(&def_bytes).to_string());
-
-        Some(definition)
+        self.fetch_definition_only(item)
     }
 
     /// Check if a word exists in the dictionary
     pub fn contains(&self, word: &str) -> bool {
-        self.base.lookup_keyword_by_word(word, false).is_some()
+        MdictFile::contains(self, word)
     }
 
     /// Get the file path
     pub fn filepath(&self) -> &str {
-        &self.base.filepath
+        MdictFile::filepath(self)
     }
 
     /// Get associated keywords (words in the same key block)
@@ -196,6 +829,121 @@ impl Mdx {
         self.base.get_associated_keywords(word)
     }
 
+    /// Get up to `limit` entries of `word`'s key block with their
+    /// definitions, centered on `word`
+    ///
+    /// The window of [`Self::associate`] for a "see also" box: the matched
+    /// entry comes with its neighbours on either side, fewer on one side
+    /// near the block's edges. A word that isn't in the dictionary is
+    /// centered like its nearest keyword. Record blocks shared by the
+    /// entries are decompressed once; entries whose record can't be read
+    /// are skipped.
+    pub fn related(&mut self, word: &str, limit: usize) -> Vec<LookupResult> {
+        let Some(target) = self.base.lookup_keyword_by_word(word, true) else {
+            return Vec::new();
+        };
+        let block: Vec<&KeyWordItem> = self.base.get_associated_keywords(word);
+        let position = block
+            .iter()
+            .position(|item| std::ptr::eq(*item, target))
+            .unwrap_or(0);
+        let start = position
+            .saturating_sub(limit / 2)
+            .min(block.len().saturating_sub(limit));
+        let items: Vec<KeyWordItem> = block.into_iter().skip(start).take(limit).cloned().collect();
+        self.fetch_all(items)
+    }
+
+    /// Get the keywords `offset..offset + count` of the sorted keyword list
+    /// with their definitions, for paging through the whole dictionary
+    ///
+    /// The window is clamped to the list. Each record block the page needs
+    /// is decompressed once; entries whose record can't be read are
+    /// skipped.
+    pub fn page(&mut self, offset: usize, count: usize) -> Vec<LookupResult> {
+        let list = &self.base.keyword_list;
+        let start = offset.min(list.len());
+        let end = offset.saturating_add(count).min(list.len());
+        let items = list[start..end].to_vec();
+        self.fetch_all(items)
+    }
+
+    /// Definitions of `items` in order, reading each record block once
+    fn fetch_all(&mut self, items: Vec<KeyWordItem>) -> Vec<LookupResult> {
+        let records = self.fetch_raw_batch(&items);
+        items
+            .into_iter()
+            .zip(records)
+            .filter_map(|(item, record)| {
+                Some(LookupResult {
+                    definition: self.base.decode_definition(&record.ok()?),
+                    key_text: item.key_text,
+                })
+            })
+            .collect()
+    }
+
+    /// Strip `word` as lookups do, and return it along with the stripped
+    /// form of the nearest keyword
+    ///
+    /// If the two differ, `word` isn't found by `lookup`. The nearest
+    /// keyword is empty for an empty dictionary.
+    pub fn debug_strip(&self, word: &str) -> (String, String) {
+        let nearest = self
+            .base
+            .lookup_keyword_by_word(word, true)
+            .map(|item| self.base.strip(&item.key_text))
+            .unwrap_or_default();
+        (self.base.strip(word), nearest)
+    }
+
+    /// Look up `word` and record each step of the binary search
+    pub fn explain_lookup(&self, word: &str) -> LookupTrace {
+        let mut steps = Vec::new();
+        let index = self.base.search_keyword(word, false, |index, ordering| {
+            let key_text = &self.base.keyword_list[index].key_text;
+            steps.push(LookupStep {
+                index,
+                key_text: key_text.clone(),
+                stripped_key: self.base.strip(key_text),
+                ordering,
+            });
+        });
+
+        LookupTrace {
+            query: word.to_string(),
+            stripped_query: self.base.strip(word),
+            steps,
+            found: index.map(|i| self.base.keyword_list[i].clone()),
+        }
+    }
+
+    /// Look up a word and report where its record was read from
+    ///
+    /// For reproducing garbled definitions: gives the record block, the
+    /// record's byte range within the decompressed block, the block's
+    /// compression and the undecoded record. `None` if the word isn't found
+    /// or its block can't be read.
+    pub fn lookup_debug(&mut self, word: &str) -> Option<LookupDebug> {
+        let item = self.base.lookup_keyword_by_word(word, false)?.clone();
+        let block_index = self.base.record_block_for(&item).ok()?;
+        let (packed, unpack_size) = self.base.read_packed_record_block(block_index).ok()?;
+        let block = self
+            .base
+            .decompress_record_block(&packed, unpack_size)
+            .ok()?;
+        let range = self.base.record_range(block.len(), block_index, &item);
+
+        Some(LookupDebug {
+            item,
+            block_index,
+            start: range.start,
+            end: range.end,
+            compression: CompressionType::from_bytes(&packed),
+            raw: block[range].to_vec(),
+        })
+    }
+
     /// Lookup keyword by word (returns KeyWordItem with block info)
     pub fn lookup_keyword(&self, word: &str) -> Option<&KeyWordItem> {
         self.base.lookup_keyword_by_word(word, false)
@@ -203,27 +951,365 @@ impl Mdx {
 
     /// Fetch definition for a keyword item
     pub fn fetch(&mut self, item: &KeyWordItem) -> Option<LookupResult> {
-        let def_bytes = self.base.lookup_record_by_keyword(item).ok()?;
-
-        let definition = decode_string(&def_bytes, self.base.meta.encoding)
-            .unwrap_or_else(|_| String::from_utf8_lossy(&def_bytes).to_string());
+        #[cfg(feature = "metrics")]
+        if let Some(hook) = self.base.lookup_hook() {
+            return self.base.fetch_timed(item, hook);
+        }
 
         Some(LookupResult {
+            definition: self.fetch_definition_only(item)?,
             key_text: item.key_text.clone(),
-            definition,
         })
     }
 
+    /// Fetch only the definition for a keyword item
+    ///
+    /// For batches where the caller already holds `item`: unlike
+    /// [`Self::fetch`], the key text isn't copied into a [`LookupResult`],
+    /// and `&item.key_text` serves as the borrowed key. Not reported to a
+    /// lookup callback.
+    pub fn fetch_definition_only(&mut self, item: &KeyWordItem) -> Option<String> {
+        self.base.definition_of(item).ok()
+    }
+
     /// Get all keyword items
     pub fn keyword_list(&self) -> &[KeyWordItem] {
         &self.base.keyword_list
     }
+
+    /// Owned copy of all keyword items
+    ///
+    /// Unlike [`Self::keyword_list`], the copy doesn't borrow the dictionary,
+    /// so it can be held while fetching its items.
+    pub fn keyword_list_cloned(&self) -> Vec<KeyWordItem> {
+        self.base.keyword_list.clone()
+    }
+
+    /// Get the key block info: first and last key, sizes and entry count
+    /// of every key block
+    pub fn key_info_list(&self) -> &[KeyInfoItem] {
+        &self.base.key_info_list
+    }
+
+    /// `(first_key, last_key)` of every key block, in file order
+    ///
+    /// Enough for a coarse alphabetic index without walking the keyword
+    /// list. Keys are as stored, so ranges follow the dictionary's own sort
+    /// order.
+    pub fn key_block_ranges(&self) -> Vec<(String, String)> {
+        self.base
+            .key_info_list
+            .iter()
+            .map(|info| (info.first_key.clone(), info.last_key.clone()))
+            .collect()
+    }
+
+    /// Whether any key or record block is LZO-compressed
+    ///
+    /// Reads each block's 4-byte compression tag, stopping at the first
+    /// LZO one, so a dictionary can be checked for LZO before relying on
+    /// it. Nothing is decompressed.
+    pub fn uses_lzo(&self) -> bool {
+        self.base
+            .block_compressions()
+            .any(|compression| compression == Some(CompressionType::Lzo))
+    }
+
+    /// `(packed, unpacked)` total size in bytes of the record blocks
+    ///
+    /// Summed from the record block info, for showing the size on disk
+    /// against the size once decompressed.
+    pub fn record_bytes(&self) -> (u64, u64) {
+        self.base
+            .record_info_list
+            .iter()
+            .fold((0, 0), |(packed, unpacked), info| {
+                (packed + info.pack_size, unpacked + info.unpack_size)
+            })
+    }
+
+    /// `(packed, unpacked)` total size in bytes of the key blocks
+    pub fn key_block_bytes(&self) -> (u64, u64) {
+        self.base
+            .key_info_list
+            .iter()
+            .fold((0, 0), |(packed, unpacked), info| {
+                (
+                    packed + info.key_block_pack_size,
+                    unpacked + info.key_block_unpack_size,
+                )
+            })
+    }
+
+    /// Distribution of definition sizes in bytes
+    ///
+    /// Computed from the record offsets in the keyword index, so nothing is
+    /// read from disk or decompressed. Sizes include any trailing NUL.
+    pub fn definition_size_stats(&self) -> SizeStats {
+        let sizes = self
+            .base
+            .keyword_list
+            .iter()
+            .map(|item| self.base.record_len(item))
+            .collect();
+        SizeStats::from_sizes(sizes)
+    }
+
+    /// The `n` keys with the largest definitions, largest first
+    ///
+    /// Sizes come from the record offsets like
+    /// [`Self::definition_size_stats`], so nothing is decompressed. Ties keep
+    /// keyword order.
+    pub fn largest_entries(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut entries: Vec<(&str, u64)> = self
+            .base
+            .keyword_list
+            .iter()
+            .map(|item| (item.key_text.as_str(), self.base.record_len(item)))
+            .collect();
+        entries.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+        entries.truncate(n);
+        entries
+    }
+
+    /// Index of the record block holding `item`'s definition
+    ///
+    /// Items sharing an index can be fetched with one decompression, e.g.
+    /// via [`Self::fetch_block_definitions`].
+    pub fn record_block_index(&self, item: &KeyWordItem) -> usize {
+        self.base.find_record_block_index(item.record_start_offset)
+    }
+
+    /// Number of keywords whose records start in record block `idx`
+    pub fn entries_in_record_block(&self, idx: usize) -> usize {
+        self.base
+            .keyword_list
+            .iter()
+            .filter(|item| self.base.find_record_block_index(item.record_start_offset) == idx)
+            .count()
+    }
+
+    /// Whether the keyword list is sorted the way lookups compare keys
+    ///
+    /// Adjacent keys are compared character by character, as
+    /// [`Self::has_prefix`] does, rather than with the stripped strings the
+    /// load-time sort uses. The two disagree where lowercasing depends on
+    /// context, such as a final `Σ`; a `false` means lookups may miss keys
+    /// that exist. See [`MdxBuilder::verify_sort_order`] to check this when
+    /// opening.
+    pub fn is_sorted_for_lookup(&self) -> bool {
+        self.base.first_unsorted_index().is_none()
+    }
+
+    /// The first adjacent pair of keywords out of lookup order, if any
+    pub fn first_unsorted_pair(&self) -> Option<(&str, &str)> {
+        let i = self.base.first_unsorted_index()?;
+        let list = &self.base.keyword_list;
+        Some((&list[i].key_text, &list[i + 1].key_text))
+    }
+
+    /// Check that every keyword's record lies within a single record block
+    ///
+    /// Records spanning two blocks aren't supported, so such a file would
+    /// return truncated definitions. Fails with `InvalidFormat` describing
+    /// the first record that starts past the record data, ends before it
+    /// starts, or crosses a block boundary.
+    pub fn self_test(&self) -> Result<()> {
+        for item in &self.base.keyword_list {
+            let block_idx = self.base.record_block_for(item).map_err(|_| {
+                MdictError::InvalidFormat(format!(
+                    "Record of {:?} starts at {}, past the record data",
+                    item.key_text, item.record_start_offset
+                ))
+            })?;
+            let info = &self.base.record_info_list[block_idx];
+            let block_end = info.unpack_accumulate_offset + info.unpack_size;
+            let end = match item.record_end_offset {
+                0 => block_end,
+                end => end,
+            };
+
+            if end < item.record_start_offset {
+                return Err(MdictError::InvalidFormat(format!(
+                    "Record of {:?} ends at {} before it starts at {}",
+                    item.key_text, end, item.record_start_offset
+                )));
+            }
+            if end > block_end {
+                return Err(MdictError::InvalidFormat(format!(
+                    "Record of {:?} spans record blocks {} and {}: ends at {}, block ends at {}",
+                    item.key_text,
+                    block_idx,
+                    block_idx + 1,
+                    end,
+                    block_end
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch every `(key, definition)` whose record lives in record block
+    /// `block_idx`, decompressing the block only once
+    ///
+    /// Entries are returned in record order.
+    pub fn fetch_block_definitions(&mut self, block_idx: usize) -> Result<Vec<(String, String)>> {
+        Ok(self.block_entries(block_idx)?.collect())
+    }
+
+    /// Iterate over every `(key, definition)` whose record lives in record
+    /// block `block_idx`
+    ///
+    /// The block is decompressed once, up front, so a missing or corrupt
+    /// block fails here rather than mid-iteration. Each definition is then
+    /// sliced from it by its offsets and decoded as the iterator reaches
+    /// it, in record order. Finding the block's keywords scans the keyword
+    /// list, so to walk the whole dictionary prefer [`Self::into_entries`].
+    pub fn block_entries(
+        &mut self,
+        block_idx: usize,
+    ) -> Result<impl Iterator<Item = (String, String)> + '_> {
+        let block = self.base.read_record_block(block_idx)?;
+
+        let base = &self.base;
+        let mut items: Vec<&KeyWordItem> = base
+            .keyword_list
+            .iter()
+            .filter(|item| base.find_record_block_index(item.record_start_offset) == block_idx)
+            .collect();
+        items.sort_by_key(|item| item.record_start_offset);
+
+        Ok(items.into_iter().map(move |item| {
+            let def_bytes = base.slice_record(&block, block_idx, item);
+            (item.key_text.clone(), base.decode_definition(def_bytes))
+        }))
+    }
+
+    /// Export as an uncompressed StarDict dictionary
+    ///
+    /// Writes `<base_path>.ifo`, `<base_path>.idx` and `<base_path>.dict`.
+    /// Definitions are re-encoded as UTF-8 HTML. The book name is the
+    /// `Title` header, falling back to the file name.
+    pub fn export_stardict(&mut self, base_path: &Path) -> Result<()> {
+        self.export_stardict_with_progress(base_path, |_, _| {})
+    }
+
+    /// [`Self::export_stardict`], calling `progress(done, total)` after
+    /// each record block
+    ///
+    /// `total` is the number of record blocks holding entries, so the last
+    /// call has `done == total`.
+    pub fn export_stardict_with_progress(
+        &mut self,
+        base_path: &Path,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<()> {
+        let mut writer = StarDictWriter::create(base_path)?;
+
+        let groups = self.base.group_by_record_block(&self.base.keyword_list);
+        let total = groups.len();
+        for (done, (block_idx, items)) in groups.into_iter().enumerate() {
+            let block = self.base.read_record_block(block_idx)?;
+            for item in &items {
+                let def_bytes = self.base.slice_record(&block, block_idx, item);
+                writer.add(&item.key_text, &self.base.decode_definition(def_bytes))?;
+            }
+            progress(done + 1, total);
+        }
+
+        let bookname = match self.title() {
+            Some(title) => title.to_string(),
+            None => Path::new(&self.base.filepath)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        writer.finish(&bookname, self.about_html().as_deref())
+    }
+
+    /// Check every key and record block and report all that fail
+    ///
+    /// Each block is decompressed and compared with its declared size and
+    /// stored checksum. An empty result means the file is intact.
+    pub fn verify(&self) -> Vec<BlockError> {
+        self.base.verify_blocks()
+    }
+
+    /// Like [`Self::verify`], but checks blocks on all available cores
+    #[cfg(feature = "parallel")]
+    pub fn verify_parallel(&self) -> Vec<BlockError> {
+        self.base.verify_blocks_parallel()
+    }
+
+    /// CRC-32 of each decompressed record block, in block order
+    ///
+    /// Comparing the lists of two editions shows which blocks changed
+    /// without comparing their entries. Fails on the first block that
+    /// can't be read.
+    pub fn record_block_crcs(&mut self) -> Result<Vec<u32>> {
+        (0..self.base.record_info_list.len())
+            .map(|index| {
+                let mut crc = Crc::new();
+                crc.update(&self.base.read_record_block(index)?);
+                Ok(crc.sum())
+            })
+            .collect()
+    }
+
+    /// Fetch raw record bytes for a batch of keyword items
+    ///
+    /// Items are grouped by record block so each block is decompressed once.
+    /// Results are returned in the same order as `items`.
+    pub fn fetch_raw_batch(&mut self, items: &[KeyWordItem]) -> Vec<Result<Vec<u8>>> {
+        let mut results: Vec<Option<Result<Vec<u8>>>> = items.iter().map(|_| None).collect();
+
+        for (block_idx, indices) in self.base.group_indices_by_record_block(items) {
+            match self.base.read_record_block(block_idx) {
+                Ok(block) => {
+                    for i in indices {
+                        let bytes = self.base.slice_record(&block, block_idx, &items[i]);
+                        results[i] = Some(Ok(bytes.to_vec()));
+                    }
+                }
+                Err(e) => {
+                    // Errors aren't cloneable; retry the rest individually so
+                    // each item reports its own error
+                    let (first, rest) = indices.split_first().unwrap();
+                    results[*first] = Some(Err(e));
+                    for &i in rest {
+                        results[i] = Some(self.base.lookup_record_by_keyword(&items[i]));
+                    }
+                }
+            }
+        }
+
+        results.into_iter().flatten().collect()
+    }
+}
+
+/// Replace the resource references in `html` with `data:` URIs from the
+/// first of `mdds` that has each resource
+///
+/// References that are already `data:` URIs, point to the web or aren't in
+/// any MDD are left as they are.
+pub(crate) fn inline_resources(html: &str, mdds: &mut [Mdd]) -> String {
+    rewrite_resource_refs(html, |value| {
+        let lower = value.to_ascii_lowercase();
+        if ["data:", "http://", "https://", "//"]
+            .iter()
+            .any(|scheme| lower.starts_with(scheme))
+        {
+            return None;
+        }
+        mdds.iter_mut().find_map(|mdd| {
+            let key = mdd.resolve(value)?.to_string();
+            mdd.locate_data_uri(&key)
+        })
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn test_mdx_creation() {
         // This test requires an actual MDX file
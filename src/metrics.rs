@@ -0,0 +1,94 @@
+//! Lookup timing callbacks
+//!
+//! With the `metrics` feature, [`MdxBuilder::on_lookup`](crate::MdxBuilder::on_lookup)
+//! installs a callback that receives a [`LookupMetrics`] after every
+//! `lookup`/`fetch`. Without a callback installed, lookups take the usual
+//! untimed path.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::mdict_base::MdictBase;
+use crate::types::{KeyWordItem, LookupResult};
+
+/// Callback receiving the metrics of each lookup
+pub(crate) type LookupHook = Arc<dyn Fn(LookupMetrics) + Send + Sync>;
+
+/// Timings and sizes of a single lookup
+#[derive(Debug, Clone, Default)]
+pub struct LookupMetrics {
+    /// Word looked up, or the keyword for a `fetch`
+    pub word: String,
+    /// Whether a definition was returned
+    pub found: bool,
+    /// Whether the record block came from a cache instead of the file
+    pub cache_hit: bool,
+    /// Size of the record block decompressed for this lookup, 0 if it came
+    /// from the cache
+    pub bytes_decompressed: usize,
+    /// Time spent finding the keyword in the index; zero for `fetch`
+    pub search: Duration,
+    /// Time spent reading the compressed record block
+    pub read: Duration,
+    /// Time spent decompressing the record block and decoding the record
+    pub decompress: Duration,
+}
+
+impl MdictBase {
+    /// `lookup` that reports its metrics to `hook`
+    pub(crate) fn lookup_timed(&self, word: &str, hook: &LookupHook) -> Option<LookupResult> {
+        let mut metrics = LookupMetrics {
+            word: word.to_string(),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let item = self.lookup_keyword_or_alias(word).map(|(item, _)| item);
+        metrics.search = started.elapsed();
+
+        let result = item.and_then(|item| self.fetch_into(item, &mut metrics));
+        metrics.found = result.is_some();
+        hook(metrics);
+        result
+    }
+
+    /// `fetch` that reports its metrics to `hook`
+    pub(crate) fn fetch_timed(
+        &self,
+        item: &KeyWordItem,
+        hook: &LookupHook,
+    ) -> Option<LookupResult> {
+        let mut metrics = LookupMetrics {
+            word: item.key_text.clone(),
+            ..Default::default()
+        };
+
+        let result = self.fetch_into(item, &mut metrics);
+        metrics.found = result.is_some();
+        hook(metrics);
+        result
+    }
+
+    /// Fetch `item`'s definition, recording read and decompress timings
+    fn fetch_into(&self, item: &KeyWordItem, metrics: &mut LookupMetrics) -> Option<LookupResult> {
+        let block_idx = self.record_block_for(item).ok()?;
+
+        let started = Instant::now();
+        let (packed, unpack_size) = self.read_packed_record_block(block_idx).ok()?;
+        metrics.read = started.elapsed();
+
+        let started = Instant::now();
+        let (block, cache_hit) = self
+            .decompress_record_block_cached(&packed, unpack_size)
+            .ok()?;
+        metrics.cache_hit = cache_hit;
+        metrics.bytes_decompressed = if cache_hit { 0 } else { block.len() };
+        let definition = self.decode_definition(self.slice_record(&block, block_idx, item));
+        metrics.decompress = started.elapsed();
+
+        Some(LookupResult {
+            key_text: item.key_text.clone(),
+            definition,
+        })
+    }
+}
@@ -52,7 +52,7 @@ fn pad_message(data: &[u8]) -> Vec<u8> {
 /// Left rotation
 #[inline]
 fn rotl(x: u32, n: u32) -> u32 {
-    (x << n) | (x >> (32 - n))
+    x.rotate_left(n)
 }
 
 /// Boolean functions
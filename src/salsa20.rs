@@ -0,0 +1,109 @@
+//! Salsa20/8 stream cipher for MDX key header decryption
+//!
+//! MDict encrypts the key header of registered dictionaries with Salsa20
+//! reduced to 8 rounds, using a 128-bit key and an all-zero nonce. This is
+//! a pure Rust implementation of just that variant.
+
+/// "expand 16-byte k"
+const TAU: [u32; 4] = [0x6170_7865, 0x3120_646e, 0x7962_2d36, 0x6b20_6574];
+
+/// Encrypt or decrypt `data` with Salsa20/8 under `key` and a zero nonce
+pub fn salsa20_8(data: &[u8], key: &[u8; 16]) -> Vec<u8> {
+    let mut k = [0u32; 4];
+    for (i, word) in key.chunks(4).enumerate() {
+        k[i] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    for (counter, chunk) in data.chunks(64).enumerate() {
+        let counter = counter as u64;
+        let input = [
+            TAU[0],
+            k[0],
+            k[1],
+            k[2],
+            k[3],
+            TAU[1],
+            0,
+            0,
+            counter as u32,
+            (counter >> 32) as u32,
+            TAU[2],
+            k[0],
+            k[1],
+            k[2],
+            k[3],
+            TAU[3],
+        ];
+        let keystream = block(&input);
+        output.extend(chunk.iter().zip(keystream.iter()).map(|(d, k)| d ^ k));
+    }
+
+    output
+}
+
+/// Produce one 64-byte keystream block
+fn block(input: &[u32; 16]) -> [u8; 64] {
+    let mut x = *input;
+    for _ in 0..4 {
+        // Column round
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 5, 9, 13, 1);
+        quarter_round(&mut x, 10, 14, 2, 6);
+        quarter_round(&mut x, 15, 3, 7, 11);
+        // Row round
+        quarter_round(&mut x, 0, 1, 2, 3);
+        quarter_round(&mut x, 5, 6, 7, 4);
+        quarter_round(&mut x, 10, 11, 8, 9);
+        quarter_round(&mut x, 15, 12, 13, 14);
+    }
+
+    let mut out = [0u8; 64];
+    for (i, (word, init)) in x.iter().zip(input.iter()).enumerate() {
+        out[i * 4..(i + 1) * 4].copy_from_slice(&word.wrapping_add(*init).to_le_bytes());
+    }
+    out
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+    x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+    x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+    x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarter_round() {
+        // Examples from the Salsa20 specification
+        let mut x = [0u32; 16];
+        x[0] = 1;
+        quarter_round(&mut x, 0, 1, 2, 3);
+        assert_eq!(
+            &x[..4],
+            &[0x0800_8145, 0x0000_0080, 0x0001_0200, 0x2050_0000]
+        );
+
+        let mut x = [0u32; 16];
+        x[..4].copy_from_slice(&[0xe7e8_c006, 0xc4f9_417d, 0x6479_b4b2, 0x68c6_7137]);
+        quarter_round(&mut x, 0, 1, 2, 3);
+        assert_eq!(
+            &x[..4],
+            &[0xe876_d72b, 0x9361_dfd5, 0xf146_0244, 0x9485_41a3]
+        );
+    }
+
+    #[test]
+    fn test_salsa20_8_roundtrip() {
+        let key = *b"0123456789abcdef";
+        let data: Vec<u8> = (0..200u8).collect();
+
+        let encrypted = salsa20_8(&data, &key);
+        assert_eq!(encrypted.len(), data.len());
+        assert_ne!(encrypted, data);
+        assert_eq!(salsa20_8(&encrypted, &key), data);
+    }
+}
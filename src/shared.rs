@@ -0,0 +1,81 @@
+//! Thread-safe read-only MDX access
+//!
+//! [`SharedMdx`] is `Send + Sync` and all of its queries take `&self`, so a
+//! single instance can sit in an `Arc` (e.g. in web server state) and serve
+//! many request handlers at once.
+//!
+//! Threading model: the keyword index is immutable after opening, and record
+//! blocks are read with positional reads (`pread` on Unix, `seek_read` on
+//! Windows), so concurrent lookups never contend on a shared file cursor or
+//! a lock. Each lookup reads and decompresses its record block independently.
+
+use crate::dictionary::MdictFile;
+use crate::mdict_base::MdictBase;
+use crate::types::*;
+
+/// MDX dictionary that can be queried concurrently from many threads
+pub struct SharedMdx {
+    /// Base parser
+    pub(crate) base: MdictBase,
+}
+
+impl SharedMdx {
+    /// Wrap an already parsed base
+    pub(crate) fn from_base(base: MdictBase) -> Self {
+        SharedMdx { base }
+    }
+
+    /// Get dictionary header
+    pub fn header(&self) -> &DictHeader {
+        MdictFile::header(self)
+    }
+
+    /// Get dictionary metadata
+    pub fn meta(&self) -> &DictMeta {
+        MdictFile::meta(self)
+    }
+
+    /// Get total number of keywords
+    pub fn keyword_count(&self) -> usize {
+        self.base.keyword_list.len()
+    }
+
+    /// Get the file path
+    pub fn filepath(&self) -> &str {
+        MdictFile::filepath(self)
+    }
+
+    /// Lookup a word and return its definition
+    pub fn lookup(&self, word: &str) -> Option<LookupResult> {
+        #[cfg(feature = "metrics")]
+        if let Some(hook) = self.base.lookup_hook() {
+            return self.base.lookup_timed(word, hook);
+        }
+
+        let (keyword_item, _) = self.base.lookup_keyword_or_alias(word)?;
+        self.fetch(keyword_item)
+    }
+
+    /// Fetch definition for a keyword item
+    pub fn fetch(&self, item: &KeyWordItem) -> Option<LookupResult> {
+        #[cfg(feature = "metrics")]
+        if let Some(hook) = self.base.lookup_hook() {
+            return self.base.fetch_timed(item, hook);
+        }
+
+        Some(LookupResult {
+            definition: self.base.definition_of(item).ok()?,
+            key_text: item.key_text.clone(),
+        })
+    }
+
+    /// Check if a word exists in the dictionary
+    pub fn contains(&self, word: &str) -> bool {
+        MdictFile::contains(self, word)
+    }
+
+    /// Lookup keyword by word (returns KeyWordItem with block info)
+    pub fn lookup_keyword(&self, word: &str) -> Option<&KeyWordItem> {
+        self.base.lookup_keyword_by_word(word, false)
+    }
+}
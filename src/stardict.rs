@@ -0,0 +1,126 @@
+//! StarDict dictionary writer
+//!
+//! Writes the three files of an uncompressed StarDict 2.4.2 dictionary:
+//! `.dict` with the concatenated definitions, `.idx` with the sorted word
+//! index, and `.ifo` with metadata. Definitions are stored as HTML
+//! (`sametypesequence=h`). Synonym files and dictzip are not supported.
+
+use std::cmp::Ordering;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{MdictError, Result};
+
+/// StarDict writer streaming definitions to the `.dict` file
+pub struct StarDictWriter {
+    base_path: PathBuf,
+    dict: BufWriter<File>,
+    /// `(word, offset, size)` for every entry, sorted on finish
+    index: Vec<(String, u32, u32)>,
+    offset: u64,
+}
+
+impl StarDictWriter {
+    /// Create `<base_path>.dict`; the other files are written on finish
+    pub fn create(base_path: &Path) -> Result<Self> {
+        let dict = BufWriter::new(File::create(with_suffix(base_path, ".dict"))?);
+        Ok(StarDictWriter {
+            base_path: base_path.to_path_buf(),
+            dict,
+            index: Vec::new(),
+            offset: 0,
+        })
+    }
+
+    /// Append a definition for `word`
+    pub fn add(&mut self, word: &str, definition: &str) -> Result<()> {
+        let data = definition.trim_end_matches('\0').as_bytes();
+        let offset = to_u32(self.offset, "dict file size")?;
+        let size = to_u32(data.len() as u64, "definition size")?;
+
+        self.dict.write_all(data)?;
+        self.offset += data.len() as u64;
+        self.index.push((word.to_string(), offset, size));
+
+        Ok(())
+    }
+
+    /// Write the `.idx` and `.ifo` files
+    pub fn finish(mut self, bookname: &str, description: Option<&str>) -> Result<()> {
+        self.dict.flush()?;
+
+        self.index.sort_by(|a, b| stardict_cmp(&a.0, &b.0));
+        let mut idx = Vec::new();
+        for (word, offset, size) in &self.index {
+            idx.extend_from_slice(word.as_bytes());
+            idx.push(0);
+            idx.extend_from_slice(&offset.to_be_bytes());
+            idx.extend_from_slice(&size.to_be_bytes());
+        }
+        std::fs::write(with_suffix(&self.base_path, ".idx"), &idx)?;
+
+        // Values in the .ifo must fit on one line
+        let mut ifo = String::from("StarDict's dict ifo file\nversion=2.4.2\n");
+        ifo.push_str(&format!("wordcount={}\n", self.index.len()));
+        ifo.push_str(&format!("idxfilesize={}\n", idx.len()));
+        ifo.push_str(&format!("bookname={}\n", single_line(bookname, " ")));
+        if let Some(description) = description {
+            ifo.push_str(&format!(
+                "description={}\n",
+                single_line(description, "<br>")
+            ));
+        }
+        ifo.push_str("sametypesequence=h\n");
+        std::fs::write(with_suffix(&self.base_path, ".ifo"), ifo)?;
+
+        Ok(())
+    }
+}
+
+/// StarDict index order: ASCII case-insensitive, then byte order
+fn stardict_cmp(a: &str, b: &str) -> Ordering {
+    let folded = a
+        .bytes()
+        .map(|c| c.to_ascii_lowercase())
+        .cmp(b.bytes().map(|c| c.to_ascii_lowercase()));
+    folded.then_with(|| a.cmp(b))
+}
+
+/// Replace line breaks so `text` fits on one `.ifo` line
+fn single_line(text: &str, separator: &str) -> String {
+    text.replace("\r\n", "\n")
+        .trim()
+        .replace(['\r', '\n'], separator)
+}
+
+/// `base_path` with `suffix` appended (not replacing any extension)
+fn with_suffix(base_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(base_path.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn to_u32(value: u64, what: &str) -> Result<u32> {
+    u32::try_from(value).map_err(|_| {
+        MdictError::InvalidFormat(format!("StarDict {} exceeds 4 GiB: {}", what, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stardict_cmp() {
+        let mut words = vec!["b", "B", "a", "ab", "A"];
+        words.sort_by(|a, b| stardict_cmp(a, b));
+        assert_eq!(words, vec!["A", "a", "ab", "B", "b"]);
+    }
+
+    #[test]
+    fn test_single_line() {
+        assert_eq!(single_line("a\r\nb\nc\n", "<br>"), "a<br>b<br>c");
+    }
+}
@@ -16,6 +16,27 @@ impl FileExt {
             FileExt::Mdd => "mdd",
         }
     }
+
+    /// Infer the file type from `path`'s extension, ignoring case
+    ///
+    /// Numbered MDD volumes named `name.mdd.1`, `name.mdd.2`, ... are MDD
+    /// too.
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        let mut ext = path.extension()?.to_str()?;
+        if !ext.is_empty() && ext.bytes().all(|b| b.is_ascii_digit()) {
+            ext = std::path::Path::new(path.file_stem()?)
+                .extension()?
+                .to_str()?;
+            return ext.eq_ignore_ascii_case("mdd").then_some(FileExt::Mdd);
+        }
+        if ext.eq_ignore_ascii_case("mdx") {
+            Some(FileExt::Mdx)
+        } else if ext.eq_ignore_ascii_case("mdd") {
+            Some(FileExt::Mdd)
+        } else {
+            None
+        }
+    }
 }
 
 /// Encoding type
@@ -24,6 +45,9 @@ pub enum Encoding {
     #[default]
     Utf8,
     Utf16Le,
+    /// UTF-16BE, from `Encoding="UTF-16BE"` or a header starting with a
+    /// big-endian BOM
+    Utf16Be,
     Gb18030,
     Big5,
 }
@@ -32,7 +56,11 @@ pub enum Encoding {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EncryptType {
     None = 0,
+    /// `Encrypted="1"`/`"Yes"`: the key header is encrypted with a per-user
+    /// key, see [`MdxBuilder::credentials`](crate::MdxBuilder::credentials)
     RecordBlock = 1,
+    /// `Encrypted="2"`: the key info block is encrypted with a key derived
+    /// from its own checksum, and needs no credentials
     KeyInfoBlock = 2,
 }
 
@@ -47,6 +75,52 @@ impl From<u8> for EncryptType {
     }
 }
 
+/// What a registration code was issued for, from the `RegisterBy` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterBy {
+    /// `RegisterBy="EMail"`: the user's email address, hashed as UTF-16LE
+    Email,
+    /// Any other value, or none: a device ID, hashed as its raw bytes
+    DeviceId,
+}
+
+impl RegisterBy {
+    /// Parse a `RegisterBy` header value, ignoring case
+    ///
+    /// Only `EMail` selects email registration, as in readmdict; a missing
+    /// or unknown value means a device ID.
+    pub fn from_header(value: Option<&str>) -> Self {
+        match value {
+            Some(value) if value.trim().eq_ignore_ascii_case("email") => RegisterBy::Email,
+            _ => RegisterBy::DeviceId,
+        }
+    }
+}
+
+/// Key normalization applied before comparing keys, from the `StripKey`
+/// header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StripMode {
+    /// `StripKey="No"` (or unrecognized): keys compare as stored
+    None,
+    /// `StripKey="Yes"`, the default: punctuation and spaces are removed
+    StripPunct,
+    /// `StripKey="Compact"`: runs of whitespace collapse to one space
+    CompactWhitespace,
+}
+
+impl StripMode {
+    /// Parse a `StripKey` header value, ignoring case; unknown values
+    /// mean `None`
+    pub fn from_header(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "yes" => StripMode::StripPunct,
+            "compact" => StripMode::CompactWhitespace,
+            _ => StripMode::None,
+        }
+    }
+}
+
 /// Compression type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompressionType {
@@ -69,14 +143,17 @@ impl CompressionType {
     }
 }
 
-/// Number format based on version
+/// Width of the big-endian size and offset fields, based on version
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NumFmt {
+    /// 32-bit fields (v1.x)
     Uint32,
+    /// 64-bit fields (v2.0+)
     Uint64,
 }
 
 impl NumFmt {
+    /// Field width in bytes
     pub fn width(&self) -> usize {
         match self {
             NumFmt::Uint32 => 4,
@@ -93,8 +170,24 @@ pub struct DictMeta {
     pub encrypt: EncryptType,
     pub num_fmt: NumFmt,
     pub num_width: usize,
+    /// Width in bytes of the first/last word length fields in key block
+    /// info: 2 for v2.0+, 1 for v1.x
+    pub text_len_width: usize,
     pub ext: FileExt,
+    /// Never set by the parser; credentials go through
+    /// [`MdxBuilder::credentials`](crate::MdxBuilder::credentials)
+    #[deprecated(note = "unused; pass credentials to MdxBuilder::credentials")]
     pub passcode: Option<String>,
+    /// How the registration code of an encrypted key header is keyed
+    pub register_by: RegisterBy,
+    /// Key normalization from the `StripKey` header
+    pub strip_mode: StripMode,
+    /// Whether keys keep their case when compared, from the
+    /// `KeyCaseSensitive` header
+    pub key_case_sensitive: bool,
+    /// Whether the header starts with a big-endian `FE FF` byte order mark
+    /// but declares `Encoding="UTF-16LE"`; the declared encoding is used
+    pub bom_conflict: bool,
 }
 
 impl Default for DictMeta {
@@ -105,15 +198,108 @@ impl Default for DictMeta {
             encrypt: EncryptType::None,
             num_fmt: NumFmt::Uint32,
             num_width: 4,
+            text_len_width: 1,
             ext: FileExt::Mdx,
+            #[allow(deprecated)]
             passcode: None,
+            register_by: RegisterBy::DeviceId,
+            strip_mode: StripMode::StripPunct,
+            key_case_sensitive: false,
+            bom_conflict: false,
         }
     }
 }
 
+impl DictMeta {
+    /// Set the engine version and the field widths that depend on it
+    pub fn set_version(&mut self, version: f64) {
+        self.version = version;
+        if version >= 2.0 {
+            self.num_fmt = NumFmt::Uint64;
+            self.text_len_width = 2;
+        } else {
+            self.num_fmt = NumFmt::Uint32;
+            self.text_len_width = 1;
+        }
+        self.num_width = self.num_fmt.width();
+    }
+
+    /// Bytes per code unit of key text: 2 for UTF-16 (including every
+    /// MDD), 1 otherwise
+    ///
+    /// Key text lengths in the key info are counted in code units, and key
+    /// text in key blocks ends with one NUL code unit.
+    pub fn text_unit_width(&self) -> usize {
+        if matches!(self.encoding, Encoding::Utf16Le | Encoding::Utf16Be) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+/// Overview of an MDX for display before using it, returned by
+/// [`Mdx::summary`](crate::Mdx::summary)
+#[derive(Debug, Clone)]
+pub struct DictSummary {
+    /// `Title` header, if set
+    pub title: Option<String>,
+    /// About page HTML from the `Description` header, if set
+    pub description: Option<String>,
+    /// Engine version the file was generated for
+    pub version: f64,
+    /// Text encoding
+    pub encoding: Encoding,
+    /// Encryption applied to the index
+    pub encrypt: EncryptType,
+    /// Number of keywords
+    pub keyword_count: usize,
+    /// Number of key blocks
+    pub key_block_count: usize,
+    /// Number of record blocks
+    pub record_block_count: usize,
+    /// Total decompressed size of the record data
+    pub record_data_size: u64,
+}
+
+/// What the header of an MDX or MDD file declares, returned by
+/// [`read_header_only`](crate::read_header_only)
+#[derive(Debug, Clone)]
+pub struct HeaderInfo {
+    /// `Title` header, if set
+    pub title: Option<String>,
+    /// Engine version the file was generated for
+    pub version: f64,
+    /// Text encoding
+    pub encoding: Encoding,
+    /// Encryption applied to the index
+    pub encrypt: EncryptType,
+    /// Number of keywords declared in the key header, or `None` if the key
+    /// header is encrypted
+    pub keyword_count: Option<u64>,
+}
+
 /// Dictionary header attributes
 pub type DictHeader = HashMap<String, String>;
 
+/// Raw header framing as stored in the file
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMeta {
+    /// Length of the header text in bytes, from the first 4 bytes
+    pub byte_size: u32,
+    /// Header text as stored: UTF-16LE XML
+    pub raw: Vec<u8>,
+    /// Adler-32 of `raw` stored after it (little-endian in the file)
+    pub checksum: u32,
+}
+
+impl HeaderMeta {
+    /// Whether the stored checksum matches the header text
+    pub fn checksum_matches(&self) -> bool {
+        crate::utils::adler32(&self.raw) == self.checksum
+    }
+}
+
 /// Key header information
 #[derive(Debug, Clone, Default)]
 pub struct KeyHeader {
@@ -152,12 +338,35 @@ pub struct KeyInfoItem {
     pub key_block_info_index: usize,
 }
 
+/// A key block whose declared entry count disagrees with the number of keys
+/// found by splitting it at every terminator
+///
+/// Such a block has keys holding a NUL, or is damaged. Its keys are split
+/// only at terminators followed by a plausible record offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCountMismatch {
+    /// Index of the key block
+    pub key_block_idx: usize,
+    /// `key_block_entries_num` from the key block info
+    pub declared: usize,
+    /// Keys found by splitting at every terminator
+    pub scanned: usize,
+}
+
 /// Keyword item
+///
+/// Items only hold offsets into the file's record data, not references to
+/// the dictionary, so an owned copy can be fetched at any time. The offsets
+/// stay valid for any dictionary opened on the same, unchanged file; after
+/// the file is replaced, items must be taken from the new keyword list.
 #[derive(Debug, Clone)]
 pub struct KeyWordItem {
     /// Start offset in record block
     pub record_start_offset: u64,
     /// End offset in record block
+    ///
+    /// The next keyword's start offset, or 0 for the last keyword in the
+    /// file, whose record runs to the end of the last record block.
     pub record_end_offset: u64,
     /// Keyword text
     pub key_text: String,
@@ -165,6 +374,21 @@ pub struct KeyWordItem {
     pub key_block_idx: usize,
 }
 
+impl KeyWordItem {
+    /// Length of the record from the offsets, or `None` for the last
+    /// keyword, whose `record_end_offset` is the 0 sentinel
+    ///
+    /// Sizes reported by the dictionary, such as
+    /// [`Mdx::definition_size_stats`](crate::Mdx::definition_size_stats),
+    /// fill in the last length from the record block sizes.
+    pub fn record_len(&self) -> Option<u64> {
+        match self.record_end_offset {
+            0 => None,
+            end => Some(end.saturating_sub(self.record_start_offset)),
+        }
+    }
+}
+
 /// Record header information
 #[derive(Debug, Clone, Default)]
 pub struct RecordHeader {
@@ -200,6 +424,122 @@ pub struct LookupResult {
     pub definition: String,
 }
 
+/// Lookup result that also tells how the definition was decoded, returned
+/// by [`Mdx::try_lookup`](crate::Mdx::try_lookup)
+#[derive(Debug, Clone)]
+pub struct DecodedLookup {
+    /// The keyword
+    pub key_text: String,
+    /// The definition
+    pub definition: String,
+    /// Encoding the definition was decoded with
+    pub encoding: Encoding,
+    /// Whether the bytes weren't valid in the dictionary's encoding and
+    /// were decoded as UTF-8 with replacement characters instead
+    pub lossy: bool,
+}
+
+/// Coarse kind of an MDD resource, returned by
+/// [`Mdd::media_category`](crate::Mdd::media_category)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaCategory {
+    Image,
+    Audio,
+    Video,
+    Font,
+    StyleSheet,
+    Script,
+    Html,
+    Other,
+}
+
+impl MediaCategory {
+    /// Category of a MIME type such as `image/png`
+    pub fn from_mime_type(mime_type: &str) -> Self {
+        match mime_type {
+            "text/css" => MediaCategory::StyleSheet,
+            "application/javascript" | "text/javascript" => MediaCategory::Script,
+            "text/html" => MediaCategory::Html,
+            "application/vnd.ms-fontobject" => MediaCategory::Font,
+            _ => match mime_type.split('/').next() {
+                Some("image") => MediaCategory::Image,
+                Some("audio") => MediaCategory::Audio,
+                Some("video") => MediaCategory::Video,
+                Some("font") => MediaCategory::Font,
+                _ => MediaCategory::Other,
+            },
+        }
+    }
+}
+
+/// Form of the snippet returned by [`Mdx::preview`](crate::Mdx::preview)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewFormat {
+    /// Tags removed and entities decoded, for terminals and speech
+    Text,
+    /// Markup kept, cut between tags with open elements closed
+    Html,
+}
+
+/// How [`Mdx::lookup_with_fallback_strip`](crate::Mdx::lookup_with_fallback_strip)
+/// matched a word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    /// A key identical to the word
+    Exact,
+    /// A key equal to the word after stripping, as `lookup` matches
+    Stripped,
+    /// A key equal to the word after stripping and lowercasing both, even
+    /// in case-sensitive dictionaries
+    CaseFolded,
+    /// The headword `MdxBuilder::aliases` maps the word to
+    Alias,
+}
+
+/// One probe of the binary search recorded by `Mdx::explain_lookup`
+#[derive(Debug, Clone)]
+pub struct LookupStep {
+    /// Index into the keyword list
+    pub index: usize,
+    /// Keyword as stored
+    pub key_text: String,
+    /// Keyword after stripping
+    pub stripped_key: String,
+    /// How the stripped query compared to `stripped_key`
+    pub ordering: std::cmp::Ordering,
+}
+
+/// Binary search path of a lookup, for finding out why a word isn't found
+#[derive(Debug, Clone)]
+pub struct LookupTrace {
+    /// Query as given
+    pub query: String,
+    /// Query after stripping
+    pub stripped_query: String,
+    /// Probes in search order
+    pub steps: Vec<LookupStep>,
+    /// Matching keyword, if any
+    pub found: Option<KeyWordItem>,
+}
+
+/// Where a definition's bytes come from, returned by `Mdx::lookup_debug`
+#[derive(Debug, Clone)]
+pub struct LookupDebug {
+    /// Matching keyword
+    pub item: KeyWordItem,
+    /// Index of the record block holding the record
+    pub block_index: usize,
+    /// Start of the record within the decompressed block
+    pub start: usize,
+    /// End of the record within the decompressed block, exclusive
+    pub end: usize,
+    /// Compression of the block, or `None` for a tag only a custom
+    /// decompressor knows
+    pub compression: Option<CompressionType>,
+    /// The record as stored, before decoding
+    pub raw: Vec<u8>,
+}
+
 /// Fuzzy search result
 #[derive(Debug, Clone)]
 pub struct FuzzyWord {
@@ -208,3 +548,165 @@ pub struct FuzzyWord {
     /// Edit distance
     pub edit_distance: usize,
 }
+
+/// Distribution of record sizes in bytes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeStats {
+    /// Number of records
+    pub count: usize,
+    /// Smallest record
+    pub min: u64,
+    /// Largest record
+    pub max: u64,
+    /// Sum of all records
+    pub total: u64,
+    /// Mean record size
+    pub mean: f64,
+    /// Median record size (mean of the two middle sizes for an even count)
+    pub median: f64,
+}
+
+impl SizeStats {
+    /// Compute stats from a list of sizes; all zero when empty
+    pub(crate) fn from_sizes(mut sizes: Vec<u64>) -> Self {
+        if sizes.is_empty() {
+            return SizeStats::default();
+        }
+        sizes.sort_unstable();
+
+        let count = sizes.len();
+        let total: u64 = sizes.iter().sum();
+        let median = if count.is_multiple_of(2) {
+            (sizes[count / 2 - 1] + sizes[count / 2]) as f64 / 2.0
+        } else {
+            sizes[count / 2] as f64
+        };
+
+        SizeStats {
+            count,
+            min: sizes[0],
+            max: sizes[count - 1],
+            total,
+            mean: total as f64 / count as f64,
+            median,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_ext_from_path() {
+        use std::path::Path;
+        assert_eq!(FileExt::from_path(Path::new("a/b.mdx")), Some(FileExt::Mdx));
+        assert_eq!(FileExt::from_path(Path::new("B.MDD")), Some(FileExt::Mdd));
+        assert_eq!(FileExt::from_path(Path::new("b.txt")), None);
+        assert_eq!(FileExt::from_path(Path::new("mdx")), None);
+        assert_eq!(FileExt::from_path(Path::new("b.MDD.1")), Some(FileExt::Mdd));
+        assert_eq!(FileExt::from_path(Path::new("b.mdx.1")), None);
+        assert_eq!(FileExt::from_path(Path::new("b.1")), None);
+    }
+
+    #[test]
+    fn test_strip_mode_from_header() {
+        assert_eq!(StripMode::from_header("Yes"), StripMode::StripPunct);
+        assert_eq!(StripMode::from_header("No"), StripMode::None);
+        assert_eq!(StripMode::from_header("no"), StripMode::None);
+        assert_eq!(
+            StripMode::from_header("Compact"),
+            StripMode::CompactWhitespace
+        );
+        assert_eq!(StripMode::from_header("bogus"), StripMode::None);
+    }
+
+    #[test]
+    fn test_size_stats() {
+        assert_eq!(SizeStats::from_sizes(Vec::new()), SizeStats::default());
+
+        let stats = SizeStats::from_sizes(vec![7, 1, 4]);
+        assert_eq!(
+            (stats.count, stats.min, stats.max, stats.total),
+            (3, 1, 7, 12)
+        );
+        assert_eq!(stats.mean, 4.0);
+        assert_eq!(stats.median, 4.0);
+
+        let stats = SizeStats::from_sizes(vec![10, 1, 2, 3]);
+        assert_eq!(stats.median, 2.5);
+    }
+
+    #[test]
+    fn test_widths_per_version() {
+        let mut meta = DictMeta::default();
+
+        meta.set_version(1.2);
+        assert_eq!(meta.num_fmt, NumFmt::Uint32);
+        assert_eq!(meta.num_width, 4);
+        assert_eq!(meta.text_len_width, 1);
+
+        meta.set_version(2.0);
+        assert_eq!(meta.num_fmt, NumFmt::Uint64);
+        assert_eq!(meta.num_width, 8);
+        assert_eq!(meta.text_len_width, 2);
+
+        meta.set_version(3.0);
+        assert_eq!(meta.num_width, 8);
+        assert_eq!(meta.text_len_width, 2);
+    }
+
+    #[test]
+    fn test_text_unit_width() {
+        let mut meta = DictMeta::default();
+        assert_eq!(meta.text_unit_width(), 1);
+
+        meta.encoding = Encoding::Utf16Le;
+        for version in [1.2, 2.0] {
+            meta.set_version(version);
+            assert_eq!(meta.text_unit_width(), 2);
+        }
+    }
+
+    #[test]
+    fn test_media_category_from_mime_type() {
+        assert_eq!(
+            MediaCategory::from_mime_type("image/svg+xml"),
+            MediaCategory::Image
+        );
+        assert_eq!(
+            MediaCategory::from_mime_type("audio/speex"),
+            MediaCategory::Audio
+        );
+        assert_eq!(
+            MediaCategory::from_mime_type("font/woff2"),
+            MediaCategory::Font
+        );
+        assert_eq!(
+            MediaCategory::from_mime_type("application/vnd.ms-fontobject"),
+            MediaCategory::Font
+        );
+        assert_eq!(
+            MediaCategory::from_mime_type("text/css"),
+            MediaCategory::StyleSheet
+        );
+        assert_eq!(
+            MediaCategory::from_mime_type("application/octet-stream"),
+            MediaCategory::Other
+        );
+    }
+
+    #[test]
+    fn test_keyword_record_len() {
+        let mut item = KeyWordItem {
+            record_start_offset: 10,
+            record_end_offset: 25,
+            key_text: "word".to_string(),
+            key_block_idx: 0,
+        };
+        assert_eq!(item.record_len(), Some(15));
+
+        item.record_end_offset = 0;
+        assert_eq!(item.record_len(), None);
+    }
+}
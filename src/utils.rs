@@ -1,9 +1,8 @@
 //! Utility functions for mdict parsing
 
 use crate::error::{MdictError, Result};
-use crate::types::Encoding;
-use encoding_rs::{BIG5, GB18030, UTF_16LE};
-use regex::Regex;
+use crate::types::{Encoding, FileExt, RegisterBy, StripMode};
+use encoding_rs::{DecoderResult, BIG5, GB18030, UTF_16BE, UTF_16LE, UTF_8};
 use std::collections::HashMap;
 
 /// Read big-endian u8 from bytes
@@ -63,6 +62,16 @@ pub fn decode_string(bytes: &[u8], encoding: Encoding) -> Result<String> {
                 Ok(result.into_owned())
             }
         }
+        Encoding::Utf16Be => {
+            let (result, _, had_errors) = UTF_16BE.decode(bytes);
+            if had_errors {
+                Err(MdictError::EncodingError(
+                    "UTF-16BE decode error".to_string(),
+                ))
+            } else {
+                Ok(result.into_owned())
+            }
+        }
         Encoding::Gb18030 => {
             let (result, _, had_errors) = GB18030.decode(bytes);
             if had_errors {
@@ -84,7 +93,31 @@ pub fn decode_string(bytes: &[u8], encoding: Encoding) -> Result<String> {
     }
 }
 
+/// Offset of the first byte in `bytes` that isn't valid in `encoding`, or
+/// `None` if all of it decodes
+pub fn first_invalid_byte(bytes: &[u8], encoding: Encoding) -> Option<usize> {
+    let encoding = match encoding {
+        Encoding::Utf8 => UTF_8,
+        Encoding::Utf16Le => UTF_16LE,
+        Encoding::Utf16Be => UTF_16BE,
+        Encoding::Gb18030 => GB18030,
+        Encoding::Big5 => BIG5,
+    };
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut out =
+        String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(bytes.len())?);
+    match decoder.decode_to_string_without_replacement(bytes, &mut out, true) {
+        (DecoderResult::Malformed(bad, after), read) => {
+            Some(read.saturating_sub(bad as usize + after as usize))
+        }
+        _ => None,
+    }
+}
+
 /// Decode UTF-16LE bytes to string
+///
+/// A leading byte order mark is honored and dropped, so a header starting
+/// with `FE FF` is decoded as UTF-16BE.
 pub fn decode_utf16le(bytes: &[u8]) -> Result<String> {
     let (result, _, had_errors) = UTF_16LE.decode(bytes);
     if had_errors {
@@ -97,37 +130,283 @@ pub fn decode_utf16le(bytes: &[u8]) -> Result<String> {
 }
 
 /// Unescape HTML entities
+///
+/// Handles the five named XML entities and decimal (`&#233;`) and hex
+/// (`&#x2014;`) character references. Invalid references are kept as is.
+/// Text is decoded in one left-to-right pass, so decoded output is never
+/// decoded again: `&amp;lt;` and `&#38;lt;` both give `&lt;`.
 pub fn unescape_entities(text: &str) -> String {
-    text.replace("&lt;", "<")
-        .replace("&gt;", ">")
-        .replace("&amp;", "&")
-        .replace("&quot;", "\"")
-        .replace("&apos;", "'")
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        match decode_entity(rest) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Decode the entity or character reference `text` starts with, returning
+/// the character and the reference's length
+fn decode_entity(text: &str) -> Option<(char, usize)> {
+    // The longest reference is `&#x10FFFF;`
+    let end = text.bytes().skip(1).take(9).position(|b| b == b';')?;
+    let name = &text[1..1 + end];
+    let c = match name {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        "apos" => '\'',
+        _ => {
+            let digits = name.strip_prefix('#')?;
+            let code = match digits.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) => {
+                    digits.parse().ok()?
+                }
+                None => return None,
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some((c, name.len() + 2))
+}
+
+/// Parse a `GeneratedByEngineVersion` value from its first two dotted
+/// components, so `"2.0.0"` and `"2"` both give 2.0
+///
+/// `None` if there is no numeric major version.
+pub fn parse_engine_version(version: &str) -> Option<f64> {
+    let mut parts = version.trim().split('.');
+    let major: u32 = parts.next()?.trim().parse().ok()?;
+    let minor: String = parts
+        .next()
+        .unwrap_or("")
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    if minor.is_empty() {
+        Some(major as f64)
+    } else {
+        format!("{}.{}", major, minor).parse().ok()
+    }
+}
+
+/// Parse a header flag: `Yes`/`No`, `true`/`false` or `1`/`0`, ignoring
+/// case and surrounding space
+pub fn parse_header_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
 }
 
 /// Parse header XML text to attributes
+///
+/// The header is normally a single `<Dictionary .../>` (MDX) or
+/// `<Library_Data .../>` (MDD) element, but real files are not always
+/// well-formed: the closing `>` may be missing, stray tokens may sit between
+/// attributes, and values such as `Description` may contain raw `&` or `"`.
+/// Attributes are therefore scanned by hand: a quote only closes a value when
+/// it is followed by another attribute, the end of the tag or the end of text.
 pub fn parse_header(header_text: &str) -> Result<HashMap<String, String>> {
     let mut header_attr: HashMap<String, String> = HashMap::new();
 
-    // Match all attributes in format: key="value"
-    let re = Regex::new(r#"(\w+)="((?:[^"\\]|\\.)*)""#)
-        .map_err(|e| MdictError::HeaderParseError(e.to_string()))?;
+    let body = header_body(header_text.trim_end_matches('\0'));
+    let bytes = body.as_bytes();
+    let len = bytes.len();
+    let mut pos = 0;
+
+    while pos < len {
+        // Attribute name; anything else is a stray byte and is skipped
+        let name_start = pos;
+        while pos < len && is_attr_name_byte(bytes[pos]) {
+            pos += 1;
+        }
+        if pos == name_start {
+            pos += 1;
+            continue;
+        }
+        let key = &body[name_start..pos];
+
+        // A name without `=` is a stray attribute (e.g. a bare flag)
+        pos = skip_whitespace(bytes, pos);
+        if pos >= len || bytes[pos] != b'=' {
+            continue;
+        }
+        pos = skip_whitespace(bytes, pos + 1);
+        if pos >= len {
+            break;
+        }
+
+        let raw_value = match bytes[pos] {
+            quote @ (b'"' | b'\'') => {
+                let value_start = pos + 1;
+                match find_closing_quote(bytes, value_start, quote) {
+                    Some(end) => {
+                        pos = end + 1;
+                        &body[value_start..end]
+                    }
+                    None => {
+                        // Unterminated value: take the rest of the text
+                        pos = len;
+                        body[value_start..]
+                            .trim_end()
+                            .trim_end_matches('>')
+                            .trim_end_matches('/')
+                    }
+                }
+            }
+            _ => {
+                // Unquoted value runs to the next whitespace or tag end
+                let value_start = pos;
+                while pos < len
+                    && !bytes[pos].is_ascii_whitespace()
+                    && bytes[pos] != b'>'
+                    && !bytes[pos..].starts_with(b"/>")
+                {
+                    pos += 1;
+                }
+                &body[value_start..pos]
+            }
+        };
 
-    for cap in re.captures_iter(header_text) {
-        let key = cap
-            .get(1)
-            .map(|m| m.as_str().to_string())
-            .unwrap_or_default();
-        let value = cap
-            .get(2)
-            .map(|m| unescape_entities(m.as_str()))
-            .unwrap_or_default();
-        header_attr.insert(key, value);
+        let value = raw_value.replace("\\\"", "\"").replace("\\'", "'");
+        header_attr.insert(key.to_string(), unescape_entities(&value));
     }
 
     Ok(header_attr)
 }
 
+/// File type named by the header's root element: `<Dictionary>` for MDX,
+/// `<Library_Data>` for MDD, or `None` for anything else
+pub(crate) fn header_file_ext(text: &str) -> Option<FileExt> {
+    let text = text.trim_start_matches(|c: char| c == '\u{feff}' || c.is_whitespace());
+    if text.starts_with("<Dictionary") {
+        Some(FileExt::Mdx)
+    } else if text.starts_with("<Library_Data") {
+        Some(FileExt::Mdd)
+    } else {
+        None
+    }
+}
+
+/// Return the attribute part of the `<Dictionary>`/`<Library_Data>` element,
+/// or the whole text if no known wrapper is present
+fn header_body(text: &str) -> &str {
+    for tag in ["<Dictionary", "<Library_Data"] {
+        if let Some(pos) = text.find(tag) {
+            return &text[pos + tag.len()..];
+        }
+    }
+    text
+}
+
+fn is_attr_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+        pos += 1;
+    }
+    pos
+}
+
+/// Find the quote that really closes an attribute value starting at `start`
+///
+/// Values such as `Description` may hold raw HTML whose own attributes
+/// look like header attributes (`<font color="red" size="2">`), so quotes
+/// inside an HTML tag within the value never close it. A `<` only counts
+/// as a tag while its quotes open after `=` and come in pairs, so a bare
+/// `a<b"` still ends the value.
+fn find_closing_quote(bytes: &[u8], start: usize, quote: u8) -> Option<usize> {
+    let mut i = start;
+    let mut in_html_tag = false;
+    let mut in_tag_value = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => {
+                i += 2;
+                continue;
+            }
+            b'<' if bytes
+                .get(i + 1)
+                .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'/') =>
+            {
+                in_html_tag = true
+            }
+            b'>' => {
+                in_html_tag = false;
+                in_tag_value = false;
+            }
+            b if b == quote && in_html_tag && in_tag_value => in_tag_value = false,
+            b if b == quote && in_html_tag && follows_equals(&bytes[start..i]) => {
+                in_tag_value = true
+            }
+            b if b == quote && quote_closes_value(bytes, i + 1) => {
+                return Some(i);
+            }
+            // Not a tag after all
+            b if b == quote => in_html_tag = false,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Whether `text` ends with `=` and optional whitespace
+fn follows_equals(text: &[u8]) -> bool {
+    text.iter().rev().find(|b| !b.is_ascii_whitespace()) == Some(&b'=')
+}
+
+/// A quote closes a value if what follows is the end of the text, the end of
+/// the tag, or whitespace and then another `name=`
+fn quote_closes_value(bytes: &[u8], after: usize) -> bool {
+    let pos = skip_whitespace(bytes, after);
+    if pos >= bytes.len() {
+        return true;
+    }
+
+    let rest = &bytes[pos..];
+    let tag_end = if rest.starts_with(b"/>") {
+        Some(2)
+    } else if rest.starts_with(b">") {
+        Some(1)
+    } else {
+        None
+    };
+    if let Some(n) = tag_end {
+        return rest[n..].iter().all(|b| b.is_ascii_whitespace());
+    }
+
+    if pos == after || !is_attr_name_byte(bytes[pos]) {
+        return false;
+    }
+    let mut name_end = pos;
+    while name_end < bytes.len() && is_attr_name_byte(bytes[name_end]) {
+        name_end += 1;
+    }
+    let eq = skip_whitespace(bytes, name_end);
+    eq < bytes.len() && bytes[eq] == b'='
+}
+
 /// Calculate Levenshtein distance between two strings
 pub fn levenshtein_distance(a: &str, b: &str) -> usize {
     let a_chars: Vec<char> = a.chars().collect();
@@ -167,30 +446,713 @@ pub fn levenshtein_distance(a: &str, b: &str) -> usize {
     dp[m][n]
 }
 
-/// Strip punctuation and normalize key for comparison
-pub fn strip_key(key: &str, is_mdd: bool) -> String {
-    let mut result = key.to_lowercase();
+/// Characters [`strip_key`] removes from MDD keys, after the extension
+const MDD_STRIP_CHARS: &[char] = &['(', ')', '.', ',', ' ', '\'', '/', '@'];
+
+/// Characters [`strip_key`] removes from MDX keys
+const MDX_STRIP_CHARS: &[char] = &[
+    '(', ')', '.', ',', '-', '&', ' ', '\'', '/', '\\', '@', '_', '$', '!',
+];
+
+/// Strip punctuation from a key for comparison
+///
+/// Case is left untouched; folding it depends on the `KeyCaseSensitive`
+/// header and is done by the caller. `mdd_underscore_as_bang` only applies
+/// to MDD keys.
+pub fn strip_key(key: &str, is_mdd: bool, mdd_underscore_as_bang: bool) -> String {
+    let mut result = key.to_string();
 
     if is_mdd {
         // For MDD: remove extension and special characters
         if let Some(pos) = result.rfind('.') {
             result = result[..pos].to_string();
         }
-        result = result.replace(['(', ')', '.', ',', ' ', '\'', '/', '@'], "");
-        result = result.replace('_', "!");
+        result = result.replace(MDD_STRIP_CHARS, "");
+
+        // MDict sorts `_` as `!` in resource keys
+        if mdd_underscore_as_bang {
+            result = result.replace('_', "!");
+        }
     } else {
         // For MDX: remove punctuation
-        result = result.replace(
-            [
-                '(', ')', '.', ',', '-', '&', ' ', '\'', '/', '\\', '@', '_', '$', '!',
-            ],
-            "",
-        );
+        result = result.replace(MDX_STRIP_CHARS, "");
     }
 
     result.trim().to_string()
 }
 
+/// Translate a shell-style glob into an anchored regex pattern
+///
+/// `*` matches any run of characters and `?` any single character; every
+/// other character matches itself.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    let mut buf = [0u8; 4];
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(c.encode_utf8(&mut buf))),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+/// Collapse each run of whitespace to a single space and trim the ends
+pub fn compact_whitespace(key: &str) -> String {
+    key.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The characters of a key normalized like
+/// [`MdictBase::strip`](crate::mdict_base::MdictBase::strip), produced
+/// lazily
+///
+/// Binary searches compare keys through this instead of stripping each
+/// probed key into a new `String`.
+///
+/// Case is folded one character at a time with [`char::to_lowercase`],
+/// while `strip` uses [`str::to_lowercase`]. They differ only for the
+/// context-sensitive final sigma: stripped `ΟΔΟΣ` ends in `ς`, but this
+/// yields `σ`. Keys with an uppercase `Σ` can therefore sort differently
+/// here than in the loaded keyword list;
+/// [`Mdx::is_sorted_for_lookup`](crate::Mdx::is_sorted_for_lookup)
+/// reports such dictionaries.
+#[derive(Clone)]
+pub struct StrippedChars<'a> {
+    chars: std::str::Chars<'a>,
+    mode: StripMode,
+    is_mdd: bool,
+    underscore_as_bang: bool,
+    lowercase: bool,
+    /// Rest of the lowercase form of the last character
+    lowered: Option<std::char::ToLowercase>,
+    /// Whether a non-whitespace character has been produced
+    started: bool,
+    /// Whether the current whitespace run is known to be followed by more
+    /// text, so isn't trimmed
+    in_gap: bool,
+}
+
+impl<'a> StrippedChars<'a> {
+    /// Normalize `key` with `mode`, folding case if `lowercase` is set
+    pub fn new(
+        key: &'a str,
+        mode: StripMode,
+        is_mdd: bool,
+        underscore_as_bang: bool,
+        lowercase: bool,
+    ) -> Self {
+        let key = match mode {
+            StripMode::StripPunct if is_mdd => key.rfind('.').map_or(key, |pos| &key[..pos]),
+            _ => key,
+        };
+        StrippedChars {
+            chars: key.chars(),
+            mode,
+            is_mdd,
+            underscore_as_bang,
+            lowercase,
+            lowered: None,
+            started: false,
+            in_gap: false,
+        }
+    }
+
+    /// Next character with punctuation removed and case folded, before
+    /// whitespace is trimmed or compacted
+    fn next_untrimmed(&mut self) -> Option<char> {
+        if let Some(c) = self.lowered.as_mut().and_then(Iterator::next) {
+            return Some(c);
+        }
+        loop {
+            let mut c = self.chars.next()?;
+            if self.mode == StripMode::StripPunct {
+                let removed = if self.is_mdd {
+                    MDD_STRIP_CHARS
+                } else {
+                    MDX_STRIP_CHARS
+                };
+                if removed.contains(&c) {
+                    continue;
+                }
+                if self.is_mdd && self.underscore_as_bang && c == '_' {
+                    c = '!';
+                }
+            }
+            if !self.lowercase {
+                return Some(c);
+            }
+            let mut lowered = c.to_lowercase();
+            let first = lowered.next();
+            self.lowered = Some(lowered);
+            return first;
+        }
+    }
+}
+
+impl Iterator for StrippedChars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let compact = self.mode == StripMode::CompactWhitespace;
+        loop {
+            let c = self.next_untrimmed()?;
+            if !c.is_whitespace() {
+                self.started = true;
+                self.in_gap = false;
+                return Some(c);
+            }
+            if !self.started || (self.in_gap && compact) {
+                continue;
+            }
+            if self.in_gap {
+                return Some(c);
+            }
+
+            // A new whitespace run: drop it if nothing but whitespace follows
+            let mut ahead = self.clone();
+            if !std::iter::from_fn(|| ahead.next_untrimmed()).any(|c| !c.is_whitespace()) {
+                *self = ahead;
+                return None;
+            }
+            self.in_gap = true;
+            return Some(if compact { ' ' } else { c });
+        }
+    }
+}
+
+/// Rewrite the resource references in `html`: `src` attribute values and
+/// CSS `url(...)` arguments
+///
+/// Attributes merely ending in `src`, such as `data-src`, aren't
+/// references. `replace` gets each value without quotes and returns its
+/// replacement, or `None` to keep it.
+pub fn rewrite_resource_refs(html: &str, replace: impl FnMut(&str) -> Option<String>) -> String {
+    rewrite_refs(html, &["src=", "url("], replace)
+}
+
+/// Rewrite every reference in `html` to a resource inside the dictionary
+/// so it points below `base` instead
+///
+/// `src` and `href` attributes and CSS `url(...)` are rewritten:
+/// `dict.css`, `/img/a.png` and `sound://a.mp3` become `{base}dict.css`,
+/// `{base}img/a.png` and `{base}a.mp3`. Web, `data:`, `mailto:` and
+/// `entry://` links and fragments are left alone (see [`is_internal_ref`]).
+pub fn rewrite_internal_links(html: &str, base: &str) -> String {
+    rewrite_refs(html, &["src=", "href=", "url("], |value| {
+        is_internal_ref(value).then(|| format!("{}{}", base, internal_path(value)))
+    })
+}
+
+/// Whether an `href`/`src` value names a resource in the dictionary's MDD
+/// rather than a web page (including a protocol-relative `//host/...`),
+/// `data:` URI, email address, `entry://` link to another headword or
+/// fragment
+pub fn is_internal_ref(value: &str) -> bool {
+    let lower = value.trim().to_ascii_lowercase();
+    !lower.is_empty()
+        && !lower.starts_with('#')
+        && !lower.starts_with("//")
+        && ![
+            "http://",
+            "https://",
+            "data:",
+            "mailto:",
+            "entry://",
+            "javascript:",
+        ]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+}
+
+/// Resource path of an internal reference: without a `sound://` or
+/// `file://` scheme and leading slashes
+fn internal_path(value: &str) -> &str {
+    let value = value.trim();
+    let value = ["sound://", "file://"]
+        .iter()
+        .find_map(|scheme| {
+            value
+                .get(..scheme.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(scheme))
+                .map(|_| &value[scheme.len()..])
+        })
+        .unwrap_or(value);
+    value.trim_start_matches(['/', '\\'])
+}
+
+/// Replace the MDict wrapper tags of a definition: each `<link>` to an
+/// internal style sheet becomes a `<style>` element holding what `css`
+/// returns for its path, or is removed if it returns `None`; other
+/// internal `<link>`s and each `<script>` loading an internal file are
+/// removed
+///
+/// A link is a style sheet if its `rel` contains `stylesheet` or its path
+/// ends in `.css`, so icons and preloaded fonts are never passed to `css`.
+pub fn strip_wrapper_tags(html: &str, mut css: impl FnMut(&str) -> Option<String>) -> String {
+    let tokens = html_tokens(html);
+    let consumed: usize = tokens
+        .iter()
+        .map(|token| match token {
+            HtmlToken::Tag(s) | HtmlToken::Text(s) => s.len(),
+        })
+        .sum();
+
+    let mut out = String::with_capacity(html.len());
+    let mut in_script = false;
+    for token in &tokens {
+        let text = match token {
+            HtmlToken::Tag(tag) => {
+                let (name, closing) = tag_name(tag);
+                if in_script {
+                    in_script = !(closing && name == "script");
+                    continue;
+                }
+                let internal_ref = |attr: &str| {
+                    let lower = tag.to_ascii_lowercase();
+                    next_ref(&lower, 0, &[attr])
+                        .map(|(start, end)| &tag[start..end])
+                        .filter(|value| is_internal_ref(value))
+                };
+                match (name.as_str(), closing) {
+                    ("link", false) => {
+                        if let Some(href) = internal_ref("href=") {
+                            let path = internal_path(href);
+                            let lower = tag.to_ascii_lowercase();
+                            let is_sheet =
+                                next_ref(&lower, 0, &["rel="]).is_some_and(|(start, end)| {
+                                    lower[start..end].contains("stylesheet")
+                                }) || path.to_ascii_lowercase().ends_with(".css");
+                            if let Some(sheet) = is_sheet.then(|| css(path)).flatten() {
+                                out.push_str("<style>");
+                                out.push_str(&sheet);
+                                out.push_str("</style>");
+                            }
+                            continue;
+                        }
+                    }
+                    ("script", false) if internal_ref("src=").is_some() => {
+                        in_script = !tag.ends_with("/>");
+                        continue;
+                    }
+                    _ => {}
+                }
+                tag
+            }
+            HtmlToken::Text(_) if in_script => continue,
+            HtmlToken::Text(text) => text,
+        };
+        out.push_str(text);
+    }
+    if !in_script {
+        out.push_str(&html[consumed..]);
+    }
+    out
+}
+
+/// Rewrite the values found after any of `needles` (lowercase `attr=`
+/// or `url(`) with `replace`
+fn rewrite_refs(
+    html: &str,
+    needles: &[&str],
+    mut replace: impl FnMut(&str) -> Option<String>,
+) -> String {
+    // ASCII lowercasing keeps byte offsets, so matches index into `html`
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut pos = 0;
+
+    while let Some((start, end)) = next_ref(&lower, pos, needles) {
+        out.push_str(&html[pos..start]);
+        let value = &html[start..end];
+        out.push_str(&replace(value).unwrap_or_else(|| value.to_string()));
+        pos = end;
+    }
+
+    out.push_str(&html[pos..]);
+    out
+}
+
+/// Byte range of the next non-empty value after one of `needles` at or
+/// after `pos` in lowercased `html`
+fn next_ref(html: &str, mut pos: usize, needles: &[&str]) -> Option<(usize, usize)> {
+    loop {
+        let (mut start, is_url) = needles
+            .iter()
+            .filter_map(|needle| {
+                let i = find_needle(html, pos, needle)?;
+                Some((i + needle.len(), *needle == "url("))
+            })
+            .min()?;
+
+        let bytes = html.as_bytes();
+        if is_url {
+            while bytes.get(start).is_some_and(|b| b.is_ascii_whitespace()) {
+                start += 1;
+            }
+        }
+        let end = match bytes.get(start) {
+            Some(&quote @ (b'"' | b'\'')) => {
+                start += 1;
+                html[start..]
+                    .find(quote as char)
+                    .map_or(html.len(), |i| start + i)
+            }
+            _ => {
+                let stop = |b: u8| b.is_ascii_whitespace() || b == b'>' || (is_url && b == b')');
+                bytes[start..]
+                    .iter()
+                    .position(|&b| stop(b))
+                    .map_or(html.len(), |i| start + i)
+            }
+        };
+
+        if end > start {
+            return Some((start, end));
+        }
+        pos = start;
+    }
+}
+
+/// Offset of the first `needle` at or after `pos` in `html`
+///
+/// An attribute needle such as `src=` only counts after whitespace, a
+/// quote closing the previous value or a `/`, so `data-src=` isn't taken
+/// for `src=`.
+fn find_needle(html: &str, mut pos: usize, needle: &str) -> Option<usize> {
+    loop {
+        let i = pos + html[pos..].find(needle)?;
+        let at_boundary = !needle.ends_with('=')
+            || i.checked_sub(1).is_some_and(|before| {
+                let b = html.as_bytes()[before];
+                b.is_ascii_whitespace() || matches!(b, b'"' | b'\'' | b'/')
+            });
+        if at_boundary {
+            return Some(i);
+        }
+        pos = i + 1;
+    }
+}
+
+/// Elements whose content isn't displayed text
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// Elements that never have a closing tag
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements that separate words when their tags are removed
+const BLOCK_ELEMENTS: [&str; 22] = [
+    "address",
+    "article",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "section",
+    "table",
+    "td",
+    "tr",
+];
+
+/// A tag (including comments and doctypes) or a run of text in HTML
+enum HtmlToken<'a> {
+    Tag(&'a str),
+    Text(&'a str),
+}
+
+/// Split `html` into tags and text; a final `<` without a closing `>` is
+/// dropped
+fn html_tokens(html: &str) -> Vec<HtmlToken<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let Some(end) = rest.find('>') else {
+                break;
+            };
+            tokens.push(HtmlToken::Tag(&rest[..=end]));
+            rest = &rest[end + 1..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            tokens.push(HtmlToken::Text(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+/// Lowercased element name of a tag, and whether it is a closing tag;
+/// the name is empty for comments, doctypes and processing instructions
+fn tag_name(tag: &str) -> (String, bool) {
+    let inner = &tag[1..];
+    let (inner, closing) = match inner.strip_prefix('/') {
+        Some(inner) => (inner, true),
+        None => (inner, false),
+    };
+    let name = inner
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    (name, closing)
+}
+
+/// Scan `tokens`, dropping `<script>`/`<style>` elements and passing the
+/// rest to `visit`, which returns false to stop
+fn visit_displayed<'a>(
+    tokens: &[HtmlToken<'a>],
+    mut visit: impl FnMut(&HtmlToken<'a>, &str, bool) -> bool,
+) {
+    let mut skipping: Option<String> = None;
+    for token in tokens {
+        let (name, closing) = match token {
+            HtmlToken::Tag(tag) => tag_name(tag),
+            HtmlToken::Text(_) => (String::new(), false),
+        };
+        if let Some(raw) = &skipping {
+            if closing && *raw == name {
+                skipping = None;
+            }
+            continue;
+        }
+        if matches!(token, HtmlToken::Tag(_)) && !closing && RAW_TEXT_ELEMENTS.contains(&&*name) {
+            skipping = Some(name);
+            continue;
+        }
+        if !visit(token, &name, closing) {
+            return;
+        }
+    }
+}
+
+/// Convert an HTML definition to plain text
+///
+/// Tags are removed, with block elements such as `<p>` and `<br>`
+/// separating words, `<script>` and `<style>` contents are dropped,
+/// entities are decoded and whitespace is collapsed.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    visit_displayed(&html_tokens(html), |token, name, _| {
+        match token {
+            HtmlToken::Tag(_) if BLOCK_ELEMENTS.contains(&name) => text.push(' '),
+            HtmlToken::Tag(_) => {}
+            HtmlToken::Text(t) => text.push_str(t),
+        }
+        true
+    });
+    compact_whitespace(&unescape_entities(&text).replace('\0', " "))
+}
+
+/// Cut `text` to at most `max_chars` characters, adding `…` if anything
+/// was removed
+pub fn truncate_text(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// Cut HTML to at most `max_chars` characters of text without splitting a
+/// tag or entity
+///
+/// Tags are kept, elements left open at the cut are closed, and `…` is
+/// added if any text was removed. `<script>` and `<style>` elements are
+/// dropped; an entity counts as one character.
+pub fn truncate_html(html: &str, max_chars: usize) -> String {
+    let mut out = String::with_capacity(html.len().min(max_chars * 4));
+    let mut open: Vec<String> = Vec::new();
+    let mut used = 0;
+    let mut truncated = false;
+
+    visit_displayed(&html_tokens(html), |token, name, closing| {
+        match token {
+            HtmlToken::Tag(tag) if closing => {
+                // Stray closing tags are dropped
+                if let Some(i) = open.iter().rposition(|n| n == name) {
+                    open.truncate(i);
+                    out.push_str(tag);
+                }
+            }
+            HtmlToken::Tag(tag) => {
+                let self_closing = tag.ends_with("/>") || VOID_ELEMENTS.contains(&name);
+                if !name.is_empty() && !self_closing {
+                    open.push(name.to_string());
+                }
+                out.push_str(tag);
+            }
+            HtmlToken::Text(text) => {
+                let mut rest = *text;
+                while let Some(c) = rest.chars().next() {
+                    if used == max_chars {
+                        truncated = true;
+                        return false;
+                    }
+                    let len = match rest.find(';') {
+                        Some(end) if c == '&' && is_entity(&rest[1..end]) => end + 1,
+                        _ => c.len_utf8(),
+                    };
+                    out.push_str(&rest[..len]);
+                    rest = &rest[len..];
+                    used += 1;
+                }
+            }
+        }
+        true
+    });
+
+    if truncated {
+        out.truncate(out.trim_end().len());
+        out.push('…');
+    }
+    for name in open.iter().rev() {
+        out.push_str(&format!("</{}>", name));
+    }
+    out
+}
+
+/// Whether `name` is the body of an entity such as `amp` or `#233`
+fn is_entity(name: &str) -> bool {
+    let name = name.strip_prefix('#').unwrap_or(name);
+    !name.is_empty() && name.len() <= 8 && name.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+/// Precomposed letters that fold to a base letter, sorted for binary
+/// search, with the base letters at the same positions in [`FOLD_TO`]
+///
+/// Generated from the canonical decompositions (Unicode 14) of the Latin
+/// letters up to Latin Extended-B, Latin Extended Additional, Greek and
+/// Greek Extended: every letter that decomposes into a base letter plus
+/// combining marks. Ø, Đ, Ħ, Ł and Ŧ have no decomposition and are added
+/// by hand.
+const FOLD_FROM: &[char] = &[
+    'À', 'Á', 'Â', 'Ã', 'Ä', 'Å', 'Ç', 'È', 'É', 'Ê', 'Ë', 'Ì', 'Í', 'Î', 'Ï', 'Ñ', 'Ò', 'Ó', 'Ô',
+    'Õ', 'Ö', 'Ø', 'Ù', 'Ú', 'Û', 'Ü', 'Ý', 'à', 'á', 'â', 'ã', 'ä', 'å', 'ç', 'è', 'é', 'ê', 'ë',
+    'ì', 'í', 'î', 'ï', 'ñ', 'ò', 'ó', 'ô', 'õ', 'ö', 'ø', 'ù', 'ú', 'û', 'ü', 'ý', 'ÿ', 'Ā', 'ā',
+    'Ă', 'ă', 'Ą', 'ą', 'Ć', 'ć', 'Ĉ', 'ĉ', 'Ċ', 'ċ', 'Č', 'č', 'Ď', 'ď', 'Đ', 'đ', 'Ē', 'ē', 'Ĕ',
+    'ĕ', 'Ė', 'ė', 'Ę', 'ę', 'Ě', 'ě', 'Ĝ', 'ĝ', 'Ğ', 'ğ', 'Ġ', 'ġ', 'Ģ', 'ģ', 'Ĥ', 'ĥ', 'Ħ', 'ħ',
+    'Ĩ', 'ĩ', 'Ī', 'ī', 'Ĭ', 'ĭ', 'Į', 'į', 'İ', 'Ĵ', 'ĵ', 'Ķ', 'ķ', 'Ĺ', 'ĺ', 'Ļ', 'ļ', 'Ľ', 'ľ',
+    'Ł', 'ł', 'Ń', 'ń', 'Ņ', 'ņ', 'Ň', 'ň', 'Ō', 'ō', 'Ŏ', 'ŏ', 'Ő', 'ő', 'Ŕ', 'ŕ', 'Ŗ', 'ŗ', 'Ř',
+    'ř', 'Ś', 'ś', 'Ŝ', 'ŝ', 'Ş', 'ş', 'Š', 'š', 'Ţ', 'ţ', 'Ť', 'ť', 'Ŧ', 'ŧ', 'Ũ', 'ũ', 'Ū', 'ū',
+    'Ŭ', 'ŭ', 'Ů', 'ů', 'Ű', 'ű', 'Ų', 'ų', 'Ŵ', 'ŵ', 'Ŷ', 'ŷ', 'Ÿ', 'Ź', 'ź', 'Ż', 'ż', 'Ž', 'ž',
+    'Ơ', 'ơ', 'Ư', 'ư', 'Ǎ', 'ǎ', 'Ǐ', 'ǐ', 'Ǒ', 'ǒ', 'Ǔ', 'ǔ', 'Ǖ', 'ǖ', 'Ǘ', 'ǘ', 'Ǚ', 'ǚ', 'Ǜ',
+    'ǜ', 'Ǟ', 'ǟ', 'Ǡ', 'ǡ', 'Ǣ', 'ǣ', 'Ǧ', 'ǧ', 'Ǩ', 'ǩ', 'Ǫ', 'ǫ', 'Ǭ', 'ǭ', 'Ǯ', 'ǯ', 'ǰ', 'Ǵ',
+    'ǵ', 'Ǹ', 'ǹ', 'Ǻ', 'ǻ', 'Ǽ', 'ǽ', 'Ǿ', 'ǿ', 'Ȁ', 'ȁ', 'Ȃ', 'ȃ', 'Ȅ', 'ȅ', 'Ȇ', 'ȇ', 'Ȉ', 'ȉ',
+    'Ȋ', 'ȋ', 'Ȍ', 'ȍ', 'Ȏ', 'ȏ', 'Ȑ', 'ȑ', 'Ȓ', 'ȓ', 'Ȕ', 'ȕ', 'Ȗ', 'ȗ', 'Ș', 'ș', 'Ț', 'ț', 'Ȟ',
+    'ȟ', 'Ȧ', 'ȧ', 'Ȩ', 'ȩ', 'Ȫ', 'ȫ', 'Ȭ', 'ȭ', 'Ȯ', 'ȯ', 'Ȱ', 'ȱ', 'Ȳ', 'ȳ', 'Ά', 'Έ', 'Ή', 'Ί',
+    'Ό', 'Ύ', 'Ώ', 'ΐ', 'Ϊ', 'Ϋ', 'ά', 'έ', 'ή', 'ί', 'ΰ', 'ϊ', 'ϋ', 'ό', 'ύ', 'ώ', 'ϓ', 'ϔ', 'Ḁ',
+    'ḁ', 'Ḃ', 'ḃ', 'Ḅ', 'ḅ', 'Ḇ', 'ḇ', 'Ḉ', 'ḉ', 'Ḋ', 'ḋ', 'Ḍ', 'ḍ', 'Ḏ', 'ḏ', 'Ḑ', 'ḑ', 'Ḓ', 'ḓ',
+    'Ḕ', 'ḕ', 'Ḗ', 'ḗ', 'Ḙ', 'ḙ', 'Ḛ', 'ḛ', 'Ḝ', 'ḝ', 'Ḟ', 'ḟ', 'Ḡ', 'ḡ', 'Ḣ', 'ḣ', 'Ḥ', 'ḥ', 'Ḧ',
+    'ḧ', 'Ḩ', 'ḩ', 'Ḫ', 'ḫ', 'Ḭ', 'ḭ', 'Ḯ', 'ḯ', 'Ḱ', 'ḱ', 'Ḳ', 'ḳ', 'Ḵ', 'ḵ', 'Ḷ', 'ḷ', 'Ḹ', 'ḹ',
+    'Ḻ', 'ḻ', 'Ḽ', 'ḽ', 'Ḿ', 'ḿ', 'Ṁ', 'ṁ', 'Ṃ', 'ṃ', 'Ṅ', 'ṅ', 'Ṇ', 'ṇ', 'Ṉ', 'ṉ', 'Ṋ', 'ṋ', 'Ṍ',
+    'ṍ', 'Ṏ', 'ṏ', 'Ṑ', 'ṑ', 'Ṓ', 'ṓ', 'Ṕ', 'ṕ', 'Ṗ', 'ṗ', 'Ṙ', 'ṙ', 'Ṛ', 'ṛ', 'Ṝ', 'ṝ', 'Ṟ', 'ṟ',
+    'Ṡ', 'ṡ', 'Ṣ', 'ṣ', 'Ṥ', 'ṥ', 'Ṧ', 'ṧ', 'Ṩ', 'ṩ', 'Ṫ', 'ṫ', 'Ṭ', 'ṭ', 'Ṯ', 'ṯ', 'Ṱ', 'ṱ', 'Ṳ',
+    'ṳ', 'Ṵ', 'ṵ', 'Ṷ', 'ṷ', 'Ṹ', 'ṹ', 'Ṻ', 'ṻ', 'Ṽ', 'ṽ', 'Ṿ', 'ṿ', 'Ẁ', 'ẁ', 'Ẃ', 'ẃ', 'Ẅ', 'ẅ',
+    'Ẇ', 'ẇ', 'Ẉ', 'ẉ', 'Ẋ', 'ẋ', 'Ẍ', 'ẍ', 'Ẏ', 'ẏ', 'Ẑ', 'ẑ', 'Ẓ', 'ẓ', 'Ẕ', 'ẕ', 'ẖ', 'ẗ', 'ẘ',
+    'ẙ', 'ẛ', 'Ạ', 'ạ', 'Ả', 'ả', 'Ấ', 'ấ', 'Ầ', 'ầ', 'Ẩ', 'ẩ', 'Ẫ', 'ẫ', 'Ậ', 'ậ', 'Ắ', 'ắ', 'Ằ',
+    'ằ', 'Ẳ', 'ẳ', 'Ẵ', 'ẵ', 'Ặ', 'ặ', 'Ẹ', 'ẹ', 'Ẻ', 'ẻ', 'Ẽ', 'ẽ', 'Ế', 'ế', 'Ề', 'ề', 'Ể', 'ể',
+    'Ễ', 'ễ', 'Ệ', 'ệ', 'Ỉ', 'ỉ', 'Ị', 'ị', 'Ọ', 'ọ', 'Ỏ', 'ỏ', 'Ố', 'ố', 'Ồ', 'ồ', 'Ổ', 'ổ', 'Ỗ',
+    'ỗ', 'Ộ', 'ộ', 'Ớ', 'ớ', 'Ờ', 'ờ', 'Ở', 'ở', 'Ỡ', 'ỡ', 'Ợ', 'ợ', 'Ụ', 'ụ', 'Ủ', 'ủ', 'Ứ', 'ứ',
+    'Ừ', 'ừ', 'Ử', 'ử', 'Ữ', 'ữ', 'Ự', 'ự', 'Ỳ', 'ỳ', 'Ỵ', 'ỵ', 'Ỷ', 'ỷ', 'Ỹ', 'ỹ', 'ἀ', 'ἁ', 'ἂ',
+    'ἃ', 'ἄ', 'ἅ', 'ἆ', 'ἇ', 'Ἀ', 'Ἁ', 'Ἂ', 'Ἃ', 'Ἄ', 'Ἅ', 'Ἆ', 'Ἇ', 'ἐ', 'ἑ', 'ἒ', 'ἓ', 'ἔ', 'ἕ',
+    'Ἐ', 'Ἑ', 'Ἒ', 'Ἓ', 'Ἔ', 'Ἕ', 'ἠ', 'ἡ', 'ἢ', 'ἣ', 'ἤ', 'ἥ', 'ἦ', 'ἧ', 'Ἠ', 'Ἡ', 'Ἢ', 'Ἣ', 'Ἤ',
+    'Ἥ', 'Ἦ', 'Ἧ', 'ἰ', 'ἱ', 'ἲ', 'ἳ', 'ἴ', 'ἵ', 'ἶ', 'ἷ', 'Ἰ', 'Ἱ', 'Ἲ', 'Ἳ', 'Ἴ', 'Ἵ', 'Ἶ', 'Ἷ',
+    'ὀ', 'ὁ', 'ὂ', 'ὃ', 'ὄ', 'ὅ', 'Ὀ', 'Ὁ', 'Ὂ', 'Ὃ', 'Ὄ', 'Ὅ', 'ὐ', 'ὑ', 'ὒ', 'ὓ', 'ὔ', 'ὕ', 'ὖ',
+    'ὗ', 'Ὑ', 'Ὓ', 'Ὕ', 'Ὗ', 'ὠ', 'ὡ', 'ὢ', 'ὣ', 'ὤ', 'ὥ', 'ὦ', 'ὧ', 'Ὠ', 'Ὡ', 'Ὢ', 'Ὣ', 'Ὤ', 'Ὥ',
+    'Ὦ', 'Ὧ', 'ὰ', 'ά', 'ὲ', 'έ', 'ὴ', 'ή', 'ὶ', 'ί', 'ὸ', 'ό', 'ὺ', 'ύ', 'ὼ', 'ώ', 'ᾀ', 'ᾁ', 'ᾂ',
+    'ᾃ', 'ᾄ', 'ᾅ', 'ᾆ', 'ᾇ', 'ᾈ', 'ᾉ', 'ᾊ', 'ᾋ', 'ᾌ', 'ᾍ', 'ᾎ', 'ᾏ', 'ᾐ', 'ᾑ', 'ᾒ', 'ᾓ', 'ᾔ', 'ᾕ',
+    'ᾖ', 'ᾗ', 'ᾘ', 'ᾙ', 'ᾚ', 'ᾛ', 'ᾜ', 'ᾝ', 'ᾞ', 'ᾟ', 'ᾠ', 'ᾡ', 'ᾢ', 'ᾣ', 'ᾤ', 'ᾥ', 'ᾦ', 'ᾧ', 'ᾨ',
+    'ᾩ', 'ᾪ', 'ᾫ', 'ᾬ', 'ᾭ', 'ᾮ', 'ᾯ', 'ᾰ', 'ᾱ', 'ᾲ', 'ᾳ', 'ᾴ', 'ᾶ', 'ᾷ', 'Ᾰ', 'Ᾱ', 'Ὰ', 'Ά', 'ᾼ',
+    'ῂ', 'ῃ', 'ῄ', 'ῆ', 'ῇ', 'Ὲ', 'Έ', 'Ὴ', 'Ή', 'ῌ', 'ῐ', 'ῑ', 'ῒ', 'ΐ', 'ῖ', 'ῗ', 'Ῐ', 'Ῑ', 'Ὶ',
+    'Ί', 'ῠ', 'ῡ', 'ῢ', 'ΰ', 'ῤ', 'ῥ', 'ῦ', 'ῧ', 'Ῠ', 'Ῡ', 'Ὺ', 'Ύ', 'Ῥ', 'ῲ', 'ῳ', 'ῴ', 'ῶ', 'ῷ',
+    'Ὸ', 'Ό', 'Ὼ', 'Ώ', 'ῼ',
+];
+const FOLD_TO: &[char] = &[
+    'A', 'A', 'A', 'A', 'A', 'A', 'C', 'E', 'E', 'E', 'E', 'I', 'I', 'I', 'I', 'N', 'O', 'O', 'O',
+    'O', 'O', 'O', 'U', 'U', 'U', 'U', 'Y', 'a', 'a', 'a', 'a', 'a', 'a', 'c', 'e', 'e', 'e', 'e',
+    'i', 'i', 'i', 'i', 'n', 'o', 'o', 'o', 'o', 'o', 'o', 'u', 'u', 'u', 'u', 'y', 'y', 'A', 'a',
+    'A', 'a', 'A', 'a', 'C', 'c', 'C', 'c', 'C', 'c', 'C', 'c', 'D', 'd', 'D', 'd', 'E', 'e', 'E',
+    'e', 'E', 'e', 'E', 'e', 'E', 'e', 'G', 'g', 'G', 'g', 'G', 'g', 'G', 'g', 'H', 'h', 'H', 'h',
+    'I', 'i', 'I', 'i', 'I', 'i', 'I', 'i', 'I', 'J', 'j', 'K', 'k', 'L', 'l', 'L', 'l', 'L', 'l',
+    'L', 'l', 'N', 'n', 'N', 'n', 'N', 'n', 'O', 'o', 'O', 'o', 'O', 'o', 'R', 'r', 'R', 'r', 'R',
+    'r', 'S', 's', 'S', 's', 'S', 's', 'S', 's', 'T', 't', 'T', 't', 'T', 't', 'U', 'u', 'U', 'u',
+    'U', 'u', 'U', 'u', 'U', 'u', 'U', 'u', 'W', 'w', 'Y', 'y', 'Y', 'Z', 'z', 'Z', 'z', 'Z', 'z',
+    'O', 'o', 'U', 'u', 'A', 'a', 'I', 'i', 'O', 'o', 'U', 'u', 'U', 'u', 'U', 'u', 'U', 'u', 'U',
+    'u', 'A', 'a', 'A', 'a', 'Æ', 'æ', 'G', 'g', 'K', 'k', 'O', 'o', 'O', 'o', 'Ʒ', 'ʒ', 'j', 'G',
+    'g', 'N', 'n', 'A', 'a', 'Æ', 'æ', 'Ø', 'ø', 'A', 'a', 'A', 'a', 'E', 'e', 'E', 'e', 'I', 'i',
+    'I', 'i', 'O', 'o', 'O', 'o', 'R', 'r', 'R', 'r', 'U', 'u', 'U', 'u', 'S', 's', 'T', 't', 'H',
+    'h', 'A', 'a', 'E', 'e', 'O', 'o', 'O', 'o', 'O', 'o', 'O', 'o', 'Y', 'y', 'Α', 'Ε', 'Η', 'Ι',
+    'Ο', 'Υ', 'Ω', 'ι', 'Ι', 'Υ', 'α', 'ε', 'η', 'ι', 'υ', 'ι', 'υ', 'ο', 'υ', 'ω', 'ϒ', 'ϒ', 'A',
+    'a', 'B', 'b', 'B', 'b', 'B', 'b', 'C', 'c', 'D', 'd', 'D', 'd', 'D', 'd', 'D', 'd', 'D', 'd',
+    'E', 'e', 'E', 'e', 'E', 'e', 'E', 'e', 'E', 'e', 'F', 'f', 'G', 'g', 'H', 'h', 'H', 'h', 'H',
+    'h', 'H', 'h', 'H', 'h', 'I', 'i', 'I', 'i', 'K', 'k', 'K', 'k', 'K', 'k', 'L', 'l', 'L', 'l',
+    'L', 'l', 'L', 'l', 'M', 'm', 'M', 'm', 'M', 'm', 'N', 'n', 'N', 'n', 'N', 'n', 'N', 'n', 'O',
+    'o', 'O', 'o', 'O', 'o', 'O', 'o', 'P', 'p', 'P', 'p', 'R', 'r', 'R', 'r', 'R', 'r', 'R', 'r',
+    'S', 's', 'S', 's', 'S', 's', 'S', 's', 'S', 's', 'T', 't', 'T', 't', 'T', 't', 'T', 't', 'U',
+    'u', 'U', 'u', 'U', 'u', 'U', 'u', 'U', 'u', 'V', 'v', 'V', 'v', 'W', 'w', 'W', 'w', 'W', 'w',
+    'W', 'w', 'W', 'w', 'X', 'x', 'X', 'x', 'Y', 'y', 'Z', 'z', 'Z', 'z', 'Z', 'z', 'h', 't', 'w',
+    'y', 'ſ', 'A', 'a', 'A', 'a', 'A', 'a', 'A', 'a', 'A', 'a', 'A', 'a', 'A', 'a', 'A', 'a', 'A',
+    'a', 'A', 'a', 'A', 'a', 'A', 'a', 'E', 'e', 'E', 'e', 'E', 'e', 'E', 'e', 'E', 'e', 'E', 'e',
+    'E', 'e', 'E', 'e', 'I', 'i', 'I', 'i', 'O', 'o', 'O', 'o', 'O', 'o', 'O', 'o', 'O', 'o', 'O',
+    'o', 'O', 'o', 'O', 'o', 'O', 'o', 'O', 'o', 'O', 'o', 'O', 'o', 'U', 'u', 'U', 'u', 'U', 'u',
+    'U', 'u', 'U', 'u', 'U', 'u', 'U', 'u', 'Y', 'y', 'Y', 'y', 'Y', 'y', 'Y', 'y', 'α', 'α', 'α',
+    'α', 'α', 'α', 'α', 'α', 'Α', 'Α', 'Α', 'Α', 'Α', 'Α', 'Α', 'Α', 'ε', 'ε', 'ε', 'ε', 'ε', 'ε',
+    'Ε', 'Ε', 'Ε', 'Ε', 'Ε', 'Ε', 'η', 'η', 'η', 'η', 'η', 'η', 'η', 'η', 'Η', 'Η', 'Η', 'Η', 'Η',
+    'Η', 'Η', 'Η', 'ι', 'ι', 'ι', 'ι', 'ι', 'ι', 'ι', 'ι', 'Ι', 'Ι', 'Ι', 'Ι', 'Ι', 'Ι', 'Ι', 'Ι',
+    'ο', 'ο', 'ο', 'ο', 'ο', 'ο', 'Ο', 'Ο', 'Ο', 'Ο', 'Ο', 'Ο', 'υ', 'υ', 'υ', 'υ', 'υ', 'υ', 'υ',
+    'υ', 'Υ', 'Υ', 'Υ', 'Υ', 'ω', 'ω', 'ω', 'ω', 'ω', 'ω', 'ω', 'ω', 'Ω', 'Ω', 'Ω', 'Ω', 'Ω', 'Ω',
+    'Ω', 'Ω', 'α', 'α', 'ε', 'ε', 'η', 'η', 'ι', 'ι', 'ο', 'ο', 'υ', 'υ', 'ω', 'ω', 'α', 'α', 'α',
+    'α', 'α', 'α', 'α', 'α', 'Α', 'Α', 'Α', 'Α', 'Α', 'Α', 'Α', 'Α', 'η', 'η', 'η', 'η', 'η', 'η',
+    'η', 'η', 'Η', 'Η', 'Η', 'Η', 'Η', 'Η', 'Η', 'Η', 'ω', 'ω', 'ω', 'ω', 'ω', 'ω', 'ω', 'ω', 'Ω',
+    'Ω', 'Ω', 'Ω', 'Ω', 'Ω', 'Ω', 'Ω', 'α', 'α', 'α', 'α', 'α', 'α', 'α', 'Α', 'Α', 'Α', 'Α', 'Α',
+    'η', 'η', 'η', 'η', 'η', 'Ε', 'Ε', 'Η', 'Η', 'Η', 'ι', 'ι', 'ι', 'ι', 'ι', 'ι', 'Ι', 'Ι', 'Ι',
+    'Ι', 'υ', 'υ', 'υ', 'υ', 'ρ', 'ρ', 'υ', 'υ', 'Υ', 'Υ', 'Υ', 'Υ', 'Ρ', 'ω', 'ω', 'ω', 'ω', 'ω',
+    'Ο', 'Ο', 'Ω', 'Ω', 'Ω',
+];
+
+/// Whether `c` is a combining diacritical mark
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+    )
+}
+
+/// Remove diacritics: precomposed letters become their base letter and
+/// combining marks are dropped
+///
+/// Covers Latin (including pinyin tone letters and Vietnamese) and Greek
+/// rather than full Unicode decomposition; other scripts pass through
+/// unchanged.
+pub fn fold_diacritics(text: &str) -> String {
+    text.chars()
+        .filter(|&c| !is_combining_mark(c))
+        .map(|c| match FOLD_FROM.binary_search(&c) {
+            Ok(i) => FOLD_TO[i],
+            Err(_) => c,
+        })
+        .collect()
+}
+
 /// Fast XOR decryption
 pub fn fast_decrypt(data: &mut [u8], key: &[u8]) {
     let mut previous: u8 = 0x36;
@@ -233,10 +1195,254 @@ pub fn mdx_decrypt(comp_block: &[u8]) -> Vec<u8> {
     result
 }
 
+/// Adler-32 checksum, as used for the header and key header
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Derive the key header decryption key from a user ID and the
+/// publisher's registration code
+///
+/// The registration code is the hex-encoded key encrypted with Salsa20/8,
+/// keyed by the RIPEMD-128 digest of the user ID: the email encoded as
+/// UTF-16LE for [`RegisterBy::Email`], or the device ID's bytes for
+/// [`RegisterBy::DeviceId`] (readmdict's `_decrypt_regcode_by_email` and
+/// `_decrypt_regcode_by_deviceid`).
+pub fn regcode_key(user_id: &str, regcode: &str, register_by: RegisterBy) -> Result<[u8; 16]> {
+    use crate::ripemd128::ripemd128;
+    use crate::salsa20::salsa20_8;
+
+    let invalid = || MdictError::DecryptionError(format!("Invalid regcode: {}", regcode));
+    let regcode = regcode.trim();
+    if regcode.len() != 32 || !regcode.is_ascii() {
+        return Err(invalid());
+    }
+    let mut encrypted = [0u8; 16];
+    for (i, byte) in encrypted.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&regcode[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+
+    let digest = match register_by {
+        RegisterBy::Email => {
+            let utf16: Vec<u8> = user_id.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            ripemd128(&utf16)
+        }
+        RegisterBy::DeviceId => ripemd128(user_id.as_bytes()),
+    };
+    let decrypted = salsa20_8(&encrypted, &digest);
+    let mut key = [0u8; 16];
+    key.copy_from_slice(&decrypted);
+    Ok(key)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        assert_eq!(glob_to_regex("un*ing"), "^un.*ing$");
+        assert_eq!(glob_to_regex("a?c"), "^a.c$");
+        assert_eq!(glob_to_regex("1+1.(x)"), r"^1\+1\.\(x\)$");
+    }
+
+    #[test]
+    fn test_compact_whitespace() {
+        assert_eq!(compact_whitespace("  ice \t  cream\n"), "ice cream");
+        assert_eq!(compact_whitespace("a-b"), "a-b");
+    }
+
+    #[test]
+    fn test_rewrite_resource_refs() {
+        let html = r#"<img SRC="a.png"><img src='b.png'><img src=c.png alt="x">
+<div style="background: url( 'd.png' )"></div><style>p { background: url(e.png) }</style>
+<img src="">"#;
+        let mut seen = Vec::new();
+        let out = rewrite_resource_refs(html, |value| {
+            seen.push(value.to_string());
+            (value != "c.png").then(|| value.to_uppercase())
+        });
+        assert_eq!(seen, vec!["a.png", "b.png", "c.png", "d.png", "e.png"]);
+        assert_eq!(
+            out,
+            r#"<img SRC="A.PNG"><img src='B.PNG'><img src=c.png alt="x">
+<div style="background: url( 'D.PNG' )"></div><style>p { background: url(E.PNG) }</style>
+<img src="">"#
+        );
+
+        let html = r#"<img data-src="lazy.png" src="a.png"/><img alt="x"src='b.png'>"#;
+        let mut seen = Vec::new();
+        rewrite_resource_refs(html, |value| {
+            seen.push(value.to_string());
+            None
+        });
+        assert_eq!(seen, vec!["a.png", "b.png"]);
+    }
+
+    #[test]
+    fn test_rewrite_internal_links() {
+        let html = r##"<link rel="stylesheet" href="dict.css"><img src="/img/a.png">
+<a href="sound://a.mp3">a</a><a href="entry://word">w</a><a href="https://x.org/">x</a>
+<a href="#top">t</a><img src="data:image/png;base64,AA">"##;
+        assert_eq!(
+            rewrite_internal_links(html, "http://localhost/res/"),
+            r##"<link rel="stylesheet" href="http://localhost/res/dict.css"><img src="http://localhost/res/img/a.png">
+<a href="http://localhost/res/a.mp3">a</a><a href="entry://word">w</a><a href="https://x.org/">x</a>
+<a href="#top">t</a><img src="data:image/png;base64,AA">"##
+        );
+
+        // Protocol-relative URLs and `data-` attributes are left alone
+        let html = r#"<link href="//cdn.example.com/x.css"><img data-src="a.png" src="b.png"><a data-href="c.css">"#;
+        assert_eq!(
+            rewrite_internal_links(html, "/res/"),
+            r#"<link href="//cdn.example.com/x.css"><img data-src="a.png" src="/res/b.png"><a data-href="c.css">"#
+        );
+    }
+
+    #[test]
+    fn test_strip_wrapper_tags() {
+        let html = r#"<link rel="stylesheet" href="dict.css"><link href="missing.css"><LINK href="https://cdn/x.css">
+<script src="dict.js"></script><script>var inline = 1;</script><p>word</p><script src="/a.js"/>"#;
+        let out = strip_wrapper_tags(html, |path| {
+            (path == "dict.css").then(|| "p { color: red }".to_string())
+        });
+        assert_eq!(
+            out,
+            r#"<style>p { color: red }</style><LINK href="https://cdn/x.css">
+<script>var inline = 1;</script><p>word</p>"#
+        );
+        assert_eq!(strip_wrapper_tags("a < b", |_| None), "a < b");
+        let cdn = r#"<link rel="stylesheet" href="//cdn.example.com/x.css">"#;
+        assert_eq!(strip_wrapper_tags(cdn, |_| None), cdn);
+
+        // Only style sheets are inlined; other internal links are dropped
+        let html = r#"<link rel="icon" href="fav.ico"><link rel="preload" href="f.woff"><link rel="Stylesheet" href="theme"><link href="x.CSS">"#;
+        let mut asked = Vec::new();
+        let out = strip_wrapper_tags(html, |path| {
+            asked.push(path.to_string());
+            Some(String::new())
+        });
+        assert_eq!(out, "<style></style><style></style>");
+        assert_eq!(asked, ["theme", "x.CSS"]);
+    }
+
+    #[test]
+    fn test_unescape_numeric_entities() {
+        assert_eq!(unescape_entities("caf&#233;"), "café");
+        assert_eq!(unescape_entities("a&#x2014;b&#X41;"), "a\u{2014}bA");
+        assert_eq!(unescape_entities("&#;"), "&#;");
+        assert_eq!(
+            unescape_entities("&#xZZ; &#12a; &#xD800; &#"),
+            "&#xZZ; &#12a; &#xD800; &#"
+        );
+        assert_eq!(unescape_entities("&amp;#233; &lt;b&gt;"), "&#233; <b>");
+        // Decoded text isn't decoded again
+        assert_eq!(unescape_entities("&#38;lt; &#38;amp;"), "&lt; &amp;");
+        assert_eq!(unescape_entities("&amp;lt; a & b"), "&lt; a & b");
+    }
+
+    #[test]
+    fn test_first_invalid_byte() {
+        assert_eq!(first_invalid_byte(b"abc", Encoding::Utf8), None);
+        assert_eq!(first_invalid_byte(b"ab\xffc", Encoding::Utf8), Some(2));
+        assert_eq!(first_invalid_byte("é".as_bytes(), Encoding::Utf8), None);
+        assert_eq!(
+            first_invalid_byte(&[0x61, 0x00, 0x00, 0xdc], Encoding::Utf16Le),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_decode_utf16_byte_orders() {
+        let be: Vec<u8> = "Hé".encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        assert_eq!(decode_string(&be, Encoding::Utf16Be).unwrap(), "Hé");
+
+        let mut with_bom = vec![0xfe, 0xff];
+        with_bom.extend(&be);
+        assert_eq!(decode_utf16le(&with_bom).unwrap(), "Hé");
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        let html = "<style>p { color: red }</style><p>A <b>bold</b>&amp;good\r\n</p><p>word</p>\0";
+        assert_eq!(html_to_text(html), "A bold&good word");
+        assert_eq!(html_to_text("un<i>der</i>line<br>next"), "underline next");
+        assert_eq!(truncate_text("hello world", 5), "hello…");
+        assert_eq!(truncate_text("hello", 5), "hello");
+    }
+
+    #[test]
+    fn test_truncate_html() {
+        let html = "<div class=\"d\"><b>Tom &amp; Jerry</b> and <i>friends</i></div>";
+        assert_eq!(truncate_html(html, 100), html);
+        assert_eq!(
+            truncate_html(html, 5),
+            "<div class=\"d\"><b>Tom &amp;…</b></div>"
+        );
+        assert_eq!(
+            truncate_html(html, 9),
+            "<div class=\"d\"><b>Tom &amp; Jer…</b></div>"
+        );
+
+        // Void elements aren't closed; stray and cut-off tags are dropped
+        assert_eq!(truncate_html("a<br>b</span>c<img src=", 10), "a<br>bc");
+        assert_eq!(
+            truncate_html("<p>x<script>var a = 1;</script>", 10),
+            "<p>x</p>"
+        );
+    }
+
+    #[test]
+    fn test_fold_diacritics() {
+        assert!(FOLD_FROM.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(FOLD_FROM.len(), FOLD_TO.len());
+        assert_eq!(fold_diacritics("café"), "cafe");
+        assert_eq!(fold_diacritics("Ærøskøbing"), "Æroskobing");
+        assert_eq!(fold_diacritics("cafe\u{0301}"), "cafe");
+        assert_eq!(fold_diacritics("Łódź"), "Lodz");
+        // Pinyin tone letters, Vietnamese and Greek tonos
+        assert_eq!(fold_diacritics("nǚ lǜ xiǎo shǒu"), "nu lu xiao shou");
+        assert_eq!(fold_diacritics("Tiếng Việt, người"), "Tieng Viet, nguoi");
+        assert_eq!(fold_diacritics("άλφα ΐ"), "αλφα ι");
+    }
+
+    #[test]
+    fn test_regcode_key() {
+        // Known answers from readmdict's regcode decryption
+        let regcode = "0123456789abcdef0123456789abcdef";
+        let key = regcode_key("reader@example.com", regcode, RegisterBy::Email).unwrap();
+        assert_eq!(
+            key,
+            [
+                0xba, 0x25, 0x7b, 0x2f, 0x15, 0x89, 0xe4, 0xd8, 0x3e, 0xb6, 0x62, 0x54, 0xd4, 0xf1,
+                0x58, 0x52
+            ]
+        );
+        let key = regcode_key("reader@example.com", regcode, RegisterBy::DeviceId).unwrap();
+        assert_eq!(
+            key,
+            [
+                0x7f, 0x69, 0xd3, 0x2a, 0xc8, 0x6e, 0xde, 0x9b, 0x2e, 0x7b, 0xb8, 0xd1, 0x85, 0x80,
+                0x0e, 0xb3
+            ]
+        );
+
+        assert!(regcode_key("user@example.com", "0011", RegisterBy::Email).is_err());
+        let bad_hex = "zz112233445566778899aabbccddeeff";
+        assert!(regcode_key("user@example.com", bad_hex, RegisterBy::Email).is_err());
+    }
+
     #[test]
     fn test_read_u32_be() {
         let bytes = [0x00, 0x00, 0x04, 0xa6];
@@ -318,6 +1524,90 @@ mod tests {
         assert_eq!(bytes_to_number(&bytes2), 0x20);
     }
 
+    #[test]
+    fn test_parse_header_raw_html_description() {
+        let header = r#"<Dictionary GeneratedByEngineVersion="2.0" Description="Tom & Jerry say "hi" <a href="x.html">here</a>" Title="Cartoons" Encoding="UTF-8"/>"#;
+        let attrs = parse_header(header).unwrap();
+        assert_eq!(
+            attrs["Description"],
+            r#"Tom & Jerry say "hi" <a href="x.html">here</a>"#
+        );
+        assert_eq!(attrs["Title"], "Cartoons");
+        assert_eq!(attrs["Encoding"], "UTF-8");
+        assert_eq!(attrs["GeneratedByEngineVersion"], "2.0");
+    }
+
+    #[test]
+    fn test_parse_header_long_html_description() {
+        let description = format!(
+            "<div class=\"intro\"><font color=\"red\" size=\"2\">{}</font> &lt;b&gt;bold&lt;/b&gt; a < b</div>",
+            "A long description. ".repeat(200)
+        );
+        let header = format!(
+            "<Dictionary GeneratedByEngineVersion=\"2.0\" Description=\"{}\" Encoding=\"UTF-8\" Title=\"Long\"/>\r\n\0",
+            description
+        );
+        let attrs = parse_header(&header).unwrap();
+        assert_eq!(
+            attrs["Description"],
+            description.replace("&lt;", "<").replace("&gt;", ">")
+        );
+        assert_eq!(attrs["Encoding"], "UTF-8");
+        assert_eq!(attrs["Title"], "Long");
+    }
+
+    #[test]
+    fn test_parse_header_malformed() {
+        // Missing closing `>`, a stray bare attribute and an escaped quote
+        let header = "<Library_Data Stray GeneratedByEngineVersion=\"2.0\" Title=\"A \\\"B\\\"\" Encrypted=\"0\"\r\n\0";
+        let attrs = parse_header(header).unwrap();
+        assert_eq!(attrs["Title"], "A \"B\"");
+        assert_eq!(attrs["Encrypted"], "0");
+        assert!(!attrs.contains_key("Stray"));
+
+        let attrs = parse_header("<Dictionary Title=\"Unterminated").unwrap();
+        assert_eq!(attrs["Title"], "Unterminated");
+    }
+
+    #[test]
+    fn test_header_file_ext() {
+        let mdx = "\u{feff}<Dictionary GeneratedByEngineVersion=\"2.0\"/>";
+        assert_eq!(header_file_ext(mdx), Some(FileExt::Mdx));
+        let mdd = "\r\n<Library_Data Encrypted=\"0\"/>";
+        assert_eq!(header_file_ext(mdd), Some(FileExt::Mdd));
+        assert_eq!(header_file_ext("Title=\"<Dictionary\""), None);
+    }
+
+    #[test]
+    fn test_parse_header_bare_less_than() {
+        // `<b` with no `>` before the next quote isn't a tag
+        let header = r#"<Dictionary Title="a<b" Encoding="UTF-8" Description="x <i>y</i>"/>"#;
+        let attrs = parse_header(header).unwrap();
+        assert_eq!(attrs["Title"], "a<b");
+        assert_eq!(attrs["Encoding"], "UTF-8");
+        assert_eq!(attrs["Description"], "x <i>y</i>");
+    }
+
+    #[test]
+    fn test_parse_header_bool() {
+        assert_eq!(parse_header_bool("Yes"), Some(true));
+        assert_eq!(parse_header_bool(" no "), Some(false));
+        assert_eq!(parse_header_bool("TRUE"), Some(true));
+        assert_eq!(parse_header_bool("0"), Some(false));
+        assert_eq!(parse_header_bool("maybe"), None);
+    }
+
+    #[test]
+    fn test_parse_engine_version() {
+        assert_eq!(parse_engine_version("2.0"), Some(2.0));
+        assert_eq!(parse_engine_version("2.0.0"), Some(2.0));
+        assert_eq!(parse_engine_version("2"), Some(2.0));
+        assert_eq!(parse_engine_version(" 1.2 "), Some(1.2));
+        assert_eq!(parse_engine_version("1.2b"), Some(1.2));
+        assert_eq!(parse_engine_version("v2"), None);
+        assert_eq!(parse_engine_version(""), None);
+    }
+
     #[test]
     fn test_levenshtein_distance() {
         assert_eq!(levenshtein_distance("hello", "hello"), 0);
@@ -326,4 +1616,40 @@ mod tests {
         assert_eq!(levenshtein_distance("", "abc"), 3);
         assert_eq!(levenshtein_distance("abc", ""), 3);
     }
+
+    #[test]
+    fn test_stripped_chars_matches_strip() {
+        let keys = [
+            "",
+            " ",
+            "Apple",
+            "  New York  ",
+            "a\t.",
+            "a \t b\n",
+            "rock'n'roll",
+            "\\img\\a_b.PNG",
+            "İstanbul",
+            "x. y.",
+            "a\u{3000}b ",
+        ];
+        for mode in [
+            StripMode::None,
+            StripMode::StripPunct,
+            StripMode::CompactWhitespace,
+        ] {
+            for is_mdd in [false, true] {
+                for key in keys {
+                    let mut expected = match mode {
+                        StripMode::None => key.to_string(),
+                        StripMode::StripPunct => strip_key(key, is_mdd, true),
+                        StripMode::CompactWhitespace => compact_whitespace(key),
+                    };
+                    expected = expected.to_lowercase().trim().to_string();
+                    let actual: String =
+                        StrippedChars::new(key, mode, is_mdd, true, true).collect();
+                    assert_eq!(actual, expected, "{:?} {:?} mdd={}", key, mode, is_mdd);
+                }
+            }
+        }
+    }
 }
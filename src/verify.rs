@@ -0,0 +1,116 @@
+//! Block integrity checks
+//!
+//! Every key block and record block is read and decompressed, and the
+//! result is checked against the size declared in the index and the
+//! Adler-32 checksum stored in the block's preamble.
+
+use crate::error::{MdictError, Result};
+use crate::mdict_base::MdictBase;
+use crate::utils::adler32;
+
+/// Kind of block a [`BlockError`] refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockKind {
+    Key,
+    Record,
+}
+
+/// A block that failed verification
+#[derive(Debug)]
+pub struct BlockError {
+    /// Key or record block
+    pub kind: BlockKind,
+    /// Index of the block within its kind
+    pub index: usize,
+    /// What went wrong
+    pub error: MdictError,
+}
+
+impl MdictBase {
+    /// Check every block in order and return all failures
+    pub(crate) fn verify_blocks(&self) -> Vec<BlockError> {
+        self.block_jobs()
+            .filter_map(|(kind, index)| self.verify_block(kind, index).err())
+            .collect()
+    }
+
+    /// Check every block using all available cores and return all failures,
+    /// ordered as [`Self::verify_blocks`] orders them
+    #[cfg(feature = "parallel")]
+    pub(crate) fn verify_blocks_parallel(&self) -> Vec<BlockError> {
+        let jobs: Vec<(BlockKind, usize)> = self.block_jobs().collect();
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let chunk_size = jobs.len().div_ceil(threads).max(1);
+
+        let mut errors: Vec<BlockError> = std::thread::scope(|scope| {
+            let handles: Vec<_> = jobs
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .filter_map(|&(kind, index)| self.verify_block(kind, index).err())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("verify thread panicked"))
+                .collect()
+        });
+
+        errors.sort_by_key(|e| (e.kind, e.index));
+        errors
+    }
+
+    /// Every key block followed by every record block
+    fn block_jobs(&self) -> impl Iterator<Item = (BlockKind, usize)> {
+        let keys = (0..self.key_info_list.len()).map(|i| (BlockKind::Key, i));
+        let records = (0..self.record_info_list.len()).map(|i| (BlockKind::Record, i));
+        keys.chain(records)
+    }
+
+    fn verify_block(&self, kind: BlockKind, index: usize) -> std::result::Result<(), BlockError> {
+        let result = match kind {
+            BlockKind::Key => self
+                .read_packed_key_block(index)
+                .and_then(|(packed, size)| {
+                    let unpacked = self.unpack_key_block(&packed, size)?;
+                    check_block(&packed, &unpacked, size)
+                }),
+            BlockKind::Record => self
+                .read_packed_record_block(index)
+                .and_then(|(packed, size)| {
+                    let unpacked = self.decompress_record_block(&packed, size)?;
+                    check_block(&packed, &unpacked, size)
+                }),
+        };
+        result.map_err(|error| BlockError { kind, index, error })
+    }
+}
+
+/// Compare a decompressed block with its declared size and the checksum in
+/// its preamble
+fn check_block(packed: &[u8], unpacked: &[u8], unpack_size: usize) -> Result<()> {
+    if unpacked.len() != unpack_size {
+        return Err(MdictError::DecompressionError(format!(
+            "Decompressed to {} bytes, expected {}",
+            unpacked.len(),
+            unpack_size
+        )));
+    }
+
+    let stored = u32::from_be_bytes([packed[4], packed[5], packed[6], packed[7]]);
+    let actual = adler32(unpacked);
+    if stored != actual {
+        return Err(MdictError::DecompressionError(format!(
+            "Checksum mismatch: stored {:08x}, computed {:08x}",
+            stored, actual
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,257 @@
+//! Minimal zip archive writer
+//!
+//! This module writes deflate-compressed zip archives without pulling in a
+//! full zip implementation. Only what is needed to bundle MDD resources is
+//! supported: no encryption, no comments, and zip64 records only where
+//! the entry count or archive size needs them, not for entries of 4 GiB
+//! or more.
+
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::collections::HashSet;
+use std::io::{Seek, Write};
+
+use crate::error::{MdictError, Result};
+
+const LOCAL_FILE_HEADER_SIG: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_SIG: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIG: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIG: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIG: u32 = 0x0706_4b50;
+/// Extra field holding the zip64 values of fields set to `u32::MAX`
+const ZIP64_EXTRA_TAG: u16 = 0x0001;
+
+/// Version 2.0: deflate
+const VERSION: u16 = 20;
+/// Version 4.5: zip64
+const VERSION_ZIP64: u16 = 45;
+/// General purpose flag bit 11: file names are UTF-8
+const FLAG_UTF8: u16 = 0x0800;
+const METHOD_DEFLATE: u16 = 8;
+/// MS-DOS date for 1980-01-01, the earliest representable
+const DOS_DATE: u16 = 0x0021;
+
+/// Central directory record kept until the archive is finished
+struct CentralEntry {
+    name: String,
+    name_len: u16,
+    crc: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    header_offset: u64,
+}
+
+/// Zip archive writer
+pub struct ZipWriter<'a, W: Write + Seek> {
+    out: &'a mut W,
+    entries: Vec<CentralEntry>,
+    names: HashSet<String>,
+}
+
+impl<'a, W: Write + Seek> ZipWriter<'a, W> {
+    /// Create a new writer appending to `out` at its current position
+    pub fn new(out: &'a mut W) -> Self {
+        ZipWriter {
+            out,
+            entries: Vec::new(),
+            names: HashSet::new(),
+        }
+    }
+
+    /// Compress `data` and add it as entry `name`
+    ///
+    /// Fails with `InvalidFormat`, writing nothing, if `name` was already
+    /// added or is longer than 65,535 bytes.
+    pub fn add_file(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let name_len = to_name_len(name)?;
+        if self.names.contains(name) {
+            return Err(MdictError::InvalidFormat(format!(
+                "Duplicate zip entry name: {}",
+                name
+            )));
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let mut crc = Crc::new();
+        crc.update(data);
+
+        let entry = CentralEntry {
+            name: name.to_string(),
+            name_len,
+            crc: crc.sum(),
+            compressed_size: to_u32(compressed.len() as u64, "entry size")?,
+            uncompressed_size: to_u32(data.len() as u64, "entry size")?,
+            header_offset: self.out.stream_position()?,
+        };
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&LOCAL_FILE_HEADER_SIG.to_le_bytes());
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+        header.extend_from_slice(&METHOD_DEFLATE.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // mod time
+        header.extend_from_slice(&DOS_DATE.to_le_bytes());
+        header.extend_from_slice(&entry.crc.to_le_bytes());
+        header.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        header.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+        header.extend_from_slice(&name_len.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        header.extend_from_slice(name.as_bytes());
+
+        self.out.write_all(&header)?;
+        self.out.write_all(&compressed)?;
+        self.names.insert(entry.name.clone());
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    /// Write the central directory and return the number of entries
+    ///
+    /// More than 65,535 entries, or a directory past 4 GiB, adds the zip64
+    /// end of central directory records.
+    pub fn finish(self) -> Result<usize> {
+        let central_directory_offset = self.out.stream_position()?;
+        let entry_count = self.entries.len();
+
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            let zip64_offset = entry.header_offset >= u32::MAX as u64;
+            let version = if zip64_offset { VERSION_ZIP64 } else { VERSION };
+            directory.extend_from_slice(&CENTRAL_DIRECTORY_SIG.to_le_bytes());
+            directory.extend_from_slice(&version.to_le_bytes()); // made by
+            directory.extend_from_slice(&version.to_le_bytes()); // needed
+            directory.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+            directory.extend_from_slice(&METHOD_DEFLATE.to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes()); // mod time
+            directory.extend_from_slice(&DOS_DATE.to_le_bytes());
+            directory.extend_from_slice(&entry.crc.to_le_bytes());
+            directory.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            directory.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+            directory.extend_from_slice(&entry.name_len.to_le_bytes());
+            let extra_len: u16 = if zip64_offset { 12 } else { 0 };
+            directory.extend_from_slice(&extra_len.to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes()); // comment length
+            directory.extend_from_slice(&0u16.to_le_bytes()); // disk number
+            directory.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+            directory.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+            directory.extend_from_slice(&clamp_u32(entry.header_offset).to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+            if zip64_offset {
+                directory.extend_from_slice(&ZIP64_EXTRA_TAG.to_le_bytes());
+                directory.extend_from_slice(&8u16.to_le_bytes());
+                directory.extend_from_slice(&entry.header_offset.to_le_bytes());
+            }
+        }
+        let central_directory_size = directory.len() as u64;
+
+        if entry_count >= u16::MAX as usize
+            || central_directory_offset >= u32::MAX as u64
+            || central_directory_size >= u32::MAX as u64
+        {
+            let zip64_end_offset = central_directory_offset + central_directory_size;
+            directory.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY_SIG.to_le_bytes());
+            directory.extend_from_slice(&44u64.to_le_bytes()); // size of the rest
+            directory.extend_from_slice(&VERSION_ZIP64.to_le_bytes()); // made by
+            directory.extend_from_slice(&VERSION_ZIP64.to_le_bytes()); // needed
+            directory.extend_from_slice(&0u32.to_le_bytes()); // this disk
+            directory.extend_from_slice(&0u32.to_le_bytes()); // central directory disk
+            directory.extend_from_slice(&(entry_count as u64).to_le_bytes());
+            directory.extend_from_slice(&(entry_count as u64).to_le_bytes());
+            directory.extend_from_slice(&central_directory_size.to_le_bytes());
+            directory.extend_from_slice(&central_directory_offset.to_le_bytes());
+
+            directory.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIG.to_le_bytes());
+            directory.extend_from_slice(&0u32.to_le_bytes()); // zip64 end record disk
+            directory.extend_from_slice(&zip64_end_offset.to_le_bytes());
+            directory.extend_from_slice(&1u32.to_le_bytes()); // total disks
+        }
+
+        let count = entry_count.min(u16::MAX as usize) as u16;
+        directory.extend_from_slice(&END_OF_CENTRAL_DIRECTORY_SIG.to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        directory.extend_from_slice(&0u16.to_le_bytes()); // central directory disk
+        directory.extend_from_slice(&count.to_le_bytes());
+        directory.extend_from_slice(&count.to_le_bytes());
+        directory.extend_from_slice(&clamp_u32(central_directory_size).to_le_bytes());
+        directory.extend_from_slice(&clamp_u32(central_directory_offset).to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes()); // comment length
+
+        self.out.write_all(&directory)?;
+        self.out.flush()?;
+
+        Ok(entry_count)
+    }
+}
+
+/// `value`, or `u32::MAX` to defer to the zip64 record when it doesn't fit
+fn clamp_u32(value: u64) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+/// Entry sizes are stored as u32, as zip64 entry sizes aren't written
+pub(crate) fn to_u32(value: u64, what: &str) -> Result<u32> {
+    u32::try_from(value)
+        .map_err(|_| MdictError::InvalidFormat(format!("zip {} exceeds 4 GiB", what)))
+}
+
+/// Entry names are stored with a u16 length
+pub(crate) fn to_name_len(name: &str) -> Result<u16> {
+    u16::try_from(name.len()).map_err(|_| {
+        MdictError::InvalidFormat(format!(
+            "zip entry name of {} bytes exceeds 65,535",
+            name.len()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zip64_entry_count() {
+        let mut out = std::io::Cursor::new(Vec::new());
+        let mut zip = ZipWriter::new(&mut out);
+        let count = u16::MAX as usize + 1;
+        for i in 0..count {
+            zip.add_file(&format!("{}.txt", i), b"x").unwrap();
+        }
+        assert_eq!(zip.finish().unwrap(), count);
+        let archive = out.into_inner();
+
+        // The plain record defers to the zip64 one
+        let eocd = &archive[archive.len() - 22..];
+        assert_eq!(&eocd[..4], &END_OF_CENTRAL_DIRECTORY_SIG.to_le_bytes());
+        assert_eq!(&eocd[10..12], &[0xff, 0xff]);
+
+        let locator = &archive[archive.len() - 42..archive.len() - 22];
+        assert_eq!(
+            &locator[..4],
+            &ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIG.to_le_bytes()
+        );
+        let offset = u64::from_le_bytes(locator[8..16].try_into().unwrap()) as usize;
+        let record = &archive[offset..offset + 56];
+        assert_eq!(
+            &record[..4],
+            &ZIP64_END_OF_CENTRAL_DIRECTORY_SIG.to_le_bytes()
+        );
+        let total = u64::from_le_bytes(record[32..40].try_into().unwrap());
+        assert_eq!(total as usize, count);
+    }
+
+    #[test]
+    fn test_rejected_names() {
+        let mut out = std::io::Cursor::new(Vec::new());
+        let mut zip = ZipWriter::new(&mut out);
+        zip.add_file("a.txt", b"x").unwrap();
+        assert!(zip.add_file("a.txt", b"y").is_err());
+        assert!(zip
+            .add_file(&"n".repeat(u16::MAX as usize + 1), b"z")
+            .is_err());
+        assert_eq!(zip.finish().unwrap(), 1);
+    }
+}
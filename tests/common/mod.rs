@@ -0,0 +1,448 @@
+//! Helpers for building small synthetic MDX/MDD files in tests
+
+#![allow(dead_code)]
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+use tempfile::NamedTempFile;
+
+/// Encoder applied to block payloads written with a custom tag
+pub type BlockEncoder = fn(&[u8]) -> Vec<u8>;
+
+/// Builder for a synthetic dictionary file
+pub struct DictBuilder {
+    mdd: bool,
+    version: String,
+    attrs: Vec<(String, String)>,
+    entries: Vec<(String, Vec<u8>)>,
+    keys_per_block: usize,
+    records_per_block: usize,
+    compress: bool,
+    custom_blocks: Option<(u32, BlockEncoder)>,
+    key_header_key: Option<[u8; 16]>,
+    key_encoding: Option<&'static encoding_rs::Encoding>,
+    big_endian: bool,
+}
+
+impl DictBuilder {
+    /// A v2.0 UTF-8 MDX
+    pub fn mdx() -> Self {
+        DictBuilder {
+            mdd: false,
+            version: "2.0".to_string(),
+            attrs: vec![("Encoding".to_string(), "UTF-8".to_string())],
+            entries: Vec::new(),
+            keys_per_block: 2,
+            records_per_block: 2,
+            compress: true,
+            custom_blocks: None,
+            key_header_key: None,
+            key_encoding: None,
+            big_endian: false,
+        }
+    }
+
+    /// A v2.0 MDD (UTF-16LE keys)
+    pub fn mdd() -> Self {
+        DictBuilder {
+            mdd: true,
+            attrs: Vec::new(),
+            ..Self::mdx()
+        }
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = version.to_string();
+        self
+    }
+
+    pub fn attr(mut self, key: &str, value: &str) -> Self {
+        self.attrs.retain(|(k, _)| k != key);
+        self.attrs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Add an entry; entries are written in the order given
+    pub fn entry(mut self, key: &str, record: impl AsRef<[u8]>) -> Self {
+        self.entries
+            .push((key.to_string(), record.as_ref().to_vec()));
+        self
+    }
+
+    /// Add MDX entries whose definitions are `<p>{key}</p>`
+    pub fn words(mut self, words: &[&str]) -> Self {
+        for word in words {
+            self = self.entry(word, format!("<p>{}</p>\r\n\0", word));
+        }
+        self
+    }
+
+    pub fn keys_per_block(mut self, n: usize) -> Self {
+        self.keys_per_block = n;
+        self
+    }
+
+    pub fn records_per_block(mut self, n: usize) -> Self {
+        self.records_per_block = n;
+        self
+    }
+
+    /// Store blocks uncompressed instead of zlib
+    pub fn uncompressed(mut self) -> Self {
+        self.compress = false;
+        self
+    }
+
+    /// Store key and record blocks with a custom tag, encoding each
+    /// payload with `encode`
+    pub fn custom_blocks(mut self, tag: u32, encode: BlockEncoder) -> Self {
+        self.custom_blocks = Some((tag, encode));
+        self
+    }
+
+    /// Mark the file `Encrypted="1"` and encrypt the key header with
+    /// Salsa20/8 under `key`
+    pub fn encrypt_key_header(mut self, key: [u8; 16]) -> Self {
+        self.key_header_key = Some(key);
+        self
+    }
+
+    /// Encode MDX keys with `encoding` instead of UTF-8; records are
+    /// written as given
+    pub fn key_encoding(mut self, encoding: &'static encoding_rs::Encoding) -> Self {
+        self.key_encoding = Some(encoding);
+        self
+    }
+
+    /// Write the header (with a `FE FF` BOM) and keys as UTF-16BE; an MDX
+    /// is declared `Encoding="UTF-16"`
+    pub fn utf16be(mut self) -> Self {
+        self.big_endian = true;
+        if !self.mdd {
+            self.attrs.retain(|(key, _)| key != "Encoding");
+            self.attrs
+                .push(("Encoding".to_string(), "UTF-16".to_string()));
+        }
+        self
+    }
+
+    fn utf16(&self) -> bool {
+        self.mdd || self.big_endian
+    }
+
+    fn utf16_bytes(&self, text: &str) -> Vec<u8> {
+        if self.big_endian {
+            text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect()
+        } else {
+            text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+        }
+    }
+
+    fn is_v2(&self) -> bool {
+        let major = self.version.split('.').next().unwrap_or("");
+        major.parse::<u32>().is_ok_and(|major| major >= 2)
+    }
+
+    fn num(&self, n: u64) -> Vec<u8> {
+        if self.is_v2() {
+            n.to_be_bytes().to_vec()
+        } else {
+            (n as u32).to_be_bytes().to_vec()
+        }
+    }
+
+    fn encode_text(&self, text: &str) -> Vec<u8> {
+        if self.utf16() {
+            self.utf16_bytes(text)
+        } else if let Some(encoding) = self.key_encoding {
+            encoding.encode(text).0.into_owned()
+        } else {
+            text.as_bytes().to_vec()
+        }
+    }
+
+    fn terminator(&self) -> &'static [u8] {
+        if self.utf16() {
+            &[0, 0]
+        } else {
+            &[0]
+        }
+    }
+
+    /// Wrap data in a block with the 8-byte type/checksum preamble
+    pub fn block(&self, data: &[u8]) -> Vec<u8> {
+        if let Some((tag, encode)) = self.custom_blocks {
+            let mut out = tag.to_le_bytes().to_vec();
+            out.extend_from_slice(&adler32(data).to_be_bytes());
+            out.extend(encode(data));
+            out
+        } else if self.compress {
+            zlib_block(data)
+        } else {
+            let mut out = vec![0, 0, 0, 0];
+            out.extend_from_slice(&adler32(data).to_be_bytes());
+            out.extend_from_slice(data);
+            out
+        }
+    }
+
+    /// Header section: length, UTF-16LE text, checksum
+    pub fn header_section(&self) -> Vec<u8> {
+        let tag = if self.mdd {
+            "Library_Data"
+        } else {
+            "Dictionary"
+        };
+        let encrypted = if self.key_header_key.is_some() {
+            "1"
+        } else {
+            "No"
+        };
+        let mut text = format!(
+            "<{} GeneratedByEngineVersion=\"{}\" RequiredEngineVersion=\"{}\" Encrypted=\"{}\"",
+            tag, self.version, self.version, encrypted
+        );
+        for (key, value) in &self.attrs {
+            text.push_str(&format!(" {}=\"{}\"", key, value));
+        }
+        text.push_str("/>\r\n\0");
+        let mut header = if self.big_endian {
+            vec![0xfe, 0xff]
+        } else {
+            Vec::new()
+        };
+        header.extend(self.utf16_bytes(&text));
+
+        let mut out = (header.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&adler32(&header).to_le_bytes());
+        out
+    }
+
+    /// Decompressed key block contents for the given entries
+    pub fn key_block_data(&self, entries: &[(String, u64)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (key, offset) in entries {
+            data.extend(self.num(*offset));
+            data.extend(self.encode_text(key));
+            data.extend_from_slice(self.terminator());
+        }
+        data
+    }
+
+    fn text_len_field(&self, text: &str) -> Vec<u8> {
+        let len = if self.utf16() {
+            text.encode_utf16().count()
+        } else {
+            self.encode_text(text).len()
+        };
+        if self.is_v2() {
+            (len as u16).to_be_bytes().to_vec()
+        } else {
+            vec![len as u8]
+        }
+    }
+
+    /// Assemble the file with the given raw key block contents, one per
+    /// block, each followed by its declared entry count and first/last key
+    pub fn build_with_key_blocks(&self, key_blocks: &[(Vec<u8>, u64, String, String)]) -> Vec<u8> {
+        let mut out = self.header_section();
+
+        let mut key_info = Vec::new();
+        let mut packed_blocks = Vec::new();
+        for (data, count, first, last) in key_blocks {
+            let packed = self.block(data);
+            key_info.extend(self.num(*count));
+            key_info.extend(self.text_len_field(first));
+            key_info.extend(self.encode_text(first));
+            if self.is_v2() {
+                key_info.extend_from_slice(self.terminator());
+            }
+            key_info.extend(self.text_len_field(last));
+            key_info.extend(self.encode_text(last));
+            if self.is_v2() {
+                key_info.extend_from_slice(self.terminator());
+            }
+            key_info.extend(self.num(packed.len() as u64));
+            key_info.extend(self.num(data.len() as u64));
+            packed_blocks.extend(packed);
+        }
+
+        let key_info_section = if self.is_v2() {
+            zlib_block(&key_info)
+        } else {
+            key_info.clone()
+        };
+
+        let mut key_header = self.num(key_blocks.len() as u64);
+        key_header.extend(self.num(self.entries.len() as u64));
+        if self.is_v2() {
+            key_header.extend(self.num(key_info.len() as u64));
+        }
+        key_header.extend(self.num(key_info_section.len() as u64));
+        key_header.extend(self.num(packed_blocks.len() as u64));
+        match self.key_header_key {
+            Some(key) => out.extend(salsa20_8(&key_header, &key)),
+            None => out.extend_from_slice(&key_header),
+        }
+        if self.is_v2() {
+            out.extend_from_slice(&adler32(&key_header).to_be_bytes());
+        }
+        out.extend(key_info_section);
+        out.extend(packed_blocks);
+
+        out.extend(self.record_section());
+        out
+    }
+
+    /// Record offsets of each entry in the concatenated record data
+    pub fn record_offsets(&self) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        let mut pos = 0u64;
+        for (_, record) in &self.entries {
+            offsets.push(pos);
+            pos += record.len() as u64;
+        }
+        offsets
+    }
+
+    /// Record header, record info and record blocks
+    pub fn record_section(&self) -> Vec<u8> {
+        let mut record_info = Vec::new();
+        let mut record_blocks = Vec::new();
+        for chunk in self.entries.chunks(self.records_per_block.max(1)) {
+            let data: Vec<u8> = chunk.iter().flat_map(|(_, r)| r.clone()).collect();
+            let packed = self.block(&data);
+            record_info.extend(self.num(packed.len() as u64));
+            record_info.extend(self.num(data.len() as u64));
+            record_blocks.extend(packed);
+        }
+        let block_count = self.entries.chunks(self.records_per_block.max(1)).count();
+
+        let mut out = self.num(block_count as u64);
+        out.extend(self.num(self.entries.len() as u64));
+        out.extend(self.num(record_info.len() as u64));
+        out.extend(self.num(record_blocks.len() as u64));
+        out.extend(record_info);
+        out.extend(record_blocks);
+        out
+    }
+
+    /// Assemble the whole file
+    pub fn build(&self) -> Vec<u8> {
+        let offsets = self.record_offsets();
+        let indexed: Vec<(String, u64)> = self
+            .entries
+            .iter()
+            .zip(offsets)
+            .map(|((key, _), offset)| (key.clone(), offset))
+            .collect();
+
+        let key_blocks: Vec<(Vec<u8>, u64, String, String)> = indexed
+            .chunks(self.keys_per_block.max(1))
+            .map(|chunk| {
+                (
+                    self.key_block_data(chunk),
+                    chunk.len() as u64,
+                    chunk[0].0.clone(),
+                    chunk[chunk.len() - 1].0.clone(),
+                )
+            })
+            .collect();
+
+        self.build_with_key_blocks(&key_blocks)
+    }
+
+    /// Build and write to a temporary file
+    pub fn write(&self) -> NamedTempFile {
+        write_temp(&self.build(), if self.mdd { ".mdd" } else { ".mdx" })
+    }
+}
+
+/// Write bytes to a temporary file with the given suffix
+pub fn write_temp(bytes: &[u8], suffix: &str) -> NamedTempFile {
+    let mut file = tempfile::Builder::new()
+        .suffix(suffix)
+        .tempfile()
+        .expect("Failed to create temp file");
+    file.write_all(bytes).expect("Failed to write temp file");
+    file.flush().unwrap();
+    file
+}
+
+/// Zlib-compressed block with the 8-byte type/checksum preamble
+pub fn zlib_block(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut out = vec![2, 0, 0, 0];
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out.extend(compressed);
+    out
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Salsa20/8 with a 128-bit key and zero nonce, as MDict uses for key
+/// headers
+pub fn salsa20_8(data: &[u8], key: &[u8; 16]) -> Vec<u8> {
+    fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    }
+
+    let k: Vec<u32> = key
+        .chunks(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect();
+    let tau = [0x6170_7865, 0x3120_646e, 0x7962_2d36, 0x6b20_6574];
+
+    let mut out = Vec::new();
+    for (counter, chunk) in data.chunks(64).enumerate() {
+        let input = [
+            tau[0],
+            k[0],
+            k[1],
+            k[2],
+            k[3],
+            tau[1],
+            0,
+            0,
+            counter as u32,
+            0,
+            tau[2],
+            k[0],
+            k[1],
+            k[2],
+            k[3],
+            tau[3],
+        ];
+        let mut x = input;
+        for _ in 0..4 {
+            for (a, b, c, d) in [(0, 4, 8, 12), (5, 9, 13, 1), (10, 14, 2, 6), (15, 3, 7, 11)] {
+                quarter_round(&mut x, a, b, c, d);
+            }
+            for (a, b, c, d) in [(0, 1, 2, 3), (5, 6, 7, 4), (10, 11, 8, 9), (15, 12, 13, 14)] {
+                quarter_round(&mut x, a, b, c, d);
+            }
+        }
+        let keystream: Vec<u8> = x
+            .iter()
+            .zip(input.iter())
+            .flat_map(|(w, i)| w.wrapping_add(*i).to_le_bytes())
+            .collect();
+        out.extend(chunk.iter().zip(keystream).map(|(d, k)| d ^ k));
+    }
+    out
+}
@@ -1,6 +1,6 @@
 //! Integration tests for rust-mdict using real dictionary files
 
-use rust_mdict::{Mdd, Mdx};
+use rust_mdict::{Dictionary, Mdd, MdictFile, Mdx};
 
 const MDX_PATH: &str = "tests/test.mdx";
 const MDD_PATH: &str = "tests/test.mdd";
@@ -115,6 +115,51 @@ fn test_mdx_prefix_search_app() {
     assert!(!keys.is_empty(), "No words found with prefix 'app'");
 }
 
+#[test]
+fn test_mdx_has_prefix() {
+    let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+
+    assert!(mdx.has_prefix("hel"));
+    assert!(mdx.has_prefix("HEL"));
+    assert!(mdx.has_prefix("hello"));
+    assert!(!mdx.has_prefix("xyznonexistent"));
+
+    // Agrees with the scanning implementation
+    for prefix in ["", "a", "ap", "caf", "w", "wor", "zz", "hello!"] {
+        assert_eq!(
+            mdx.has_prefix(prefix),
+            !mdx.prefix_keys(prefix).is_empty(),
+            "Mismatch for prefix {:?}",
+            prefix
+        );
+    }
+}
+
+#[test]
+fn test_mdx_search_keys_glob() {
+    let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+
+    let keys = mdx.search_keys_glob("*ello", 100);
+    println!("=== Glob '*ello': {:?} ===", keys);
+    assert!(keys.contains(&"hello".to_string()));
+    assert!(keys.iter().all(|k| k.to_lowercase().ends_with("ello")));
+
+    assert!(mdx
+        .search_keys_glob("h?llo", 100)
+        .contains(&"hello".to_string()));
+    assert!(mdx
+        .search_keys_glob("HEL*", 100)
+        .contains(&"hello".to_string()));
+
+    // Anchored at both ends, and regex metacharacters are literal
+    assert!(!mdx
+        .search_keys_glob("ell", 100)
+        .contains(&"hello".to_string()));
+    assert!(mdx.search_keys_glob("hel.o", 100).is_empty());
+
+    assert_eq!(mdx.search_keys_glob("*", 1).len(), 1);
+}
+
 #[test]
 fn test_mdx_suggest() {
     let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
@@ -144,6 +189,25 @@ fn test_mdx_contains() {
     println!("=== Contains test passed ===");
 }
 
+#[test]
+fn test_mdx_filter_keys() {
+    let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+
+    let short: Vec<&str> = mdx.filter_keys(|k| k.chars().count() <= 5).collect();
+    println!("=== Keys with at most 5 chars: {} ===", short.len());
+    assert!(short.contains(&"hello"));
+    assert!(short.iter().all(|k| k.chars().count() <= 5));
+
+    let expected = mdx
+        .keywords()
+        .into_iter()
+        .filter(|k| k.chars().count() <= 5)
+        .count();
+    assert_eq!(short.len(), expected);
+
+    assert_eq!(mdx.filter_keys(|_| false).count(), 0);
+}
+
 #[test]
 fn test_mdx_keywords_sample() {
     let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
@@ -477,3 +541,380 @@ fn test_mdx_get_definition() {
     let def = definition.unwrap();
     println!("Definition length: {} chars", def.len());
 }
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_mdd_export_zip() {
+    let mut mdd = Mdd::new(MDD_PATH).expect("Failed to load MDD");
+
+    let mut out = std::io::Cursor::new(Vec::new());
+    let count = mdd.export_zip(&mut out).expect("Failed to export zip");
+    let archive = out.into_inner();
+
+    println!("=== Export zip ===");
+    println!("Entries: {}, archive size: {} bytes", count, archive.len());
+
+    assert_eq!(count, mdd.resource_count());
+
+    // End of central directory record: signature, then the entry count
+    let eocd = &archive[archive.len() - 22..];
+    assert_eq!(&eocd[..4], &[0x50, 0x4b, 0x05, 0x06]);
+    assert_eq!(u16::from_le_bytes([eocd[10], eocd[11]]) as usize, count);
+
+    // Entry names use forward slashes only
+    let name_len = u16::from_le_bytes([archive[26], archive[27]]) as usize;
+    let first_name = std::str::from_utf8(&archive[30..30 + name_len]).unwrap();
+    assert!(!first_name.contains('\\'));
+    assert!(!first_name.starts_with('/'));
+}
+
+#[test]
+fn test_mdx_fetch_block_definitions() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+
+    let entries = mdx
+        .fetch_block_definitions(0)
+        .expect("Failed to fetch block definitions");
+
+    println!("=== Definitions in record block 0 ===");
+    println!("Found {} entries", entries.len());
+    for (key, definition) in entries.iter().take(5) {
+        println!("  {} ({} chars)", key, definition.len());
+    }
+
+    assert!(!entries.is_empty(), "Record block 0 should hold entries");
+
+    // Each definition should match a regular lookup of the same keyword
+    let (key, definition) = entries[0].clone();
+    let item = mdx
+        .keyword_list()
+        .iter()
+        .find(|item| item.key_text == key)
+        .cloned()
+        .unwrap();
+    assert_eq!(mdx.get_definition(&item).unwrap(), definition);
+
+    assert!(mdx.fetch_block_definitions(usize::MAX).is_err());
+}
+
+#[test]
+fn test_mdx_fetch_raw_batch() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+
+    let items: Vec<_> = ["world", "apple", "hello"]
+        .iter()
+        .map(|word| mdx.lookup_keyword(word).cloned().unwrap())
+        .collect();
+
+    let results = mdx.fetch_raw_batch(&items);
+
+    println!("=== Fetch raw batch ===");
+    assert_eq!(results.len(), items.len());
+    for (item, result) in items.iter().zip(&results) {
+        let bytes = result.as_ref().expect("Failed to fetch raw bytes");
+        println!("  {}: {} bytes", item.key_text, bytes.len());
+
+        // Batch results line up with the input order
+        let definition = mdx.get_definition(item).unwrap();
+        assert_eq!(String::from_utf8_lossy(bytes), definition);
+    }
+}
+
+#[test]
+fn test_shared_mdx_concurrent_lookups() {
+    use rust_mdict::SharedMdx;
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedMdx>();
+
+    let mdx = Arc::new(Mdx::open_readonly_shared(MDX_PATH).expect("Failed to load MDX"));
+    let expected = mdx.lookup("hello").expect("Word 'hello' not found");
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let mdx = Arc::clone(&mdx);
+            std::thread::spawn(move || {
+                let words = ["hello", "world", "apple"];
+                for n in 0..20 {
+                    let word = words[(i + n) % words.len()];
+                    assert!(mdx.lookup(word).is_some(), "'{}' not found", word);
+                }
+                mdx.lookup("hello").unwrap().definition
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), expected.definition);
+    }
+    println!("=== Shared lookups from 8 threads passed ===");
+}
+
+#[test]
+fn test_mdx_open_incremental() {
+    let mut incremental = Mdx::open_incremental(MDX_PATH).expect("Failed to open MDX");
+    let block_count = incremental.block_count();
+
+    let mut total = 0;
+    while let Some(block) = incremental.next_block().expect("Failed to read key block") {
+        total += block.len();
+        assert_eq!(incremental.keywords_read().len(), total);
+    }
+
+    println!("=== Incremental load ===");
+    println!("Read {} keywords from {} key blocks", total, block_count);
+    assert_eq!(incremental.blocks_read(), block_count);
+
+    let mut mdx = incremental.finish().expect("Failed to finish loading");
+    assert_eq!(mdx.keyword_count(), total);
+    assert!(mdx.lookup("hello").is_some(), "Word 'hello' not found");
+}
+
+#[test]
+fn test_mdx_open_incremental_early_finish() {
+    let mut incremental = Mdx::open_incremental(MDX_PATH).expect("Failed to open MDX");
+    let first = incremental.next_block().unwrap().expect("No key blocks");
+    assert!(!first.is_empty());
+
+    // Finishing early reads the remaining blocks
+    let mdx = incremental.finish().expect("Failed to finish loading");
+    let full = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    assert_eq!(mdx.keyword_count(), full.keyword_count());
+}
+
+#[test]
+fn test_mdx_export_stardict() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_path = dir.path().join("test");
+
+    mdx.export_stardict(&base_path)
+        .expect("Failed to export StarDict");
+
+    let ifo = std::fs::read_to_string(dir.path().join("test.ifo")).unwrap();
+    let idx = std::fs::read(dir.path().join("test.idx")).unwrap();
+    let dict = std::fs::read(dir.path().join("test.dict")).unwrap();
+
+    println!("=== StarDict export ===");
+    println!("{}", ifo);
+    assert!(ifo.starts_with("StarDict's dict ifo file\nversion=2.4.2\n"));
+    assert!(ifo.contains(&format!("wordcount={}\n", mdx.keyword_count())));
+    assert!(ifo.contains(&format!("idxfilesize={}\n", idx.len())));
+
+    // Walk the index and check every entry points inside the .dict
+    let mut words = Vec::new();
+    let mut pos = 0;
+    while pos < idx.len() {
+        let end = pos + idx[pos..].iter().position(|&b| b == 0).unwrap();
+        let word = String::from_utf8(idx[pos..end].to_vec()).unwrap();
+        let offset = u32::from_be_bytes(idx[end + 1..end + 5].try_into().unwrap()) as usize;
+        let size = u32::from_be_bytes(idx[end + 5..end + 9].try_into().unwrap()) as usize;
+        assert!(offset + size <= dict.len());
+        words.push((word, offset, size));
+        pos = end + 9;
+    }
+    assert_eq!(words.len(), mdx.keyword_count());
+
+    let (_, offset, size) = words.iter().find(|(w, _, _)| w == "hello").unwrap();
+    let definition = std::str::from_utf8(&dict[*offset..offset + size]).unwrap();
+    let expected = mdx.lookup("hello").unwrap().definition;
+    assert_eq!(definition, expected.trim_end_matches('\0'));
+}
+
+#[test]
+fn test_mdx_record_block_index() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let item = mdx
+        .lookup_keyword("hello")
+        .expect("Word 'hello' not found")
+        .clone();
+
+    let block_idx = mdx.record_block_index(&item);
+    let block = mdx
+        .fetch_block_definitions(block_idx)
+        .expect("Failed to fetch block");
+    assert!(block.iter().any(|(key, _)| key == &item.key_text));
+
+    // Every keyword maps to a block that can be read
+    let mut indices: Vec<usize> = mdx
+        .keyword_list()
+        .iter()
+        .map(|item| mdx.record_block_index(item))
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+    for block_idx in indices {
+        assert!(mdx.fetch_block_definitions(block_idx).is_ok());
+    }
+}
+
+#[test]
+fn test_mdx_into_entries() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let count = mdx.keyword_count();
+    let hello = mdx.lookup("hello").expect("Word 'hello' not found");
+
+    let entries: Vec<(String, String)> = mdx
+        .into_entries()
+        .collect::<rust_mdict::Result<_>>()
+        .expect("Failed to read entries");
+
+    println!("=== Consuming walk: {} entries ===", entries.len());
+    assert_eq!(entries.len(), count);
+    assert!(entries
+        .iter()
+        .any(|(key, definition)| key == &hello.key_text && definition == &hello.definition));
+}
+
+#[test]
+fn test_mdx_header_meta() {
+    let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let header_meta = mdx.header_meta();
+
+    println!("=== Header framing ===");
+    println!("Header size: {} bytes", header_meta.byte_size);
+    println!("Checksum: {:08x}", header_meta.checksum);
+
+    assert_eq!(header_meta.byte_size as usize, header_meta.raw.len());
+    assert!(header_meta.checksum_matches(), "Header checksum mismatch");
+
+    // The raw bytes are the start of the file after the length prefix
+    let file = std::fs::read(MDX_PATH).unwrap();
+    assert_eq!(&file[4..4 + header_meta.raw.len()], &header_meta.raw[..]);
+    let text: String = char::decode_utf16(
+        header_meta
+            .raw
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]])),
+    )
+    .map(|c| c.unwrap())
+    .collect();
+    assert!(text.starts_with("<Dictionary"));
+}
+
+#[test]
+fn test_mdx_verify() {
+    let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let errors = mdx.verify();
+    for error in &errors {
+        println!("{:?} block {}: {}", error.kind, error.index, error.error);
+    }
+    assert!(errors.is_empty(), "Found {} corrupt blocks", errors.len());
+}
+
+#[test]
+fn test_mdx_explain_lookup() {
+    let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+
+    let trace = mdx.explain_lookup("Hello");
+    println!("=== Lookup trace for 'Hello' ===");
+    for step in &trace.steps {
+        println!(
+            "  [{}] {:?} -> {:?} ({:?})",
+            step.index, step.key_text, step.stripped_key, step.ordering
+        );
+    }
+    assert_eq!(trace.stripped_query, "hello");
+    assert_eq!(trace.found.unwrap().key_text, "hello");
+    assert_eq!(
+        trace.steps.last().unwrap().ordering,
+        std::cmp::Ordering::Equal
+    );
+
+    let trace = mdx.explain_lookup("xyznonexistent");
+    assert!(trace.found.is_none());
+    assert!(!trace.steps.is_empty());
+
+    let (stripped, nearest) = mdx.debug_strip("Hel-lo");
+    assert_eq!(stripped, "hello");
+    assert_eq!(nearest, "hello");
+}
+
+#[test]
+fn test_mdx_key_block_ranges() {
+    let mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let ranges = mdx.key_block_ranges();
+    println!("=== Key block ranges ===");
+    for (i, (first, last)) in ranges.iter().enumerate() {
+        println!("  [{}] {:?} .. {:?}", i, first, last);
+    }
+
+    assert!(!ranges.is_empty());
+    let keys: Vec<&str> = mdx.keywords();
+    for (first, last) in &ranges {
+        assert!(keys.contains(&first.as_str()));
+        assert!(keys.contains(&last.as_str()));
+    }
+}
+
+#[test]
+fn test_mdx_keyword_list_cloned() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let items = mdx.keyword_list_cloned();
+    assert_eq!(items.len(), mdx.keyword_count());
+
+    // Owned items can be fetched while the snapshot is held
+    for item in items.iter().take(10) {
+        let result = mdx.fetch(item).expect("Failed to fetch snapshot item");
+        assert_eq!(result.key_text, item.key_text);
+    }
+}
+
+#[test]
+fn test_mdx_lookup_fold_diacritics() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    assert!(mdx.lookup("cafe").is_none());
+
+    let result = mdx
+        .lookup_fold_diacritics("cafe")
+        .expect("'cafe' should find 'café'");
+    assert_eq!(result.key_text, "café");
+
+    // Exact matches take the fast path
+    assert_eq!(
+        mdx.lookup_fold_diacritics("hello").unwrap().key_text,
+        "hello"
+    );
+    assert!(mdx.lookup_fold_diacritics("xyznonexistent").is_none());
+}
+
+#[test]
+fn test_dictionary_open() {
+    fn summary(file: &dyn MdictFile) -> (usize, String) {
+        (file.keys().len(), file.meta().ext.as_str().to_string())
+    }
+
+    let mdx = Dictionary::open(MDX_PATH).expect("Failed to open MDX");
+    assert!(matches!(mdx, Dictionary::Mdx(_)));
+    assert!(mdx.contains("hello"));
+    assert_eq!(mdx.filepath(), MDX_PATH);
+    assert_eq!(summary(mdx.as_file()).1, "mdx");
+
+    let mdd = Dictionary::open(MDD_PATH).expect("Failed to open MDD");
+    assert!(matches!(mdd, Dictionary::Mdd(_)));
+    let (count, ext) = summary(&mdd);
+    assert!(count > 0);
+    assert_eq!(ext, "mdd");
+
+    assert!(Dictionary::open("tests/test.txt").is_err());
+}
+
+#[test]
+fn test_mdx_prefix_iter() {
+    let mut mdx = Mdx::new(MDX_PATH).expect("Failed to load MDX");
+    let eager: Vec<(String, String)> = mdx
+        .prefix("a")
+        .into_iter()
+        .map(|r| (r.key_text, r.definition))
+        .collect();
+    let lazy: Vec<(String, String)> = mdx
+        .prefix_iter("a")
+        .map(|r| (r.key_text, r.definition))
+        .collect();
+    assert!(!lazy.is_empty());
+    assert_eq!(lazy, eager);
+
+    assert_eq!(mdx.prefix_iter("a").take(1).count(), 1);
+    assert_eq!(mdx.prefix_iter("xyznonexistent").count(), 0);
+}
@@ -0,0 +1,2322 @@
+//! Tests against small synthetic dictionaries built in memory
+//!
+//! These cover header flags and file layouts that the real test
+//! dictionaries don't exercise.
+
+mod common;
+
+use common::DictBuilder;
+use rust_mdict::{
+    BlockDecompressor, BlockKind, DefaultDecompressor, Encoding, KeyCountMismatch, KeyWordItem,
+    Mdd, MddBuilder, MdictError, MdictFile, Mdx, MdxBuilder, NumFmt, StripMode,
+};
+
+#[test]
+fn test_prefix_respects_key_case_sensitive() {
+    let file = DictBuilder::mdx()
+        .attr("KeyCaseSensitive", "Yes")
+        .words(&["Na", "NaCl", "na", "nab"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let mut keys = mdx.prefix_keys("Na");
+    keys.sort();
+    assert_eq!(keys, vec!["Na", "NaCl"]);
+
+    let mut keys = mdx.prefix_keys("na");
+    keys.sort();
+    assert_eq!(keys, vec!["na", "nab"]);
+
+    // Exact lookup agrees with prefix matching
+    assert!(mdx.contains("NaCl"));
+    assert!(!mdx.contains("nacl"));
+}
+
+#[test]
+fn test_prefix_case_insensitive_by_default() {
+    let file = DictBuilder::mdx()
+        .words(&["Na", "NaCl", "na", "nab"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(mdx.prefix_keys("NA").len(), 4);
+}
+
+fn xor_encode(data: &[u8]) -> Vec<u8> {
+    data.iter().map(|b| b ^ 0x5a).collect()
+}
+
+/// Handles tag 0x10 (XOR-scrambled) and defers everything else
+struct XorDecompressor;
+
+impl BlockDecompressor for XorDecompressor {
+    fn decompress(&self, tag: u32, data: &[u8], unpack_size: usize) -> rust_mdict::Result<Vec<u8>> {
+        if tag == 0x10 {
+            Ok(xor_encode(data))
+        } else {
+            DefaultDecompressor.decompress(tag, data, unpack_size)
+        }
+    }
+}
+
+#[test]
+fn test_custom_block_decompressor() {
+    let file = DictBuilder::mdx()
+        .words(&["apple", "banana", "cherry"])
+        .custom_blocks(0x10, xor_encode)
+        .write();
+
+    let err = Mdx::new(file.path())
+        .err()
+        .expect("Unknown tag should fail");
+    assert!(matches!(err, MdictError::InvalidCompressionType(0x10)));
+
+    let mut mdx = MdxBuilder::new()
+        .decompressor(XorDecompressor)
+        .open(file.path())
+        .expect("Failed to load MDX with custom decompressor");
+    assert_eq!(mdx.keyword_count(), 3);
+    assert_eq!(
+        mdx.lookup("banana").unwrap().definition,
+        "<p>banana</p>\r\n\0"
+    );
+}
+
+#[test]
+fn test_about_html() {
+    let file = DictBuilder::mdx()
+        .attr("Title", "Sample")
+        .attr("Description", "&lt;b&gt;Sample&lt;/b&gt; &amp; more")
+        .words(&["a"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.title(), Some("Sample"));
+    assert_eq!(mdx.about_html().unwrap(), "<b>Sample</b> & more");
+
+    // Decoded once, like every other attribute
+    let file = DictBuilder::mdx()
+        .attr("Description", "&amp;lt;i&amp;gt;About")
+        .words(&["a"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.about_html().unwrap(), "&lt;i&gt;About");
+
+    let file = DictBuilder::mdx().words(&["a"]).write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.about_html().is_none());
+    assert!(mdx.title().is_none());
+}
+
+#[test]
+fn test_about_entry() {
+    let file = DictBuilder::mdx()
+        .attr("Title", "Sample")
+        .entry("00-About", "<h1>About</h1>\0")
+        .words(&["apple"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.about_entry().unwrap(), "<h1>About</h1>");
+
+    // Falls back to the title page
+    let file = DictBuilder::mdx()
+        .attr("Title", "Sample")
+        .entry("Sample", "<h1>Sample</h1>\0")
+        .words(&["apple"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.about_entry().unwrap(), "<h1>Sample</h1>");
+
+    let file = DictBuilder::mdx().words(&["apple"]).write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.about_entry().is_none());
+}
+
+#[test]
+fn test_mdd_underscore_keys() {
+    let file = DictBuilder::mdd()
+        .entry("\\a.png", b"a")
+        .entry("\\a_b.png", b"a_b")
+        .entry("\\ab.png", b"ab")
+        .entry("\\b.png", b"b")
+        .write();
+
+    // Default: `_` sorts and compares as `!`
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+    assert_eq!(mdd.locate_raw("\\a_b.png").unwrap(), b"a_b");
+    assert_eq!(mdd.locate_raw("\\a!b.png").unwrap(), b"a_b");
+
+    // Substitution disabled: the underscore key is still found, but only
+    // as itself
+    let mut mdd = MddBuilder::new()
+        .underscore_as_bang(false)
+        .open(file.path())
+        .expect("Failed to load MDD");
+    assert_eq!(mdd.locate_raw("\\a_b.png").unwrap(), b"a_b");
+    assert!(mdd.locate_raw("\\a!b.png").is_none());
+    assert_eq!(mdd.locate_raw("\\ab.png").unwrap(), b"ab");
+}
+
+/// Replace the record section of a built file with `record_section`
+fn with_record_section(builder: &DictBuilder, record_section: &[u8]) -> Vec<u8> {
+    let mut bytes = builder.build();
+    bytes.truncate(bytes.len() - builder.record_section().len());
+    bytes.extend_from_slice(record_section);
+    bytes
+}
+
+#[test]
+fn test_no_record_blocks() {
+    let builder = DictBuilder::mdx().words(&["apple", "banana"]);
+    // Record header declaring zero blocks, entries and sizes
+    let bytes = with_record_section(&builder, &[0u8; 32]);
+    let file = common::write_temp(&bytes, ".mdx");
+
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.keyword_count(), 2);
+    assert!(mdx.lookup("apple").is_none());
+
+    let item = mdx.lookup_keyword("apple").unwrap().clone();
+    let results = mdx.fetch_raw_batch(&[item]);
+    assert!(matches!(results[0], Err(MdictError::NoRecordBlocks)));
+}
+
+#[test]
+fn test_truncated_record_info() {
+    let builder = DictBuilder::mdx().words(&["apple", "banana"]);
+    // Three record blocks declared, but no record info bytes
+    let mut record_section = 3u64.to_be_bytes().to_vec();
+    record_section.extend(2u64.to_be_bytes());
+    record_section.extend([0u8; 16]);
+    let bytes = with_record_section(&builder, &record_section);
+    let file = common::write_temp(&bytes, ".mdx");
+
+    assert!(matches!(
+        Mdx::new(file.path()),
+        Err(MdictError::InvalidFormat(_))
+    ));
+}
+
+#[test]
+fn test_definition_size_stats() {
+    // Definitions are `<p>{word}</p>\r\n\0`, i.e. word length + 10 bytes
+    let file = DictBuilder::mdx()
+        .words(&["a", "bb", "cccc", "dddddddd"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let stats = mdx.definition_size_stats();
+    assert_eq!(stats.count, 4);
+    assert_eq!(stats.min, 11);
+    assert_eq!(stats.max, 18);
+    assert_eq!(stats.total, 55);
+    assert_eq!(stats.mean, 13.75);
+    assert_eq!(stats.median, 13.0);
+}
+
+#[test]
+fn test_largest_entries() {
+    let file = DictBuilder::mdx()
+        .words(&["a", "bb", "cccc", "dddddddd", "ee"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(
+        mdx.largest_entries(3),
+        vec![("dddddddd", 18), ("cccc", 14), ("bb", 12)]
+    );
+    assert_eq!(mdx.largest_entries(10).len(), 5);
+    assert!(mdx.largest_entries(0).is_empty());
+}
+
+#[test]
+fn test_v1_mdd() {
+    let file = DictBuilder::mdd()
+        .version("1.2")
+        .entry("\\a.png", b"png-a")
+        .entry("\\\u{00e9}t\u{00e9}.css", b"css-ete")
+        .entry("\\img\\x.jpg", b"jpg-x")
+        // U+0100 encodes as `00 01`, which must not end the key
+        .entry("\\\u{0100}.png", b"png-0100")
+        .keys_per_block(2)
+        .write();
+
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load v1.2 MDD");
+    assert_eq!(mdd.meta().num_width, 4);
+    assert_eq!(mdd.resource_count(), 4);
+    assert_eq!(mdd.locate_raw("\\a.png").unwrap(), b"png-a");
+    assert_eq!(
+        mdd.locate_raw("\\\u{00e9}t\u{00e9}.css").unwrap(),
+        b"css-ete"
+    );
+    assert_eq!(mdd.locate_raw("\\img\\x.jpg").unwrap(), b"jpg-x");
+    assert_eq!(mdd.locate_raw("\\\u{0100}.png").unwrap(), b"png-0100");
+}
+
+/// Key derived from the email `reader@example.com` and [`REGCODE`], as
+/// readmdict derives it
+const REGISTERED_KEY: [u8; 16] = [
+    0xba, 0x25, 0x7b, 0x2f, 0x15, 0x89, 0xe4, 0xd8, 0x3e, 0xb6, 0x62, 0x54, 0xd4, 0xf1, 0x58, 0x52,
+];
+/// Key derived from the device ID `reader@example.com` and [`REGCODE`]
+const DEVICE_KEY: [u8; 16] = [
+    0x7f, 0x69, 0xd3, 0x2a, 0xc8, 0x6e, 0xde, 0x9b, 0x2e, 0x7b, 0xb8, 0xd1, 0x85, 0x80, 0x0e, 0xb3,
+];
+const REGCODE: &str = "0123456789abcdef0123456789abcdef";
+
+#[test]
+fn test_credentials() {
+    let file = DictBuilder::mdx()
+        .attr("RegisterBy", "EMail")
+        .words(&["apple", "banana", "cherry"])
+        .encrypt_key_header(REGISTERED_KEY)
+        .write();
+
+    assert!(matches!(
+        Mdx::new(file.path()),
+        Err(MdictError::EncryptedFileRequiresPasscode)
+    ));
+
+    assert!(matches!(
+        MdxBuilder::new()
+            .credentials("someone@example.com", REGCODE)
+            .open(file.path()),
+        Err(MdictError::DecryptionError(_))
+    ));
+
+    let mut mdx = MdxBuilder::new()
+        .credentials("reader@example.com", REGCODE)
+        .open(file.path())
+        .expect("Failed to open with credentials");
+    assert_eq!(mdx.meta().register_by, rust_mdict::RegisterBy::Email);
+    assert_eq!(mdx.keyword_count(), 3);
+    assert_eq!(
+        mdx.lookup("banana").unwrap().definition,
+        "<p>banana</p>\r\n\0"
+    );
+
+    // Without `RegisterBy="EMail"` the user ID is a device ID
+    let file = DictBuilder::mdx()
+        .words(&["apple", "banana", "cherry"])
+        .encrypt_key_header(DEVICE_KEY)
+        .write();
+    let mdx = MdxBuilder::new()
+        .credentials("reader@example.com", REGCODE)
+        .open(file.path())
+        .expect("Failed to open with a device ID");
+    assert_eq!(mdx.meta().register_by, rust_mdict::RegisterBy::DeviceId);
+    assert_eq!(mdx.keyword_count(), 3);
+}
+
+fn invalid_format_message(bytes: &[u8]) -> String {
+    let file = common::write_temp(bytes, ".mdx");
+    match Mdx::new(file.path()) {
+        Err(MdictError::InvalidFormat(message)) => message,
+        Err(e) => panic!("Expected InvalidFormat, got {:?}", e),
+        Ok(_) => panic!("Expected InvalidFormat, got a dictionary"),
+    }
+}
+
+#[test]
+fn test_sections_past_end_of_file() {
+    let bytes = DictBuilder::mdx()
+        .words(&["apple", "banana", "cherry"])
+        .build();
+
+    // Truncated inside the record blocks
+    let message = invalid_format_message(&bytes[..bytes.len() - 3]);
+    assert!(message.starts_with("Record blocks"), "{}", message);
+
+    // Key block size in the key header pointing far past the end
+    let mut corrupt = bytes.clone();
+    let header_len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+    let key_blocks_size = header_len + 8 + 32;
+    corrupt[key_blocks_size..key_blocks_size + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+    let message = invalid_format_message(&corrupt);
+    assert!(message.starts_with("Key blocks"), "{}", message);
+
+    // Header length larger than the file
+    let mut corrupt = bytes.clone();
+    corrupt[..4].copy_from_slice(&0x7fff_ffffu32.to_be_bytes());
+    let message = invalid_format_message(&corrupt);
+    assert!(message.starts_with("Header"), "{}", message);
+}
+
+#[test]
+fn test_unique_keys() {
+    let file = DictBuilder::mdx()
+        .words(&["Apple", "banana", "apple", "APPLE", "banana", "cherry"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(mdx.keywords().len(), 6);
+    assert_eq!(mdx.unique_keys(), vec!["Apple", "banana", "cherry"]);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_lookup_metrics() {
+    use rust_mdict::LookupMetrics;
+    use std::sync::{Arc, Mutex};
+
+    let file = DictBuilder::mdx()
+        .words(&["apple", "banana", "cherry"])
+        .write();
+    let recorded: Arc<Mutex<Vec<LookupMetrics>>> = Arc::default();
+    let sink = recorded.clone();
+    let mut mdx = MdxBuilder::new()
+        .on_lookup(move |metrics| sink.lock().unwrap().push(metrics))
+        .open(file.path())
+        .expect("Failed to load MDX");
+
+    assert!(mdx.lookup("banana").is_some());
+    assert!(mdx.lookup("durian").is_none());
+    let item = mdx.lookup_keyword("cherry").unwrap().clone();
+    assert!(mdx.fetch(&item).is_some());
+
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.len(), 3);
+
+    assert_eq!(recorded[0].word, "banana");
+    assert!(recorded[0].found);
+    assert!(!recorded[0].cache_hit);
+    // The block holds "apple" (15 bytes) and "banana" (16 bytes)
+    assert_eq!(recorded[0].bytes_decompressed, 31);
+
+    assert_eq!(recorded[1].word, "durian");
+    assert!(!recorded[1].found);
+    assert_eq!(recorded[1].bytes_decompressed, 0);
+
+    assert_eq!(recorded[2].word, "cherry");
+    assert!(recorded[2].found);
+    assert_eq!(recorded[2].search, std::time::Duration::ZERO);
+    drop(recorded);
+
+    // Cache hits decompress nothing
+    let recorded: Arc<Mutex<Vec<LookupMetrics>>> = Arc::default();
+    let sink = recorded.clone();
+    let mut mdx = MdxBuilder::new()
+        .block_cache(rust_mdict::BlockCache::new(1024))
+        .on_lookup(move |metrics| sink.lock().unwrap().push(metrics))
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert!(mdx.lookup("apple").is_some());
+    assert!(mdx.lookup("banana").is_some());
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded[0].bytes_decompressed, 31);
+    assert!(recorded[1].cache_hit);
+    assert_eq!(recorded[1].bytes_decompressed, 0);
+}
+
+#[test]
+fn test_strip_modes() {
+    let words = ["ice  cream", "ice-cream", "icecream"];
+
+    // Default (StripKey="Yes"): punctuation and spaces are ignored
+    let file = DictBuilder::mdx().words(&words).write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().strip_mode, StripMode::StripPunct);
+    assert!(mdx.lookup("ice cream").is_some());
+    assert_eq!(mdx.unique_keys().len(), 1);
+
+    // Compact: whitespace runs collapse, punctuation still matters
+    let file = DictBuilder::mdx()
+        .attr("StripKey", "Compact")
+        .words(&words)
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().strip_mode, StripMode::CompactWhitespace);
+    assert_eq!(
+        mdx.lookup("ice cream").unwrap().definition,
+        "<p>ice  cream</p>\r\n\0"
+    );
+    assert_eq!(
+        mdx.lookup("ice-cream").unwrap().definition,
+        "<p>ice-cream</p>\r\n\0"
+    );
+    assert_eq!(mdx.unique_keys().len(), 3);
+
+    // No: keys compare as stored
+    let file = DictBuilder::mdx()
+        .attr("StripKey", "No")
+        .words(&words)
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().strip_mode, StripMode::None);
+    assert!(mdx.lookup("ice cream").is_none());
+    assert!(mdx.lookup("ice  cream").is_some());
+}
+
+#[test]
+fn test_num_format() {
+    let file = DictBuilder::mdx().words(&["a", "b"]).write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.num_format(), NumFmt::Uint64);
+    assert_eq!(mdx.meta().num_width, 8);
+
+    let file = DictBuilder::mdx().version("1.2").words(&["a", "b"]).write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.num_format(), NumFmt::Uint32);
+    assert_eq!(mdx.meta().num_width, 4);
+}
+
+/// Flip the last byte of the first occurrence of `needle`
+fn corrupt(bytes: &mut [u8], needle: &[u8]) {
+    let pos = bytes
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .expect("Needle not found");
+    bytes[pos + needle.len() - 1] ^= 0xff;
+}
+
+#[test]
+fn test_verify() {
+    let builder = DictBuilder::mdx()
+        .uncompressed()
+        .words(&["apple", "banana", "cherry", "date"]);
+
+    let file = builder.write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.verify().is_empty());
+
+    // "cherry" is in key block 1, "<p>banana" in record block 0
+    let mut bytes = builder.build();
+    corrupt(&mut bytes, b"cherry\0");
+    corrupt(&mut bytes, b"<p>banana");
+    let file = common::write_temp(&bytes, ".mdx");
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let errors = mdx.verify();
+    let failed: Vec<(BlockKind, usize)> = errors.iter().map(|e| (e.kind, e.index)).collect();
+    assert_eq!(failed, vec![(BlockKind::Key, 1), (BlockKind::Record, 0)]);
+    assert!(matches!(errors[0].error, MdictError::DecompressionError(_)));
+
+    #[cfg(feature = "parallel")]
+    {
+        let parallel: Vec<(BlockKind, usize)> = mdx
+            .verify_parallel()
+            .iter()
+            .map(|e| (e.kind, e.index))
+            .collect();
+        assert_eq!(parallel, failed);
+    }
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_export_zip_name_collision() {
+    let file = DictBuilder::mdd()
+        .entry("/a.png", b"slash")
+        .entry("\\a.png", b"backslash")
+        .write();
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+
+    // Both keys would be written as `a.png`
+    let mut out = std::io::Cursor::new(Vec::new());
+    let err = mdd.export_zip(&mut out).unwrap_err();
+    assert!(matches!(err, MdictError::InvalidFormat(_)), "{:?}", err);
+    assert!(out.into_inner().is_empty());
+}
+
+#[test]
+fn test_mdd_resolve() {
+    let file = DictBuilder::mdd()
+        .attr("KeyCaseSensitive", "Yes")
+        .entry("\\a.jpg", b"jpg")
+        .entry("\\a.png", b"png")
+        .entry("\\html\\style.css", b"css")
+        .entry("\\img\\Pic.png", b"pic")
+        .write();
+    let mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+
+    // Exact, with or without the leading separator, either slash
+    assert_eq!(mdd.resolve("\\a.png"), Some("\\a.png"));
+    assert_eq!(mdd.resolve("a.png"), Some("\\a.png"));
+    assert_eq!(mdd.resolve("/a.jpg"), Some("\\a.jpg"));
+    assert_eq!(mdd.resolve("./img/Pic.png"), Some("\\img\\Pic.png"));
+
+    // Schemes, queries and fragments are ignored
+    assert_eq!(mdd.resolve("sound://a.png"), Some("\\a.png"));
+    assert_eq!(mdd.resolve("img/Pic.png?v=2#top"), Some("\\img\\Pic.png"));
+
+    // Case-folded fallback
+    assert_eq!(mdd.resolve("IMG/pic.PNG"), Some("\\img\\Pic.png"));
+
+    // Relative to the current entry's directory
+    assert_eq!(mdd.resolve("style.css"), None);
+    assert_eq!(
+        mdd.resolve_in("\\html", "style.css"),
+        Some("\\html\\style.css")
+    );
+    assert_eq!(
+        mdd.resolve_in("html/", "style.css"),
+        Some("\\html\\style.css")
+    );
+
+    assert_eq!(mdd.resolve("missing.png"), None);
+    assert_eq!(mdd.resolve(""), None);
+
+    // Case-insensitive keys fold case without a scan
+    let file = DictBuilder::mdd().entry("\\img\\Pic.png", b"pic").write();
+    let mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+    assert_eq!(mdd.resolve("IMG/pic.PNG"), Some("\\img\\Pic.png"));
+    assert_eq!(mdd.resolve("img/other.png"), None);
+}
+
+fn gzip_encode(data: &[u8]) -> Vec<u8> {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_gzip_in_zlib_blocks() {
+    // Zlib-tagged key and record blocks holding gzip streams
+    let file = DictBuilder::mdx()
+        .words(&["apple", "banana", "cherry"])
+        .custom_blocks(2, gzip_encode)
+        .write();
+
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.keyword_count(), 3);
+    assert_eq!(
+        mdx.lookup("cherry").unwrap().definition,
+        "<p>cherry</p>\r\n\0"
+    );
+}
+
+/// GBK keys and records, written with the given header attributes
+fn gbk_dict(encoding_attr: &str) -> DictBuilder {
+    let mut builder = DictBuilder::mdx()
+        .attr("Encoding", encoding_attr)
+        .key_encoding(encoding_rs::GBK);
+    for (word, definition) in [
+        ("中国", "<p>中国是一个国家，我们在这里生活。</p>"),
+        ("人民", "<p>人民是国家的主人，也是历史的创造者。</p>"),
+        ("学习", "<p>学习就是从书本和生活中得到知识。</p>"),
+    ] {
+        let (record, _, _) = encoding_rs::GBK.encode(definition);
+        let mut record = record.into_owned();
+        record.push(0);
+        builder = builder.entry(word, record);
+    }
+    builder
+}
+
+#[test]
+#[cfg(feature = "cjk-detect")]
+fn test_detect_cjk_encoding() {
+    use rust_mdict::Encoding::{Gb18030, Utf8};
+
+    // No Encoding header: detected automatically
+    let file = gbk_dict("").write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Gb18030);
+    assert_eq!(
+        mdx.lookup("人民").unwrap().definition,
+        "<p>人民是国家的主人，也是历史的创造者。</p>\0"
+    );
+
+    // A wrong header is trusted unless detection is requested
+    let file = gbk_dict("UTF-8").write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Utf8);
+
+    let mut mdx = MdxBuilder::new()
+        .detect_cjk_encoding(true)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Gb18030);
+    assert!(mdx.lookup("学习").is_some());
+
+    let mdx = Mdx::open_with_cjk_detection(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Gb18030);
+
+    // UTF-8 dictionaries keep UTF-8
+    let file = DictBuilder::mdx()
+        .attr("Encoding", "")
+        .words(&["café", "naïve"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Utf8);
+}
+
+#[test]
+#[cfg(feature = "cjk-detect")]
+fn test_detect_cjk_encoding_keeps_utf16() {
+    let definition = |text: &str| -> Vec<u8> {
+        let mut bytes: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        bytes.extend([0, 0]);
+        bytes
+    };
+    let file = DictBuilder::mdx()
+        .utf16be()
+        .entry("apple", definition("<p>apple is a fruit</p>"))
+        .entry("banana", definition("<p>banana is yellow</p>"))
+        .write();
+
+    // UTF-16 records look like byte text with NULs in between; detection
+    // must not replace a declared UTF-16
+    let mut mdx = MdxBuilder::new()
+        .detect_cjk_encoding(true)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Encoding::Utf16Be);
+    assert_eq!(
+        mdx.lookup("banana").unwrap().definition,
+        "<p>banana is yellow</p>\0"
+    );
+}
+
+#[test]
+fn test_utf16be() {
+    let definition = |text: &str| -> Vec<u8> {
+        let mut bytes: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        bytes.extend([0, 0]);
+        bytes
+    };
+    let file = DictBuilder::mdx()
+        .utf16be()
+        .entry("café", definition("<p>café</p>"))
+        .entry("naïve", definition("<p>naïve</p>"))
+        .entry("zebra", definition("<p>zebra</p>"))
+        .write();
+
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Encoding::Utf16Be);
+    assert_eq!(mdx.header().get("Encoding").unwrap(), "UTF-16");
+    assert_eq!(mdx.keyword_count(), 3);
+    assert_eq!(mdx.lookup("naïve").unwrap().definition, "<p>naïve</p>\0");
+
+    let file = DictBuilder::mdd()
+        .utf16be()
+        .entry("\\a.png", b"png-a")
+        .entry("\\\u{0100}.png", b"png-0100")
+        .write();
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+    assert_eq!(mdd.meta().encoding, Encoding::Utf16Be);
+    assert_eq!(mdd.locate_raw("\\\u{0100}.png").unwrap(), b"png-0100");
+
+    // An explicit little-endian header wins over a big-endian BOM
+    let file = DictBuilder::mdx()
+        .utf16be()
+        .attr("Encoding", "UTF-16LE")
+        .entry("a", definition("<p>a</p>"))
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.meta().encoding, Encoding::Utf16Le);
+    assert!(mdx.meta().bom_conflict);
+    assert!(!mdd.meta().bom_conflict);
+}
+
+#[test]
+fn test_offset_fixup() {
+    // Record data starts with a stray byte the offsets don't account for
+    let file = DictBuilder::mdx()
+        .entry("alpha", "\0<p>alpha</p>")
+        .entry("beta", "\0<p>beta</p>\0")
+        .write();
+
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.lookup("alpha").unwrap().definition, "\0<p>alpha</p>");
+
+    let mut mdx = MdxBuilder::new()
+        .offset_fixup(1)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert_eq!(mdx.lookup("alpha").unwrap().definition, "<p>alpha</p>\0");
+    // The last record is clamped to the end of the block
+    assert_eq!(mdx.lookup("beta").unwrap().definition, "<p>beta</p>\0");
+
+    // Negative fixups clamp at the start of the block
+    let mut mdx = MdxBuilder::new()
+        .offset_fixup(-5)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert_eq!(mdx.lookup("alpha").unwrap().definition, "\0<p>alph");
+}
+
+#[test]
+fn test_last_entry_of_each_block() {
+    // Five words: key blocks and record blocks both split after every
+    // second entry, so the last word sits alone in the final blocks
+    let words = ["alpha", "bravo", "charlie", "delta", "echo"];
+    let file = DictBuilder::mdx()
+        .words(&words)
+        .keys_per_block(2)
+        .records_per_block(2)
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.key_block_ranges().len(), 3);
+
+    // The last keyword relies on the end-of-block fallback
+    let last = mdx.lookup_keyword("echo").unwrap().clone();
+    assert_eq!(last.record_end_offset, 0);
+    assert_eq!(mdx.fetch(&last).unwrap().definition, "<p>echo</p>\r\n\0");
+
+    // Entries ending a key or record block get the next block's start
+    for word in words {
+        assert_eq!(
+            mdx.lookup(word).unwrap().definition,
+            format!("<p>{}</p>\r\n\0", word)
+        );
+    }
+}
+
+#[test]
+fn test_max_block_size() {
+    let builder = DictBuilder::mdx().words(&["apple", "banana"]);
+
+    // Declare a 1 TiB record block: the record info's unpack size follows
+    // the 32-byte record header and the 8-byte pack size
+    let mut bytes = builder.build();
+    let unpack_size_at = bytes.len() - builder.record_section().len() + 40;
+    bytes[unpack_size_at..unpack_size_at + 8].copy_from_slice(&(1u64 << 40).to_be_bytes());
+    let file = common::write_temp(&bytes, ".mdx");
+
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.lookup("apple").is_none());
+    let item = mdx.lookup_keyword("apple").unwrap().clone();
+    assert!(matches!(
+        mdx.fetch_raw_batch(&[item])[0],
+        Err(MdictError::BlockTooLarge { size, .. }) if size == 1 << 40
+    ));
+
+    // A lower limit also applies to key blocks when opening
+    let file = builder.write();
+    assert!(matches!(
+        MdxBuilder::new().max_block_size(16).open(file.path()),
+        Err(MdictError::BlockTooLarge { limit: 16, .. })
+    ));
+    assert!(MdxBuilder::new()
+        .max_block_size(1024)
+        .open(file.path())
+        .is_ok());
+}
+
+#[test]
+fn test_lookup_with_fallback_strip() {
+    use rust_mdict::MatchKind;
+
+    let file = DictBuilder::mdx()
+        .attr("KeyCaseSensitive", "Yes")
+        .words(&["Apple", "ice cream", "ice-cream"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let (result, kind) = mdx.lookup_with_fallback_strip("ice-cream").unwrap();
+    assert_eq!(
+        (result.key_text.as_str(), kind),
+        ("ice-cream", MatchKind::Exact)
+    );
+    let (result, kind) = mdx.lookup_with_fallback_strip("ice cream").unwrap();
+    assert_eq!(
+        (result.key_text.as_str(), kind),
+        ("ice cream", MatchKind::Exact)
+    );
+
+    let (result, kind) = mdx.lookup_with_fallback_strip("icecream").unwrap();
+    assert_eq!(kind, MatchKind::Stripped);
+    assert!(result.key_text.starts_with("ice"));
+
+    // Case-sensitive keys only match case-insensitively as a last resort
+    assert!(mdx.lookup("apple").is_none());
+    let (result, kind) = mdx.lookup_with_fallback_strip("apple").unwrap();
+    assert_eq!(
+        (result.key_text.as_str(), kind),
+        ("Apple", MatchKind::CaseFolded)
+    );
+
+    assert!(mdx.lookup_with_fallback_strip("banana").is_none());
+}
+
+#[test]
+fn test_lookup_fold_case() {
+    let file = DictBuilder::mdx()
+        .words(&["U.S.A.", "USA", "usage"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(mdx.lookup_fold_case("u.s.a.").unwrap().key_text, "U.S.A.");
+    assert_eq!(mdx.lookup_fold_case("Usa").unwrap().key_text, "USA");
+    assert!(mdx.lookup_fold_case("us.a").is_none());
+
+    // Case-sensitive keys are found by the scan
+    let file = DictBuilder::mdx()
+        .attr("KeyCaseSensitive", "Yes")
+        .words(&["Apple", "U.S.A."])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.lookup("u.s.a.").is_none());
+    assert_eq!(mdx.lookup_fold_case("u.s.a.").unwrap().key_text, "U.S.A.");
+    assert_eq!(mdx.lookup_fold_case("APPLE").unwrap().key_text, "Apple");
+}
+
+#[test]
+fn test_lookup_fold_diacritics() {
+    let file = DictBuilder::mdx()
+        .words(&["nǚ", "tiếng việt", "xiǎo", "άλφα"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    // Pinyin tone letters, Vietnamese and Greek tonos all fold
+    assert_eq!(mdx.lookup_fold_diacritics("nu").unwrap().key_text, "nǚ");
+    assert_eq!(mdx.lookup_fold_diacritics("xiao").unwrap().key_text, "xiǎo");
+    assert_eq!(
+        mdx.lookup_fold_diacritics("tieng viet").unwrap().key_text,
+        "tiếng việt"
+    );
+    assert_eq!(mdx.lookup_fold_diacritics("αλφα").unwrap().key_text, "άλφα");
+    assert!(mdx.lookup_fold_diacritics("nuu").is_none());
+}
+
+#[test]
+fn test_global_css_and_js() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sample.mdx");
+    let bytes = DictBuilder::mdx()
+        .words(&["apple"])
+        .entry("sample.css", "p { color: red }\0")
+        .build();
+    std::fs::write(&path, bytes).unwrap();
+
+    let mut mdx = Mdx::new(&path).expect("Failed to load MDX");
+    assert_eq!(mdx.global_css().as_deref(), Some("p { color: red }"));
+    assert_eq!(mdx.global_js(), None);
+
+    // Falls back to a file next to the dictionary
+    std::fs::write(dir.path().join("sample.js"), "init();\n").unwrap();
+    assert_eq!(mdx.global_js().as_deref(), Some("init();\n"));
+
+    // An entry that can't be read also falls back to the file
+    let css = format!("p {{ color: red }}{}\0", " ".repeat(1024));
+    let bytes = DictBuilder::mdx()
+        .uncompressed()
+        .words(&["apple"])
+        .entry("sample.css", css)
+        .build();
+    std::fs::write(&path, bytes).unwrap();
+    std::fs::write(dir.path().join("sample.css"), "p { color: blue }").unwrap();
+    let mut mdx = MdxBuilder::new()
+        .max_block_size(512)
+        .open(&path)
+        .expect("Failed to load MDX");
+    assert!(mdx.lookup("sample.css").is_none());
+    assert_eq!(mdx.global_css().as_deref(), Some("p { color: blue }"));
+}
+
+#[test]
+fn test_mdd_open_optional_and_try_locate() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(Mdd::open_optional(dir.path().join("missing.mdd"))
+        .unwrap()
+        .is_none());
+
+    // A file that exists but is corrupt is still an error
+    let corrupt = common::write_temp(b"not an mdd", ".mdd");
+    assert!(Mdd::open_optional(corrupt.path()).is_err());
+
+    let builder = DictBuilder::mdd().entry("\\a.png", b"png-a");
+    let file = builder.write();
+    let mut mdd = Mdd::open_optional(file.path())
+        .unwrap()
+        .expect("MDD should exist");
+    assert_eq!(mdd.try_locate_raw("\\a.png").unwrap().unwrap(), b"png-a");
+    assert!(mdd.try_locate_raw("\\b.png").unwrap().is_none());
+    assert_eq!(
+        mdd.try_locate("\\a.png").unwrap().unwrap().definition,
+        "cG5nLWE="
+    );
+    assert!(mdd.try_locate("\\b.png").unwrap().is_none());
+
+    // Listed in the key index, but without record data
+    let bytes = with_record_section(&builder, &[0u8; 32]);
+    let file = common::write_temp(&bytes, ".mdd");
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+    match mdd.try_locate_raw("\\a.png") {
+        Err(MdictError::ResourceUnreadable { key, source }) => {
+            assert_eq!(key, "\\a.png");
+            assert!(matches!(*source, MdictError::NoRecordBlocks));
+        }
+        other => panic!("Expected ResourceUnreadable, got {:?}", other),
+    }
+    assert!(matches!(
+        mdd.try_locate("\\a.png"),
+        Err(MdictError::ResourceUnreadable { .. })
+    ));
+    assert!(mdd.locate("\\a.png").is_none());
+}
+
+#[test]
+fn test_suffix_keys() {
+    let file = DictBuilder::mdx()
+        .words(&[
+            "action",
+            "nation",
+            "Station",
+            "stationery",
+            "motion",
+            "lion",
+        ])
+        .write();
+
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    let mut scanned = mdx.suffix_keys("tion", 10);
+    scanned.sort();
+    assert_eq!(scanned, vec!["Station", "action", "motion", "nation"]);
+    assert_eq!(mdx.suffix_keys("tion", 2).len(), 2);
+
+    let mdx = MdxBuilder::new()
+        .with_suffix_index(true)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    let mut indexed = mdx.suffix_keys("TION", 10);
+    indexed.sort();
+    assert_eq!(indexed, scanned);
+    assert_eq!(mdx.suffix_keys("ion", 10).len(), 5);
+    assert!(mdx.suffix_keys("xyz", 10).is_empty());
+}
+
+#[test]
+fn test_odd_length_utf16_key_block() {
+    let builder = DictBuilder::mdd()
+        .keys_per_block(3)
+        .entry("\\a.png", b"a")
+        .entry("\\b.png", b"b")
+        .entry("\\c.png", b"c");
+    let file = builder.write();
+    let mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+    assert_eq!(mdd.key_decode_warnings(), 0);
+
+    // Drop the last key's terminator and one more byte, leaving text of
+    // odd length running to the end of the block
+    let entries: Vec<(String, u64)> = ["\\a.png", "\\b.png", "\\c.png"]
+        .iter()
+        .zip(builder.record_offsets())
+        .map(|(key, offset)| (key.to_string(), offset))
+        .collect();
+    let mut data = builder.key_block_data(&entries);
+    data.truncate(data.len() - 3);
+    let bytes =
+        builder.build_with_key_blocks(&[(data, 3, "\\a.png".to_string(), "\\c.png".to_string())]);
+    let file = common::write_temp(&bytes, ".mdd");
+
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+    assert_eq!(mdd.resource_keys(), vec!["\\a.png", "\\b.png", "\\c.pn"]);
+    assert_eq!(mdd.key_decode_warnings(), 1);
+    assert_eq!(mdd.locate_raw("\\b.png").unwrap(), b"b");
+}
+
+#[test]
+fn test_key_info_only() {
+    let file = DictBuilder::mdx()
+        .words(&["alpha", "bravo", "charlie", "delta", "echo"])
+        .keys_per_block(2)
+        .write();
+
+    let mut mdx = MdxBuilder::new()
+        .key_info_only(true)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert_eq!(mdx.keyword_count(), 0);
+    assert!(mdx.lookup("alpha").is_none());
+
+    let counts: Vec<u64> = mdx
+        .key_info_list()
+        .iter()
+        .map(|info| info.key_block_entries_num)
+        .collect();
+    assert_eq!(counts, vec![2, 2, 1]);
+    assert_eq!(
+        mdx.key_block_ranges()[1],
+        ("charlie".to_string(), "delta".to_string())
+    );
+}
+
+#[test]
+fn test_lookup_inlined() {
+    let mdx_file = DictBuilder::mdx()
+        .entry(
+            "logo",
+            "<img src=\"logo.png\"><img src=\"https://example.com/x.png\">\
+             <span style=\"background: url(img/bg.gif)\"></span><img src=\"missing.png\">\0",
+        )
+        .write();
+    let mdd_file = DictBuilder::mdd()
+        .entry("\\logo.png", b"PNG")
+        .entry("\\img\\bg.gif", b"GIF")
+        .write();
+
+    let mut mdx = Mdx::new(mdx_file.path()).expect("Failed to load MDX");
+    let mut mdd = Mdd::new(mdd_file.path()).expect("Failed to load MDD");
+
+    assert_eq!(
+        mdd.locate_data_uri("\\logo.png").as_deref(),
+        Some("data:image/png;base64,UE5H")
+    );
+    assert_eq!(
+        mdx.lookup_inlined("logo", &mut mdd).unwrap(),
+        "<img src=\"data:image/png;base64,UE5H\"><img src=\"https://example.com/x.png\">\
+         <span style=\"background: url(data:image/gif;base64,R0lG)\"></span>\
+         <img src=\"missing.png\">\0"
+    );
+    assert!(mdx.lookup_inlined("absent", &mut mdd).is_none());
+}
+
+#[test]
+fn test_shared_block_cache() {
+    use rust_mdict::BlockCache;
+
+    let words = ["alpha", "bravo", "charlie", "delta"];
+    // Two "editions" with identical record blocks but different headers
+    let first = DictBuilder::mdx().words(&words).write();
+    let second = DictBuilder::mdx()
+        .attr("Title", "Second edition")
+        .words(&words)
+        .write();
+
+    let cache = BlockCache::new(1024 * 1024);
+    let mut first = MdxBuilder::new()
+        .block_cache(cache.clone())
+        .open(first.path())
+        .expect("Failed to load MDX");
+    let mut second = MdxBuilder::new()
+        .block_cache(cache.clone())
+        .open(second.path())
+        .expect("Failed to load MDX");
+
+    for word in words {
+        assert!(first.lookup(word).is_some());
+    }
+    let after_first = cache.stats();
+    assert_eq!((after_first.hits, after_first.misses), (2, 2));
+    assert_eq!(after_first.entries, 2);
+
+    // Every block of the second dictionary is already cached
+    for word in words {
+        assert_eq!(
+            second.lookup(word).unwrap().definition,
+            format!("<p>{}</p>\r\n\0", word)
+        );
+    }
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (6, 2));
+    assert_eq!(stats.hit_rate(), 0.75);
+
+    // A custom decompressor never sees blocks another one decoded, even
+    // once the first is dropped and its allocation reused
+    struct Upper(bool);
+    impl BlockDecompressor for Upper {
+        fn decompress(&self, tag: u32, data: &[u8], size: usize) -> rust_mdict::Result<Vec<u8>> {
+            let mut block = DefaultDecompressor.decompress(tag, data, size)?;
+            if self.0 {
+                block.make_ascii_uppercase();
+            }
+            Ok(block)
+        }
+    }
+    for upper in [true, false] {
+        let mut mdx = MdxBuilder::new()
+            .decompressor(Upper(upper))
+            .block_cache(cache.clone())
+            .open(first.filepath())
+            .expect("Failed to load MDX");
+        let expected = if upper {
+            "<P>ALPHA</P>\r\n\0"
+        } else {
+            "<p>alpha</p>\r\n\0"
+        };
+        assert_eq!(mdx.lookup("alpha").unwrap().definition, expected);
+    }
+}
+
+/// Hit rate of one `BlockCache` shared by three derived editions of a
+/// 2,000-entry dictionary, looked up in turn
+///
+/// The second edition revises 20 definitions scattered through the text,
+/// the third also appends 200 entries. Each reader looks up every fifth
+/// headword of its edition, twice over.
+#[test]
+fn test_block_cache_derived_editions() {
+    use rust_mdict::BlockCache;
+
+    let words: Vec<String> = (0..2000).map(|i| format!("word{:04}", i)).collect();
+    let edition = |revised: bool, appended: usize| {
+        let mut builder = DictBuilder::mdx().records_per_block(50);
+        for (i, word) in words.iter().enumerate() {
+            let definition = if revised && i % 100 == 7 {
+                format!("<p>{} (revised)</p>\r\n\0", word)
+            } else {
+                format!("<p>{}</p>\r\n\0", word)
+            };
+            builder = builder.entry(word, definition);
+        }
+        for i in 0..appended {
+            builder = builder.entry(&format!("zword{:04}", i), "<p>new</p>\r\n\0");
+        }
+        builder.write()
+    };
+    let files = [edition(false, 0), edition(true, 0), edition(true, 200)];
+
+    let cache = BlockCache::new(16 * 1024 * 1024);
+    let mut editions: Vec<Mdx> = files
+        .iter()
+        .map(|file| {
+            MdxBuilder::new()
+                .block_cache(cache.clone())
+                .open(file.path())
+                .expect("Failed to load MDX")
+        })
+        .collect();
+    for _ in 0..2 {
+        for mdx in &mut editions {
+            for word in words.iter().step_by(5) {
+                assert!(mdx.lookup(word).is_some());
+            }
+        }
+    }
+
+    // 40 blocks per edition: the first decompresses all of them, the
+    // revised ones only their 20 changed blocks, once between them as the
+    // third shares the second's revisions
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (2340, 60));
+    assert_eq!(stats.entries, 60);
+    assert_eq!(stats.hit_rate(), 0.975);
+}
+
+#[test]
+fn test_try_lookup_reports_decoding() {
+    let file = DictBuilder::mdx()
+        .entry("good", "<p>café</p>\0")
+        .entry("bad", b"<p>caf\xe9</p>\0")
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let good = mdx.try_lookup("good").unwrap();
+    assert_eq!(good.definition, "<p>café</p>\0");
+    assert_eq!(good.encoding, rust_mdict::Encoding::Utf8);
+    assert!(!good.lossy);
+
+    let bad = mdx.try_lookup("bad").unwrap();
+    assert!(bad.lossy);
+    assert_eq!(bad.definition, "<p>caf\u{FFFD}</p>\0");
+
+    assert!(matches!(
+        mdx.try_lookup("missing"),
+        Err(MdictError::KeyNotFound(_))
+    ));
+}
+
+#[test]
+fn test_self_test_and_entries_in_record_block() {
+    let words = ["alpha", "bravo", "charlie", "delta", "echo"];
+    let builder = DictBuilder::mdx().words(&words).records_per_block(2);
+    let file = builder.write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.self_test().is_ok());
+    let counts: Vec<usize> = (0..3).map(|i| mdx.entries_in_record_block(i)).collect();
+    assert_eq!(counts, vec![2, 2, 1]);
+
+    // Shift "charlie" and later records so "bravo" ends inside block 1
+    let offsets = builder.record_offsets();
+    let entries: Vec<(String, u64)> = words
+        .iter()
+        .zip(&offsets)
+        .map(|(word, &offset)| {
+            let shift = if offset >= offsets[2] { 3 } else { 0 };
+            (word.to_string(), offset + shift)
+        })
+        .collect();
+    let bytes = builder.build_with_key_blocks(&[(
+        builder.key_block_data(&entries),
+        5,
+        "alpha".to_string(),
+        "echo".to_string(),
+    )]);
+    let file = common::write_temp(&bytes, ".mdx");
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    let message = match mdx.self_test() {
+        Err(MdictError::InvalidFormat(message)) => message,
+        other => panic!("Expected InvalidFormat, got {:?}", other),
+    };
+    assert!(message.contains("\"bravo\" spans record blocks 0 and 1"));
+}
+
+#[test]
+fn test_from_shared() {
+    let data: std::sync::Arc<[u8]> = DictBuilder::mdx()
+        .words(&["alpha", "beta", "gamma"])
+        .build()
+        .into();
+
+    let mut first = Mdx::from_shared(data.clone()).expect("Failed to load MDX");
+    let mut second = MdxBuilder::new()
+        .open_shared(data.clone())
+        .expect("Failed to load MDX");
+    assert_eq!(std::sync::Arc::strong_count(&data), 3);
+    assert_eq!(first.filepath(), "");
+    assert_eq!(
+        first.lookup("beta").unwrap().definition,
+        "<p>beta</p>\r\n\0"
+    );
+    assert_eq!(
+        second.lookup("gamma").unwrap().definition,
+        "<p>gamma</p>\r\n\0"
+    );
+
+    let data: std::sync::Arc<[u8]> = DictBuilder::mdd().entry("\\a.png", b"png").build().into();
+    let mut mdd = Mdd::from_shared(data).expect("Failed to load MDD");
+    assert_eq!(mdd.locate_raw("\\a.png").unwrap(), b"png");
+
+    // A truncated buffer fails like a truncated file
+    let data = DictBuilder::mdx().words(&["alpha"]).build();
+    let truncated: std::sync::Arc<[u8]> = data[..data.len() - 4].into();
+    assert!(Mdx::from_shared(truncated).is_err());
+}
+
+#[test]
+fn test_export_stardict_with_progress() {
+    let file = DictBuilder::mdx()
+        .words(&["alpha", "beta", "gamma", "delta", "epsilon"])
+        .records_per_block(2)
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut calls = Vec::new();
+    mdx.export_stardict_with_progress(&dir.path().join("dict"), |done, total| {
+        calls.push((done, total))
+    })
+    .expect("Failed to export");
+
+    assert_eq!(calls, vec![(1, 3), (2, 3), (3, 3)]);
+    assert!(dir.path().join("dict.idx").exists());
+}
+
+#[test]
+fn test_record_block_index_at_block_boundaries() {
+    // Two records per block: every other entry starts a block
+    let words = ["alpha", "beta", "delta", "epsilon", "gamma"];
+    let builder = DictBuilder::mdx().words(&words).records_per_block(2);
+    let offsets = builder.record_offsets();
+    let file = builder.write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    for (i, word) in words.iter().enumerate() {
+        let item = mdx.keyword_list()[i].clone();
+        assert_eq!(item.key_text, *word);
+        assert_eq!(item.record_start_offset, offsets[i]);
+        assert_eq!(mdx.record_block_index(&item), i / 2, "{}", word);
+
+        // The byte before a block's first record belongs to the previous
+        // block
+        if i > 0 && i % 2 == 0 {
+            let before = KeyWordItem {
+                record_start_offset: offsets[i] - 1,
+                ..item.clone()
+            };
+            assert_eq!(mdx.record_block_index(&before), i / 2 - 1);
+        }
+
+        let expected = format!("<p>{}</p>\r\n\0", word);
+        assert_eq!(mdx.fetch(&item).unwrap().definition, expected);
+    }
+}
+
+#[test]
+fn test_preview() {
+    use rust_mdict::PreviewFormat;
+
+    let file = DictBuilder::mdx()
+        .entry(
+            "tom",
+            "<div><b>Tom</b> &amp; <i>Jerry</i> chase each other</div>\r\n\0",
+        )
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(
+        mdx.preview("tom", 11, PreviewFormat::Text).unwrap(),
+        "Tom & Jerry…"
+    );
+    assert_eq!(
+        mdx.preview("tom", 9, PreviewFormat::Html).unwrap(),
+        "<div><b>Tom</b> &amp; <i>Jer…</i></div>"
+    );
+    assert_eq!(
+        mdx.preview("tom", 100, PreviewFormat::Text).unwrap(),
+        "Tom & Jerry chase each other"
+    );
+    assert!(mdx.preview("jerry", 10, PreviewFormat::Text).is_none());
+}
+
+#[test]
+fn test_open_bundle() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, builder: DictBuilder| {
+        std::fs::write(dir.path().join(name), builder.build()).unwrap();
+    };
+    write(
+        "dict.mdx",
+        DictBuilder::mdx().entry("cat", "<img src=\"cat.png\"><img src=\"dog.png\">\0"),
+    );
+    write("dict.mdd", DictBuilder::mdd().entry("\\cat.png", b"meow"));
+    write("dict.1.mdd", DictBuilder::mdd().entry("\\dog.png", b"woof"));
+    write("other.mdd", DictBuilder::mdd().entry("\\cat.png", b"other"));
+
+    let paths = rust_mdict::companion_mdd_paths(&dir.path().join("dict.mdx"));
+    let names: Vec<_> = paths.iter().map(|p| p.file_name().unwrap()).collect();
+    assert_eq!(names, ["dict.mdd", "dict.1.mdd"]);
+
+    let mut bundle = rust_mdict::open_bundle(dir.path()).expect("Failed to open bundle");
+    assert_eq!(bundle.mdd.len(), 2);
+    assert_eq!(bundle.locate_raw("\\dog.png").unwrap(), b"woof");
+    assert_eq!(
+        bundle.lookup("cat").unwrap(),
+        "<img src=\"data:image/png;base64,bWVvdw==\"><img src=\"data:image/png;base64,d29vZg==\">\0"
+    );
+
+    // A second MDX makes the folder ambiguous
+    write("more.mdx", DictBuilder::mdx().words(&["x"]));
+    assert!(matches!(
+        rust_mdict::open_bundle(dir.path()),
+        Err(MdictError::InvalidFormat(_))
+    ));
+}
+
+#[test]
+fn test_is_sorted_for_lookup() {
+    // Stored out of order and with punctuation the sort must strip
+    let file = DictBuilder::mdx()
+        .words(&["zebra", "Apple", "ice-cream", "ice cream", "banana"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.is_sorted_for_lookup());
+    assert_eq!(mdx.first_unsorted_pair(), None);
+
+    let mut mdx = MdxBuilder::new()
+        .verify_sort_order(true)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert!(mdx.lookup("icecream").is_some());
+
+    let mdx = Mdx::open_incremental(file.path())
+        .unwrap()
+        .finish()
+        .unwrap();
+    assert!(mdx.is_sorted_for_lookup());
+}
+
+#[test]
+fn test_is_sorted_for_lookup_detects_final_sigma() {
+    // Whole-string lowercasing turns the final `Σ` into `ς`, which sorts
+    // before `αςα`; lowercasing per character gives `σ`, which sorts after
+    let file = DictBuilder::mdx().words(&["αςα", "ΑΣ"]).write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(!mdx.is_sorted_for_lookup());
+    assert_eq!(mdx.first_unsorted_pair(), Some(("ΑΣ", "αςα")));
+
+    assert!(matches!(
+        MdxBuilder::new().verify_sort_order(true).open(file.path()),
+        Err(MdictError::InvalidFormat(_))
+    ));
+}
+
+#[test]
+fn test_locate_base64_to() {
+    use base64::Engine as _;
+
+    // Larger than one encoding chunk, and not a multiple of 3
+    let audio: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let file = DictBuilder::mdd()
+        .entry("\\a.mp3", &audio)
+        .entry("\\b.png", b"png")
+        .write();
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+
+    let mut out = String::new();
+    assert_eq!(mdd.locate_base64_to("\\a.mp3", &mut out).unwrap(), Some(()));
+    assert_eq!(
+        out,
+        base64::engine::general_purpose::STANDARD.encode(&audio)
+    );
+    assert_eq!(out, mdd.locate("\\a.mp3").unwrap().definition);
+
+    let mut out = String::new();
+    assert_eq!(
+        mdd.locate_base64_to("\\missing.png", &mut out).unwrap(),
+        None
+    );
+    assert!(out.is_empty());
+}
+
+#[test]
+fn test_gloss() {
+    let file = DictBuilder::mdx()
+        .words(&["New", "New York", "New York City", "is", "big"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let glosses = mdx.gloss(&["New", "York", "City", "is", "very", "big"]);
+    let spans: Vec<(std::ops::Range<usize>, &str)> = glosses
+        .iter()
+        .map(|(range, result)| (range.clone(), result.key_text.as_str()))
+        .collect();
+    assert_eq!(
+        spans,
+        vec![(0..3, "New York City"), (3..4, "is"), (5..6, "big")]
+    );
+
+    let glosses = mdx.gloss(&["New", "York", "is"]);
+    assert_eq!(glosses[0].0, 0..2);
+    assert_eq!(glosses[0].1.definition, "<p>New York</p>\r\n\0");
+    assert!(mdx.gloss(&[]).is_empty());
+}
+
+#[test]
+fn test_strict_keys() {
+    let builder = DictBuilder::mdx().words(&["alpha", "beta"]);
+    let offsets = builder.record_offsets();
+    let entries = vec![
+        ("alpha".to_string(), offsets[0]),
+        ("beta".to_string(), offsets[1]),
+    ];
+    // 8-byte offset + "alpha\0", then 8-byte offset: "beta" is at byte 22
+    let mut data = builder.key_block_data(&entries);
+    data[23] = 0xff;
+    let bytes =
+        builder.build_with_key_blocks(&[(data, 2, "alpha".to_string(), "beta".to_string())]);
+    let file = common::write_temp(&bytes, ".mdx");
+
+    // Lenient by default: the key is kept, empty
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx
+        .keyword_list()
+        .iter()
+        .any(|item| item.key_text.is_empty()));
+
+    let err = MdxBuilder::new()
+        .strict_keys(true)
+        .open(file.path())
+        .err()
+        .expect("strict open should fail");
+    match err {
+        MdictError::EncodingError(message) => {
+            assert!(message.contains("Key block 0"), "{}", message);
+            assert!(message.contains("byte 22"), "{}", message);
+            assert!(message.contains("bad byte at 23"), "{}", message);
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn test_block_entries() {
+    let file = DictBuilder::mdx()
+        .words(&["alpha", "beta", "delta", "epsilon", "gamma"])
+        .records_per_block(2)
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let entries: Vec<(String, String)> = mdx.block_entries(1).unwrap().collect();
+    assert_eq!(
+        entries,
+        vec![
+            ("delta".to_string(), "<p>delta</p>\r\n\0".to_string()),
+            ("epsilon".to_string(), "<p>epsilon</p>\r\n\0".to_string()),
+        ]
+    );
+    assert_eq!(mdx.block_entries(2).unwrap().count(), 1);
+    assert!(mdx.block_entries(3).is_err());
+}
+
+#[test]
+fn test_unterminated_last_key() {
+    let builder = DictBuilder::mdx().words(&["alpha", "beta"]);
+    let offsets = builder.record_offsets();
+    let entries = vec![
+        ("alpha".to_string(), offsets[0]),
+        ("beta".to_string(), offsets[1]),
+    ];
+    let mut data = builder.key_block_data(&entries);
+    assert_eq!(data.pop(), Some(0));
+    let bytes =
+        builder.build_with_key_blocks(&[(data, 2, "alpha".to_string(), "beta".to_string())]);
+    let file = common::write_temp(&bytes, ".mdx");
+
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.keyword_count(), 2);
+    assert_eq!(mdx.lookup("beta").unwrap().definition, "<p>beta</p>\r\n\0");
+
+    // Bytes past the declared entry count aren't read as keys
+    let mut data = builder.key_block_data(&entries);
+    data.extend_from_slice(&[0; 8]);
+    data.extend_from_slice(b"junk\0");
+    let bytes =
+        builder.build_with_key_blocks(&[(data, 2, "alpha".to_string(), "beta".to_string())]);
+    let file = common::write_temp(&bytes, ".mdx");
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.keyword_count(), 2);
+    assert!(!mdx.contains("junk"));
+}
+
+#[test]
+fn test_media_category() {
+    use rust_mdict::MediaCategory;
+
+    let file = DictBuilder::mdd()
+        .entry("\\a.mp3", b"mp3")
+        .entry("\\clip.mp4", b"mp4")
+        .entry("\\style.css", b"css")
+        .entry("\\x.bin", b"bin")
+        .write();
+    let mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+
+    assert_eq!(mdd.media_category("\\a.mp3"), Some(MediaCategory::Audio));
+    assert_eq!(mdd.media_category("\\clip.mp4"), Some(MediaCategory::Video));
+    assert_eq!(
+        mdd.media_category("\\style.css"),
+        Some(MediaCategory::StyleSheet)
+    );
+    assert_eq!(mdd.media_category("\\x.bin"), Some(MediaCategory::Other));
+    assert_eq!(mdd.media_category("\\missing.png"), None);
+}
+
+#[test]
+fn test_open_with_summary() {
+    let file = DictBuilder::mdx()
+        .attr("Title", "Tiny")
+        .attr("Description", "&lt;b&gt;About&lt;/b&gt;")
+        .words(&["alpha", "beta", "gamma"])
+        .records_per_block(2)
+        .write();
+
+    let (mut mdx, summary) = Mdx::open(file.path()).expect("Failed to load MDX");
+    assert_eq!(summary.title.as_deref(), Some("Tiny"));
+    assert_eq!(summary.description.as_deref(), Some("<b>About</b>"));
+    assert_eq!(summary.version, 2.0);
+    assert_eq!(summary.encoding, Encoding::Utf8);
+    assert_eq!(summary.keyword_count, 3);
+    assert_eq!(summary.key_block_count, 2);
+    assert_eq!(summary.record_block_count, 2);
+    assert_eq!(summary.record_data_size, 15 + 14 + 15);
+    assert!(mdx.lookup("beta").is_some());
+}
+
+#[test]
+fn test_v1_offset_overflow() {
+    let builder = DictBuilder::mdx()
+        .version("1.2")
+        .records_per_block(1)
+        .words(&["apple", "banana"]);
+
+    // Declare two 3 GiB record blocks: each unpack size follows the 16-byte
+    // record header and its block's 4-byte pack size
+    let mut bytes = builder.build();
+    let record_info_at = bytes.len() - builder.record_section().len() + 16;
+    for unpack_size_at in [record_info_at + 4, record_info_at + 12] {
+        bytes[unpack_size_at..unpack_size_at + 4].copy_from_slice(&0xc000_0000u32.to_be_bytes());
+    }
+    let file = common::write_temp(&bytes, ".mdx");
+    assert!(matches!(
+        Mdx::new(file.path()),
+        Err(MdictError::OffsetOverflow { total }) if total == 0x1_8000_0000
+    ));
+}
+
+#[test]
+fn test_cached_lookup() {
+    let file = DictBuilder::mdx().words(&["apple", "banana"]).write();
+
+    let mut mdx = MdxBuilder::new()
+        .cache_definitions(true)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    let first = mdx.cached_lookup("apple").unwrap();
+    assert_eq!(&*first, "<p>apple</p>\r\n\0");
+    assert!(std::sync::Arc::ptr_eq(
+        &first,
+        &mdx.cached_lookup("Apple").unwrap()
+    ));
+    assert!(mdx.cached_lookup("cherry").is_none());
+
+    // Without the option every lookup decodes its own copy
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    let first = mdx.cached_lookup("apple").unwrap();
+    let second = mdx.cached_lookup("apple").unwrap();
+    assert_eq!(first, second);
+    assert!(!std::sync::Arc::ptr_eq(&first, &second));
+}
+
+#[test]
+fn test_locate_range() {
+    let file = DictBuilder::mdd().entry("\\a.mp3", b"0123456789").write();
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+
+    assert_eq!(mdd.locate_range("\\a.mp3", 2, 3).unwrap(), b"234");
+    assert_eq!(mdd.locate_range("\\a.mp3", 7, u64::MAX).unwrap(), b"789");
+    assert!(mdd.locate_range("\\a.mp3", 10, 5).unwrap().is_empty());
+    assert!(mdd.locate_range("\\missing.mp3", 0, 1).is_none());
+}
+
+#[test]
+fn test_initial_letters() {
+    let file = DictBuilder::mdx()
+        .words(&["Apple", "apricot", "banana", "cherry", "citrus", "coconut"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(
+        mdx.initial_letters(),
+        [('a', 2, 0), ('b', 1, 2), ('c', 3, 3)]
+    );
+}
+
+#[test]
+fn test_single_entry_key_block_without_terminator() {
+    let builder = DictBuilder::mdd()
+        .keys_per_block(1)
+        .entry("\\a.png", b"png")
+        .entry("\\b.png", b"PNG");
+    let offsets = builder.record_offsets();
+
+    // Each block holds one UTF-16 key with its terminator dropped
+    let key_blocks: Vec<_> = [("\\a.png", offsets[0]), ("\\b.png", offsets[1])]
+        .into_iter()
+        .map(|(key, offset)| {
+            let mut data = builder.key_block_data(&[(key.to_string(), offset)]);
+            data.truncate(data.len() - 2);
+            (data, 1, key.to_string(), key.to_string())
+        })
+        .collect();
+    let file = common::write_temp(&builder.build_with_key_blocks(&key_blocks), ".mdd");
+
+    let mut mdd = MddBuilder::new()
+        .strict_keys(true)
+        .open(file.path())
+        .expect("Failed to load MDD");
+    assert_eq!(mdd.resource_keys(), ["\\a.png", "\\b.png"]);
+    assert_eq!(mdd.key_decode_warnings(), 0);
+    assert_eq!(mdd.locate_raw("\\b.png").unwrap(), b"PNG");
+}
+
+#[test]
+fn test_related() {
+    let words = ["alpha", "beta", "delta", "epsilon", "eta", "gamma"];
+    let file = DictBuilder::mdx().keys_per_block(6).words(&words).write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let keys = |results: Vec<rust_mdict::LookupResult>| -> Vec<String> {
+        results.into_iter().map(|r| r.key_text).collect()
+    };
+    assert_eq!(keys(mdx.related("epsilon", 3)), ["delta", "epsilon", "eta"]);
+    // Near the block's edge the window shifts instead of shrinking
+    assert_eq!(keys(mdx.related("alpha", 3)), ["alpha", "beta", "delta"]);
+    assert_eq!(keys(mdx.related("gamma", 2)), ["eta", "gamma"]);
+    assert_eq!(mdx.related("gamma", 10).len(), 6);
+
+    let related = mdx.related("beta", 1);
+    assert_eq!(related[0].definition, "<p>beta</p>\r\n\0");
+    // A missing word is centered like its nearest keyword, as in `associate`
+    assert_eq!(keys(mdx.related("zeta", 1)), ["gamma"]);
+}
+
+#[test]
+fn test_engine_version_variants() {
+    for version in ["2", "2.0.0", "3.0"] {
+        let file = DictBuilder::mdx()
+            .version(version)
+            .words(&["apple", "banana"])
+            .write();
+        let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+        assert_eq!(mdx.num_format(), NumFmt::Uint64, "version {}", version);
+        assert_eq!(
+            mdx.lookup("banana").unwrap().definition,
+            "<p>banana</p>\r\n\0"
+        );
+    }
+
+    let file = DictBuilder::mdx()
+        .version("1.2.1")
+        .words(&["apple"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert_eq!(mdx.num_format(), NumFmt::Uint32);
+}
+
+#[test]
+fn test_aliases() {
+    use rust_mdict::MatchKind;
+    use std::collections::HashMap;
+
+    let file = DictBuilder::mdx().words(&["go", "run"]).write();
+    let aliases = HashMap::from([
+        ("ran".to_string(), "run".to_string()),
+        ("went".to_string(), "go".to_string()),
+        ("flew".to_string(), "fly".to_string()),
+    ]);
+    let mut mdx = MdxBuilder::new()
+        .aliases(aliases)
+        .open(file.path())
+        .expect("Failed to load MDX");
+
+    let result = mdx.lookup("ran").unwrap();
+    assert_eq!(result.key_text, "run");
+    assert_eq!(result.definition, "<p>run</p>\r\n\0");
+    assert_eq!(&*mdx.cached_lookup("went").unwrap(), "<p>go</p>\r\n\0");
+    // An alias to a missing headword finds nothing
+    assert!(mdx.lookup("flew").is_none());
+
+    let (result, kind) = mdx.lookup_with_fallback_strip("went").unwrap();
+    assert_eq!((result.key_text.as_str(), kind), ("go", MatchKind::Alias));
+    let (_, kind) = mdx.lookup_with_fallback_strip("go").unwrap();
+    assert_eq!(kind, MatchKind::Exact);
+}
+
+#[test]
+fn test_query_cache_aliases() {
+    use std::collections::HashMap;
+
+    let file = DictBuilder::mdx().words(&["color", "run"]).write();
+    let aliases = HashMap::from([("colour".to_string(), "color".to_string())]);
+    let lookups = |capacity: usize| {
+        let mut mdx = MdxBuilder::new()
+            .aliases(aliases.clone())
+            .query_cache(capacity)
+            .open(file.path())
+            .expect("Failed to load MDX");
+        ["colour", "Colour", "co-lour", "Color", "colour", "co-lor"]
+            .map(|word| mdx.lookup(word).map(|result| result.key_text))
+    };
+
+    let uncached = lookups(0);
+    assert_eq!(uncached[0].as_deref(), Some("color"));
+    assert_eq!(uncached[1], None);
+    assert_eq!(uncached[2], None);
+    assert_eq!(lookups(16), uncached);
+}
+
+#[test]
+fn test_shared_aliases() {
+    use std::collections::HashMap;
+
+    let file = DictBuilder::mdx().words(&["go", "run"]).write();
+    let aliases = HashMap::from([("ran".to_string(), "run".to_string())]);
+    let builder = MdxBuilder::new().aliases(aliases);
+    let shared = builder
+        .clone()
+        .open(file.path())
+        .expect("Failed to load MDX")
+        .into_shared();
+    assert_eq!(shared.lookup("ran").unwrap().key_text, "run");
+    assert!(shared.lookup("rán").is_none());
+
+    // Usable wherever the shared accessors are
+    let accessors: &dyn MdictFile = &shared;
+    assert_eq!(accessors.keys(), ["go", "run"]);
+    assert!(accessors.contains("go") && !accessors.contains("ran"));
+
+    // The timed path resolves aliases the same way
+    #[cfg(feature = "metrics")]
+    {
+        let shared = builder
+            .on_lookup(|_| {})
+            .open(file.path())
+            .expect("Failed to load MDX")
+            .into_shared();
+        assert_eq!(shared.lookup("ran").unwrap().key_text, "run");
+    }
+}
+
+#[test]
+fn test_record_block_crcs() {
+    let old = DictBuilder::mdx()
+        .words(&["alpha", "beta", "gamma", "delta"])
+        .write();
+    let new = DictBuilder::mdx()
+        .entry("alpha", "<p>alpha</p>\r\n\0")
+        .entry("beta", "<p>beta</p>\r\n\0")
+        .entry("delta", "<p>changed</p>\r\n\0")
+        .entry("gamma", "<p>gamma</p>\r\n\0")
+        .write();
+
+    let old = Mdx::new(old.path()).unwrap().record_block_crcs().unwrap();
+    let new = Mdx::new(new.path()).unwrap().record_block_crcs().unwrap();
+    assert_eq!(old.len(), 2);
+    assert_eq!(old[0], new[0]);
+    assert_ne!(old[1], new[1]);
+}
+
+#[test]
+fn test_key_with_embedded_nul() {
+    let builder = DictBuilder::mdx()
+        .keys_per_block(3)
+        .entry("a\0b", "<p>ab</p>\r\n\0")
+        .entry("c", "<p>c</p>\r\n\0")
+        .entry("d", "<p>d</p>\r\n\0");
+    let offsets = builder.record_offsets();
+    let entries: Vec<_> = ["a\0b", "c", "d"]
+        .into_iter()
+        .map(String::from)
+        .zip(offsets)
+        .collect();
+    let data = builder.key_block_data(&entries);
+    let bytes = builder.build_with_key_blocks(&[(data, 3, "a\0b".to_string(), "d".to_string())]);
+    let file = common::write_temp(&bytes, ".mdx");
+
+    // "b" and the bytes after it don't make a valid record offset, so the
+    // first NUL belongs to the key
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    let keys: Vec<_> = mdx
+        .keyword_list()
+        .iter()
+        .map(|k| k.key_text.as_str())
+        .collect();
+    assert_eq!(keys, ["a\0b", "c", "d"]);
+    assert_eq!(mdx.key_decode_warnings(), 0);
+    assert_eq!(
+        mdx.key_count_mismatches(),
+        [KeyCountMismatch {
+            key_block_idx: 0,
+            declared: 3,
+            scanned: 4,
+        }]
+    );
+    assert_eq!(mdx.lookup("c").unwrap().definition, "<p>c</p>\r\n\0");
+    assert_eq!(mdx.lookup("d").unwrap().definition, "<p>d</p>\r\n\0");
+}
+
+#[test]
+fn test_key_count_matches() {
+    let file = DictBuilder::mdx().words(&["apple", "banana"]).write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(mdx.key_count_mismatches().is_empty());
+}
+
+#[test]
+fn test_page() {
+    let words = ["alpha", "beta", "delta", "epsilon", "gamma"];
+    let file = DictBuilder::mdx().words(&words).write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let page = mdx.page(1, 3);
+    let keys: Vec<_> = page.iter().map(|r| r.key_text.as_str()).collect();
+    assert_eq!(keys, ["beta", "delta", "epsilon"]);
+    assert_eq!(page[2].definition, "<p>epsilon</p>\r\n\0");
+
+    assert_eq!(mdx.page(4, 10).len(), 1);
+    assert!(mdx.page(5, 1).is_empty());
+    assert_eq!(mdx.page(0, usize::MAX).len(), words.len());
+}
+
+#[test]
+fn test_header_accessors() {
+    let file = DictBuilder::mdx()
+        .attr("KeyCaseSensitive", "yes")
+        .attr("Left2Right", "Maybe")
+        .words(&["Apple", "apple"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(mdx.header_str("KeyCaseSensitive"), Some("yes"));
+    assert!(mdx.header_str("Missing").is_none());
+    assert!(mdx.header_bool("KeyCaseSensitive", false));
+    assert!(mdx.header_bool("Left2Right", true));
+    assert!(!mdx.header_bool("Missing", false));
+
+    // A lowercase "yes" makes keys case-sensitive too
+    assert!(mdx.meta().key_case_sensitive);
+    assert_eq!(mdx.lookup_keyword("Apple").unwrap().key_text, "Apple");
+    assert!(mdx.lookup_keyword("APPLE").is_none());
+}
+
+#[test]
+fn test_lookup_standalone() {
+    let html = concat!(
+        r#"<link rel="stylesheet" href="dict.css"><script src="dict.js"></script>"#,
+        r#"<p>apple</p><a href="sound://apple.mp3">play</a>"#,
+    );
+    let mdx_file = DictBuilder::mdx().entry("apple", html).write();
+    let mdd_file = DictBuilder::mdd()
+        .entry("\\dict.css", b"p { margin: 0 }")
+        .write();
+    let mut mdx = Mdx::new(mdx_file.path()).expect("Failed to load MDX");
+    let mut mdd = Mdd::new(mdd_file.path()).expect("Failed to load MDD");
+
+    let standalone = mdx.lookup_standalone("apple", Some(&mut mdd)).unwrap();
+    assert_eq!(
+        standalone,
+        r#"<style>p { margin: 0 }</style><p>apple</p><a href="sound://apple.mp3">play</a>"#
+    );
+    let without_mdd = mdx.lookup_standalone("apple", None).unwrap();
+    assert!(without_mdd.starts_with("<p>apple</p>"));
+
+    assert_eq!(
+        rust_mdict::rewrite_internal_links(&standalone, "/res/"),
+        r#"<style>p { margin: 0 }</style><p>apple</p><a href="/res/apple.mp3">play</a>"#
+    );
+}
+
+#[test]
+fn test_keys_from() {
+    let file = DictBuilder::mdx()
+        .words(&["alpha", "beta", "delta", "gamma"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    assert_eq!(
+        mdx.keys_from("beta").collect::<Vec<_>>(),
+        ["beta", "delta", "gamma"]
+    );
+    // A checkpoint that isn't a key resumes at the next one
+    assert_eq!(
+        mdx.keys_from("Charlie").collect::<Vec<_>>(),
+        ["delta", "gamma"]
+    );
+    assert_eq!(mdx.keys_from("delta").nth(1), Some("gamma"));
+    assert_eq!(mdx.keys_from("zeta").count(), 0);
+    assert_eq!(mdx.keys_from("").count(), 4);
+}
+
+#[test]
+fn test_lookup_debug() {
+    use rust_mdict::CompressionType;
+
+    let file = DictBuilder::mdx()
+        .words(&["alpha", "beta", "gamma"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    // "gamma" opens the second two-record block
+    let debug = mdx.lookup_debug("gamma").unwrap();
+    assert_eq!(debug.item.key_text, "gamma");
+    assert_eq!(debug.block_index, 1);
+    assert_eq!((debug.start, debug.end), (0, 15));
+    assert_eq!(debug.compression, Some(CompressionType::Zlib));
+    assert_eq!(debug.raw, b"<p>gamma</p>\r\n\0");
+
+    let debug = mdx.lookup_debug("beta").unwrap();
+    assert_eq!((debug.block_index, debug.start, debug.end), (0, 15, 29));
+    assert!(mdx.lookup_debug("delta").is_none());
+
+    let file = DictBuilder::mdx().uncompressed().words(&["alpha"]).write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    let debug = mdx.lookup_debug("alpha").unwrap();
+    assert_eq!(debug.compression, Some(CompressionType::None));
+}
+
+#[test]
+fn test_fetch_out_of_block_item() {
+    use rust_mdict::MdictFile;
+
+    let file = DictBuilder::mdx()
+        .words(&["alpha", "beta", "gamma"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    // "gamma" opens the second block; an end offset before that block's
+    // start yields an empty record rather than underflowing
+    let mut item = mdx.lookup_keyword("gamma").unwrap().clone();
+    item.record_end_offset = 1;
+    assert_eq!(mdx.fetch_definition_only(&item).as_deref(), Some(""));
+
+    // "alpha" lies before the second block entirely
+    let item = mdx.lookup_keyword("alpha").unwrap().clone();
+    assert!(mdx.base().slice_record(&[0; 16], 1, &item).is_empty());
+}
+
+#[test]
+fn test_iter_category() {
+    use rust_mdict::MediaCategory;
+
+    let file = DictBuilder::mdd()
+        .entry("\\a.mp3", b"mp3-a")
+        .entry("\\b.png", b"png")
+        .entry("\\c.MP3", b"mp3-c")
+        .entry("\\style.css", b"css")
+        .write();
+    let mut mdd = Mdd::new(file.path()).expect("Failed to load MDD");
+
+    let audio: Vec<(String, Vec<u8>)> = mdd
+        .iter_category(MediaCategory::Audio)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        audio,
+        [
+            ("\\a.mp3".to_string(), b"mp3-a".to_vec()),
+            ("\\c.MP3".to_string(), b"mp3-c".to_vec()),
+        ]
+    );
+    assert_eq!(mdd.iter_category(MediaCategory::StyleSheet).count(), 1);
+    assert_eq!(mdd.iter_category(MediaCategory::Video).count(), 0);
+}
+
+#[test]
+fn test_query_cache() {
+    let builder = DictBuilder::mdx().words(&["hello", "world"]);
+    let mut bytes = builder.build();
+    let file = common::write_temp(&bytes, ".mdx");
+    let mut mdx = MdxBuilder::new()
+        .query_cache(8)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert_eq!(
+        mdx.lookup("hello").unwrap().definition,
+        "<p>hello</p>\r\n\0"
+    );
+
+    // Corrupt the record data on disk: cached queries, including other
+    // spellings of the same stripped query, don't read it again
+    let records = builder.record_section().len();
+    let record_data = bytes.len() - records..bytes.len();
+    bytes[record_data].fill(0xff);
+    std::fs::write(file.path(), &bytes).unwrap();
+    assert_eq!(
+        mdx.lookup("Hello").unwrap().definition,
+        "<p>hello</p>\r\n\0"
+    );
+    assert!(mdx.lookup("world").is_none());
+
+    mdx.clear_query_cache();
+    assert!(mdx.lookup("hello").is_none());
+}
+
+#[test]
+fn test_read_header_only() {
+    let file = DictBuilder::mdx()
+        .attr("Title", " Tiny ")
+        .words(&["alpha", "beta", "gamma"])
+        .write();
+    let info = rust_mdict::read_header_only(file.path()).expect("Failed to read header");
+    assert_eq!(info.title.as_deref(), Some("Tiny"));
+    assert_eq!(info.version, 2.0);
+    assert_eq!(info.encoding, Encoding::Utf8);
+    assert_eq!(info.encrypt, rust_mdict::EncryptType::None);
+    assert_eq!(info.keyword_count, Some(3));
+
+    let file = DictBuilder::mdx()
+        .version("1.2")
+        .words(&["apple", "banana"])
+        .write();
+    let info = rust_mdict::read_header_only(file.path()).expect("Failed to read v1 header");
+    assert_eq!((info.title, info.keyword_count), (None, Some(2)));
+
+    // The key header of a registered dictionary stays encrypted
+    let file = DictBuilder::mdx()
+        .words(&["apple", "banana", "cherry"])
+        .encrypt_key_header(REGISTERED_KEY)
+        .write();
+    let info = rust_mdict::read_header_only(file.path()).expect("Failed to read header");
+    assert_eq!(info.encrypt, rust_mdict::EncryptType::RecordBlock);
+    assert_eq!(info.keyword_count, None);
+}
+
+#[test]
+fn test_fetch_definition_only() {
+    let file = DictBuilder::mdx()
+        .words(&["alpha", "beta", "gamma"])
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let items = mdx.keyword_list().to_vec();
+    for item in &items {
+        let definition = mdx.fetch_definition_only(item).expect("Missing definition");
+        assert_eq!(definition, mdx.fetch(item).unwrap().definition);
+        assert_eq!(definition, format!("<p>{}</p>\r\n\0", item.key_text));
+    }
+}
+
+#[test]
+fn test_record_info_size_mismatch() {
+    let builder = DictBuilder::mdx().words(&["apple", "banana", "cherry"]);
+    let mut bytes = builder.build();
+    let record_at = bytes.len() - builder.record_section().len();
+
+    // Move 8 bytes from the record blocks into the record info
+    let field = |bytes: &[u8], at: usize| u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+    let info_size = field(&bytes, record_at + 16) + 8;
+    let blocks_size = field(&bytes, record_at + 24) - 8;
+    bytes[record_at + 16..record_at + 24].copy_from_slice(&info_size.to_be_bytes());
+    bytes[record_at + 24..record_at + 32].copy_from_slice(&blocks_size.to_be_bytes());
+
+    let file = common::write_temp(&bytes, ".mdx");
+    match Mdx::new(file.path()) {
+        Err(MdictError::InvalidFormat(message)) => {
+            assert!(message.contains("8 bytes left over"), "{}", message)
+        }
+        other => panic!("expected InvalidFormat, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_set_encoding() {
+    let file = gbk_dict("UTF-8").write();
+    let mut mdx = MdxBuilder::new()
+        .query_cache(4)
+        .open(file.path())
+        .expect("Failed to load MDX");
+    assert!(mdx.lookup("人民").is_none());
+
+    mdx.set_encoding(Encoding::Gb18030)
+        .expect("Failed to switch encoding");
+    assert_eq!(mdx.meta().encoding, Encoding::Gb18030);
+    assert_eq!(mdx.keyword_count(), 3);
+    assert_eq!(
+        mdx.lookup("人民").unwrap().definition,
+        "<p>人民是国家的主人，也是历史的创造者。</p>\0"
+    );
+    assert!(mdx.contains("学习"));
+}
+
+#[test]
+fn test_lookup_or_nearest() {
+    let file = DictBuilder::mdx()
+        .words(&["apple", "banana", "cherry"])
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    let (exact, nearest) = mdx.lookup_or_nearest("Banana").unwrap();
+    assert_eq!(exact.map(|item| item.key_text.as_str()), Some("banana"));
+    assert_eq!(nearest.key_text, "banana");
+
+    let (exact, nearest) = mdx.lookup_or_nearest("ban").unwrap();
+    assert!(exact.is_none());
+    assert_eq!(nearest.key_text, "banana");
+
+    // Past the end, the last entry is the nearest
+    let (exact, nearest) = mdx.lookup_or_nearest("zebra").unwrap();
+    assert!(exact.is_none());
+    assert_eq!(nearest.key_text, "cherry");
+
+    let file = DictBuilder::mdx().write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load empty MDX");
+    assert!(mdx.lookup_or_nearest("apple").is_none());
+}
+
+#[test]
+fn test_record_bytes() {
+    let builder = DictBuilder::mdx()
+        .words(&["alpha", "beta", "gamma"])
+        .records_per_block(2);
+    let file = builder.write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    // Record section: 32-byte header and 16 bytes of info per block
+    let packed = builder.record_section().len() as u64 - 32 - 2 * 16;
+    assert_eq!(mdx.record_bytes(), (packed, 15 + 14 + 15));
+
+    // Uncompressed blocks only add an 8-byte preamble
+    let file = builder.uncompressed().write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    let (packed, unpacked) = mdx.key_block_bytes();
+    assert!(unpacked > 0);
+    assert_eq!(packed, unpacked + 8 * mdx.key_info_list().len() as u64);
+    let (packed, unpacked) = mdx.record_bytes();
+    assert_eq!(packed, unpacked + 8 * 2);
+}
+
+#[test]
+fn test_wrong_file_type() {
+    let mdd = DictBuilder::mdd().entry("\\a.png", b"png").build();
+    let file = common::write_temp(&mdd, ".mdx");
+    match Mdx::new(file.path()) {
+        Err(MdictError::InvalidFormat(message)) => {
+            assert_eq!(message, "Expected MDX, but the header looks like MDD")
+        }
+        other => panic!("expected InvalidFormat, got {:?}", other.map(|_| ())),
+    }
+
+    let mdx = DictBuilder::mdx().words(&["apple"]).build();
+    let file = common::write_temp(&mdx, ".mdd");
+    assert!(matches!(
+        Mdd::new(file.path()),
+        Err(MdictError::InvalidFormat(_))
+    ));
+
+    // A `<Dictionary>` root without a byte encoding: the keys aren't paths
+    let definition = |text: &str| -> Vec<u8> {
+        let mut bytes: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        bytes.extend([0, 0]);
+        bytes
+    };
+    let utf16 = DictBuilder::mdx()
+        .utf16be()
+        .entry("apple", definition("<p>apple</p>"))
+        .build();
+    let no_encoding = DictBuilder::mdx()
+        .attr("Encoding", "")
+        .words(&["apple", "banana"])
+        .build();
+    let file = common::write_temp(&utf16, ".mdd");
+    match Mdd::new(file.path()) {
+        Err(MdictError::InvalidFormat(message)) => {
+            assert_eq!(message, "Expected MDD, but the header looks like MDX")
+        }
+        other => panic!("expected InvalidFormat, got {:?}", other.map(|_| ())),
+    }
+
+    // Key block info that doesn't decode as MDD reports why, as a damaged
+    // MDD would
+    let file = common::write_temp(&no_encoding, ".mdd");
+    match Mdd::new(file.path()) {
+        Err(MdictError::InvalidFormat(message)) => {
+            assert!(message.starts_with("Key block info"), "{}", message)
+        }
+        other => panic!("expected InvalidFormat, got {:?}", other.map(|_| ())),
+    }
+
+    // Without a known extension the header decides
+    let file = common::write_temp(&mdd, ".bin");
+    let info = rust_mdict::read_header_only(file.path()).expect("Failed to read header");
+    assert_eq!(info.keyword_count, Some(1));
+}
+
+#[test]
+fn test_suggest_ranking() {
+    let file = DictBuilder::mdx()
+        .words(&["believe", "halo", "hello", "help", "receive", "relief"])
+        .keys_per_block(10)
+        .write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+
+    // `believe` is as far from `recieve` but shares no prefix
+    let suggestions = mdx.suggest("recieve", 2);
+    assert_eq!(suggestions[0], "receive");
+    assert_eq!(suggestions, ["receive", "believe"]);
+
+    // All three are one edit away: `hel` beats `h`, then `help` is the
+    // same length as `helo`
+    assert_eq!(mdx.suggest("helo", 1), ["help", "hello", "halo"]);
+}
+
+/// Store `data` as a single LZO1X literal run followed by the end marker
+fn lzo_literal_encode(data: &[u8]) -> Vec<u8> {
+    assert!(data.len() <= 238, "literal run too long");
+    let mut out = vec![data.len() as u8 + 17];
+    out.extend_from_slice(data);
+    out.extend_from_slice(&[0x11, 0, 0]);
+    out
+}
+
+#[test]
+fn test_uses_lzo() {
+    let words = ["apple", "banana", "cherry"];
+    let file = DictBuilder::mdx().words(&words).write();
+    let mdx = Mdx::new(file.path()).expect("Failed to load MDX");
+    assert!(!mdx.uses_lzo());
+
+    let file = DictBuilder::mdx()
+        .words(&words)
+        .custom_blocks(1, lzo_literal_encode)
+        .write();
+    let mut mdx = Mdx::new(file.path()).expect("Failed to load LZO MDX");
+    assert!(mdx.uses_lzo());
+    assert_eq!(
+        mdx.lookup("banana").unwrap().definition,
+        "<p>banana</p>\r\n\0"
+    );
+}